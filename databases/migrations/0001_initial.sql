@@ -0,0 +1,92 @@
+-- Baseline schema: the tables that existed before versioned migrations.
+-- Everything is IF NOT EXISTS so databases created by the old bootstrap adopt it as-is.
+
+CREATE TABLE IF NOT EXISTS logininfo (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(100) NOT NULL,
+    email VARCHAR(255) UNIQUE NOT NULL,
+    phoneNumber VARCHAR(20) UNIQUE NOT NULL,
+    password TEXT NOT NULL,
+    status VARCHAR(10) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS temp_users (
+    temp_id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    number TEXT NOT NULL,
+    gmail TEXT NOT NULL,
+    password TEXT NOT NULL,
+    code TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS laptop_details (
+    id SERIAL PRIMARY KEY,
+    brand_name TEXT NOT NULL,
+    model_name TEXT NOT NULL,
+    model_year INTEGER,
+    display_name TEXT,
+    product_type TEXT,
+    product_authentication TEXT,
+    suitable_for TEXT,
+    color TEXT,
+    processor_generation TEXT,
+    processor TEXT,
+    processor_series TEXT,
+    ram INTEGER,
+    ram_type TEXT,
+    storage INTEGER,
+    storage_type TEXT,
+    graphic TEXT,
+    graphic_ram INTEGER,
+    display TEXT,
+    display_type TEXT,
+    touchscreen BOOLEAN,
+    power_supply TEXT,
+    battery TEXT,
+    warranty TEXT,
+    cost_price NUMERIC(10, 2) NOT NULL,
+    show_price NUMERIC(10, 2) GENERATED ALWAYS AS (cost_price + cost_price * 0.18) STORED,
+    face_image_url TEXT, 
+    quantity INTEGER DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS laptop_side_images (
+    id SERIAL PRIMARY KEY,
+    laptop_id INTEGER REFERENCES laptop_details(id) ON DELETE CASCADE,
+    image_url TEXT  
+);
+
+CREATE TABLE IF NOT EXISTS messages (
+    id SERIAL PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    content TEXT NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    sender TEXT NOT NULL CHECK (sender IN ('user', 'admin', 'bot')),
+    receiver TEXT NOT NULL CHECK (receiver IN ('user', 'admin', 'bot'))
+);
+
+CREATE TABLE IF NOT EXISTS user_bot_settings (
+    user_id TEXT PRIMARY KEY,
+    bot_enabled BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+CREATE TABLE IF NOT EXISTS khalti_temp_payments (
+    id SERIAL PRIMARY KEY,
+    pidx TEXT UNIQUE NOT NULL,
+    email TEXT NOT NULL,
+    laptop_id TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS laptops_sold (
+    sale_id SERIAL PRIMARY KEY,        
+    laptop_id INTEGER NOT NULL,            
+    sold_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    quantity INTEGER NOT NULL DEFAULT 1,
+    price_at_sale NUMERIC(10, 2) NOT NULL,
+    
+    CONSTRAINT fk_laptop
+        FOREIGN KEY (laptop_id)
+        REFERENCES laptop_details(id)
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+-- Email-change codes reuse temp_users; user_id marks the account being changed.
+ALTER TABLE temp_users ADD COLUMN IF NOT EXISTS user_id INTEGER;
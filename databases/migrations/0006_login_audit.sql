@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS login_audit (
+    id SERIAL PRIMARY KEY,
+    email TEXT NOT NULL,
+    user_id INTEGER REFERENCES logininfo(id) ON DELETE SET NULL,
+    ip TEXT NOT NULL,
+    success BOOLEAN NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_login_audit_email ON login_audit (email, created_at);
+CREATE INDEX IF NOT EXISTS idx_login_audit_ip ON login_audit (ip, created_at);
@@ -0,0 +1,34 @@
+-- Durable record of every payment attempt, replacing khalti_temp_payments, which only
+-- held in-flight Khalti payments and was wiped on restart.
+CREATE TABLE IF NOT EXISTS payments (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES logininfo(id) ON DELETE RESTRICT,
+    gateway TEXT NOT NULL,
+    -- The gateway's own id for the payment (Khalti's pidx), known once it accepts it.
+    gateway_ref TEXT,
+    -- Our id for the payment, sent to the gateway as the purchase order id.
+    order_ref TEXT UNIQUE NOT NULL,
+    amount_paisa BIGINT NOT NULL CHECK (amount_paisa > 0),
+    currency TEXT NOT NULL DEFAULT 'NPR',
+    status TEXT NOT NULL CHECK (status IN
+        ('initiated', 'pending', 'completed', 'failed', 'expired', 'refunded', 'user_canceled')),
+    transaction_id TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_payments_gateway_ref ON payments (gateway, gateway_ref);
+CREATE INDEX IF NOT EXISTS idx_payments_user ON payments (user_id, created_at);
+
+CREATE TABLE IF NOT EXISTS payment_transitions (
+    id SERIAL PRIMARY KEY,
+    payment_id INTEGER NOT NULL REFERENCES payments(id) ON DELETE CASCADE,
+    from_status TEXT,
+    to_status TEXT NOT NULL,
+    reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_payment_transitions_payment ON payment_transitions (payment_id, created_at);
+
+DROP TABLE IF EXISTS khalti_temp_payments;
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS cart_items (
+    user_id INTEGER NOT NULL REFERENCES logininfo(id) ON DELETE CASCADE,
+    laptop_id INTEGER NOT NULL REFERENCES laptop_details(id) ON DELETE CASCADE,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    added_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, laptop_id)
+);
+
+-- What a payment pays for, priced when checkout started.
+CREATE TABLE IF NOT EXISTS payment_items (
+    payment_id INTEGER NOT NULL REFERENCES payments(id) ON DELETE CASCADE,
+    laptop_id INTEGER NOT NULL REFERENCES laptop_details(id) ON DELETE RESTRICT,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    unit_price NUMERIC(10, 2) NOT NULL,
+    PRIMARY KEY (payment_id, laptop_id)
+);
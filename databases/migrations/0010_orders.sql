@@ -0,0 +1,53 @@
+-- One order per completed payment, owned by the buyer.
+CREATE TABLE IF NOT EXISTS orders (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES logininfo(id) ON DELETE RESTRICT,
+    payment_id INTEGER UNIQUE NOT NULL REFERENCES payments(id) ON DELETE RESTRICT,
+    order_ref TEXT UNIQUE NOT NULL,
+    total_paisa BIGINT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'placed' CHECK (status IN
+        ('placed', 'packed', 'shipped', 'delivered', 'cancelled', 'returned')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_orders_user ON orders (user_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_orders_status ON orders (status, created_at);
+
+CREATE TABLE IF NOT EXISTS order_items (
+    order_id INTEGER NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    laptop_id INTEGER NOT NULL REFERENCES laptop_details(id) ON DELETE RESTRICT,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    unit_price NUMERIC(10, 2) NOT NULL,
+    PRIMARY KEY (order_id, laptop_id)
+);
+
+CREATE TABLE IF NOT EXISTS order_status_history (
+    id SERIAL PRIMARY KEY,
+    order_id INTEGER NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    from_status TEXT,
+    to_status TEXT NOT NULL,
+    -- The admin who made the change; NULL when the system did.
+    changed_by INTEGER REFERENCES logininfo(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_order_status_history_order ON order_status_history (order_id, created_at);
+
+ALTER TABLE laptops_sold ADD COLUMN IF NOT EXISTS order_id INTEGER REFERENCES orders(id) ON DELETE SET NULL;
+
+-- Payments completed before orders existed become delivered orders, so customers can see them.
+INSERT INTO orders (user_id, payment_id, order_ref, total_paisa, status, created_at, updated_at)
+SELECT user_id, id, order_ref, amount_paisa, 'delivered', updated_at, updated_at
+FROM payments
+WHERE status = 'completed'
+ON CONFLICT DO NOTHING;
+
+INSERT INTO order_items (order_id, laptop_id, quantity, unit_price)
+SELECT o.id, p.laptop_id, p.quantity, p.unit_price
+FROM orders o
+JOIN payment_items p ON p.payment_id = o.payment_id
+ON CONFLICT DO NOTHING;
+
+INSERT INTO order_status_history (order_id, from_status, to_status, created_at)
+SELECT id, NULL, status, created_at FROM orders;
@@ -0,0 +1,29 @@
+-- Stock is taken out of laptop_details.quantity when a payment starts and held here
+-- until the payment completes (consumed) or fails/expires (released back to stock).
+CREATE TABLE IF NOT EXISTS stock_reservations (
+    payment_id INTEGER PRIMARY KEY REFERENCES payments(id) ON DELETE CASCADE,
+    status TEXT NOT NULL DEFAULT 'held' CHECK (status IN ('held', 'consumed', 'released')),
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_stock_reservations_held ON stock_reservations (expires_at) WHERE status = 'held';
+
+-- Payments already in flight hold their stock like new ones would.
+INSERT INTO stock_reservations (payment_id, expires_at)
+SELECT id, NOW() + INTERVAL '60 minutes' FROM payments WHERE status IN ('initiated', 'pending')
+ON CONFLICT DO NOTHING;
+
+UPDATE laptop_details l
+SET quantity = l.quantity - held.quantity
+FROM (
+    SELECT i.laptop_id, SUM(i.quantity) AS quantity
+    FROM payment_items i
+    JOIN stock_reservations r ON r.payment_id = i.payment_id
+    GROUP BY i.laptop_id
+) held
+WHERE l.id = held.laptop_id;
+
+-- Stock could previously be driven below zero; it can't any more.
+UPDATE laptop_details SET quantity = 0 WHERE quantity < 0;
+ALTER TABLE laptop_details ADD CONSTRAINT laptop_details_quantity_nonnegative CHECK (quantity >= 0);
@@ -0,0 +1,14 @@
+-- Cash-on-delivery orders hold their stock until the cash is collected or the delivery
+-- fails, however long that takes, so their reservations have no expiry.
+ALTER TABLE stock_reservations ALTER COLUMN expires_at DROP NOT NULL;
+
+-- Per-customer cap on cash-on-delivery orders awaiting collection. Customers without a
+-- row get the configured default.
+CREATE TABLE IF NOT EXISTS cod_limits (
+    user_id INTEGER PRIMARY KEY REFERENCES logininfo(id) ON DELETE CASCADE,
+    limit_paisa BIGINT NOT NULL CHECK (limit_paisa >= 0),
+    updated_by INTEGER REFERENCES logininfo(id) ON DELETE SET NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_payments_user_gateway_status ON payments (user_id, gateway, status);
@@ -0,0 +1,30 @@
+-- A customer's request to send back a delivered order, decided by an admin.
+CREATE TABLE IF NOT EXISTS return_requests (
+    id SERIAL PRIMARY KEY,
+    order_id INTEGER NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES logininfo(id) ON DELETE CASCADE,
+    reason TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'requested' CHECK (status IN ('requested', 'approved', 'rejected')),
+    admin_note TEXT,
+    decided_by INTEGER REFERENCES logininfo(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    decided_at TIMESTAMPTZ
+);
+
+-- A rejected request can be followed by a new one; an open or approved one can't.
+CREATE UNIQUE INDEX IF NOT EXISTS idx_return_requests_open
+    ON return_requests (order_id) WHERE status IN ('requested', 'approved');
+CREATE INDEX IF NOT EXISTS idx_return_requests_status ON return_requests (status, created_at);
+
+-- Money given back for a payment, either through its gateway or by hand (bank transfer,
+-- cash) with the admin's reference. Payments are refunded in full, once.
+CREATE TABLE IF NOT EXISTS refunds (
+    id SERIAL PRIMARY KEY,
+    payment_id INTEGER UNIQUE NOT NULL REFERENCES payments(id) ON DELETE RESTRICT,
+    return_id INTEGER REFERENCES return_requests(id) ON DELETE SET NULL,
+    amount_paisa BIGINT NOT NULL CHECK (amount_paisa > 0),
+    method TEXT NOT NULL CHECK (method IN ('gateway', 'manual')),
+    reference TEXT,
+    created_by INTEGER REFERENCES logininfo(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,25 @@
+-- One pass of the reconciliation job over payments still waiting on their gateway.
+CREATE TABLE IF NOT EXISTS reconciliation_runs (
+    id SERIAL PRIMARY KEY,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ,
+    checked INTEGER NOT NULL DEFAULT 0,
+    settled INTEGER NOT NULL DEFAULT 0,
+    -- Payments that need an admin: amount mismatches, unknown statuses, failed lookups.
+    unresolved INTEGER NOT NULL DEFAULT 0,
+    -- The admin who started the run; NULL for the scheduled job.
+    triggered_by INTEGER REFERENCES logininfo(id) ON DELETE SET NULL
+);
+
+CREATE TABLE IF NOT EXISTS reconciliation_items (
+    run_id INTEGER NOT NULL REFERENCES reconciliation_runs(id) ON DELETE CASCADE,
+    payment_id INTEGER NOT NULL REFERENCES payments(id) ON DELETE CASCADE,
+    previous_status TEXT NOT NULL,
+    outcome TEXT NOT NULL CHECK (outcome IN
+        ('settled', 'already_settled', 'pending', 'needs_review', 'amount_mismatch', 'lookup_failed')),
+    new_status TEXT,
+    detail TEXT,
+    PRIMARY KEY (run_id, payment_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_payments_unsettled ON payments (created_at) WHERE status IN ('initiated', 'pending');
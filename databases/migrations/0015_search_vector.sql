@@ -0,0 +1,28 @@
+-- Full-text search document for a laptop, kept up to date by Postgres on every write.
+-- Weights rank a match on the name (A) above specs (B, C) and fine print (D).
+ALTER TABLE laptop_details ADD COLUMN IF NOT EXISTS search_vector tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('english',
+        coalesce(brand_name, '') || ' ' ||
+        coalesce(model_name, '') || ' ' ||
+        coalesce(display_name, '')), 'A') ||
+    setweight(to_tsvector('english',
+        coalesce(processor, '') || ' ' ||
+        coalesce(processor_series, '') || ' ' ||
+        coalesce(processor_generation, '') || ' ' ||
+        coalesce(graphic, '') || ' ' ||
+        coalesce(product_type, '') || ' ' ||
+        coalesce(suitable_for, '')), 'B') ||
+    setweight(to_tsvector('english',
+        coalesce(ram_type, '') || ' ' ||
+        coalesce(storage_type, '') || ' ' ||
+        coalesce(display, '') || ' ' ||
+        coalesce(display_type, '') || ' ' ||
+        coalesce(color, '') || ' ' ||
+        coalesce(product_authentication, '') || ' ' ||
+        coalesce(battery, '')), 'C') ||
+    setweight(to_tsvector('english',
+        coalesce(warranty, '') || ' ' ||
+        coalesce(power_supply, '')), 'D')
+) STORED;
+
+CREATE INDEX IF NOT EXISTS laptop_details_search_vector_idx ON laptop_details USING GIN (search_vector);
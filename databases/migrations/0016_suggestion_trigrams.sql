@@ -0,0 +1,25 @@
+-- Trigram matching for search-as-you-type, so unfinished or misspelt words still find
+-- a laptop. pg_trgm is a trusted extension, so the database owner can create it.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- The names customers type, once as written and once with the spaces taken out so that
+-- "rtx305" still lands near "RTX 3050".
+ALTER TABLE laptop_details ADD COLUMN IF NOT EXISTS suggestion_text TEXT GENERATED ALWAYS AS (
+    lower(
+        coalesce(brand_name, '') || ' ' ||
+        coalesce(model_name, '') || ' ' ||
+        coalesce(display_name, '') || ' ' ||
+        coalesce(processor, '') || ' ' ||
+        coalesce(graphic, '')
+    ) || ' ' ||
+    replace(lower(
+        coalesce(brand_name, '') ||
+        coalesce(model_name, '') ||
+        coalesce(display_name, '') ||
+        coalesce(processor, '') ||
+        coalesce(graphic, '')
+    ), ' ', '')
+) STORED;
+
+CREATE INDEX IF NOT EXISTS laptop_details_suggestion_trgm_idx
+    ON laptop_details USING GIN (suggestion_text gin_trgm_ops);
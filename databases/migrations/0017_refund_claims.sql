@@ -0,0 +1,15 @@
+-- A refund row is written before the gateway is asked for the money, as a claim on the
+-- payment: 'sending' until the refund is recorded, then 'sent'. payment_id is unique, so
+-- two admins can't both send one. Existing rows were all recorded after the fact.
+ALTER TABLE refunds ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'sent'
+    CHECK (status IN ('sending', 'sent'));
+
+-- A return being approved is 'refunding' while its refund goes out, so it can't be
+-- rejected or approved a second time in the meantime.
+ALTER TABLE return_requests DROP CONSTRAINT IF EXISTS return_requests_status_check;
+ALTER TABLE return_requests ADD CONSTRAINT return_requests_status_check
+    CHECK (status IN ('requested', 'refunding', 'approved', 'rejected'));
+
+DROP INDEX IF EXISTS idx_return_requests_open;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_return_requests_open
+    ON return_requests (order_id) WHERE status IN ('requested', 'refunding', 'approved');
@@ -0,0 +1,49 @@
+//! A stand-in for Khalti's ePayment API, for developing and testing checkout offline.
+//!
+//! Run it with `cargo run --bin mock_khalti` and point the backend at it:
+//!
+//! ```text
+//! KHALTI_URL=http://127.0.0.1:9090/api/v2
+//! KHALTI_REFUND_URL=http://127.0.0.1:9090/api/merchant-transaction
+//! ```
+//!
+//! Each payment's outcome is scripted in one of three ways, latest wins:
+//! - `MOCK_KHALTI_OUTCOME` sets the outcome of every new payment (default `completed`);
+//! - `POST /mock/outcome {"outcome": ...}` changes that default while running;
+//! - `POST /mock/payments/{pidx} {"outcome": ...}` sets one payment's outcome.
+//!
+//! Outcomes are `completed`, `pending`, `user_canceled`, `expired` and `amount_mismatch`
+//! (completed, but for less than was asked). A payment's outcome only shows up in lookups
+//! once the customer has been through `payment_url`, which redirects to the shop's
+//! `return_url` the way Khalti does; until then it looks up as `Initiated`.
+
+mod mock;
+
+use actix_web::{web, App, HttpServer};
+use mock::{MockState, Outcome};
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let port: u16 = std::env::var("MOCK_KHALTI_PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(9090);
+    let default_outcome = match std::env::var("MOCK_KHALTI_OUTCOME") {
+        Ok(outcome) => match outcome.parse() {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                eprintln!("❌ MOCK_KHALTI_OUTCOME: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Err(_) => Outcome::Completed,
+    };
+
+    let state = web::Data::new(MockState::new(format!("http://127.0.0.1:{}", port), default_outcome));
+
+    println!("Mock Khalti running on http://127.0.0.1:{}", port);
+
+    HttpServer::new(move || {
+        App::new().app_data(state.clone()).configure(mock::configure)
+    })
+    .bind(("127.0.0.1", port))?
+    .run()
+    .await
+}
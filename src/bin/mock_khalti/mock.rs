@@ -0,0 +1,287 @@
+//! The mock's routes and state, shared by the `mock_khalti` binary and the backend's
+//! checkout tests.
+
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Completed,
+    Pending,
+    UserCanceled,
+    Expired,
+    AmountMismatch,
+}
+
+impl std::str::FromStr for Outcome {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| format!("unknown outcome '{}'", s))
+    }
+}
+
+impl Outcome {
+    /// The status Khalti's lookup reports once the customer is done.
+    fn lookup_status(&self) -> &'static str {
+        match self {
+            Outcome::Completed | Outcome::AmountMismatch => "Completed",
+            Outcome::Pending => "Pending",
+            Outcome::UserCanceled => "User canceled",
+            Outcome::Expired => "Expired",
+        }
+    }
+}
+
+struct MockPayment {
+    amount: i64,
+    purchase_order_id: String,
+    return_url: String,
+    outcome: Outcome,
+    /// Set once the customer has been through the payment page.
+    visited: bool,
+    refunded: bool,
+}
+
+pub struct MockState {
+    base_url: String,
+    default_outcome: Mutex<Outcome>,
+    payments: Mutex<HashMap<String, MockPayment>>,
+}
+
+impl MockState {
+    /// `base_url` is where the mock itself is reachable, for the payment links it hands out.
+    pub fn new(base_url: String, default_outcome: Outcome) -> Self {
+        MockState {
+            base_url,
+            default_outcome: Mutex::new(default_outcome),
+            payments: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct InitiateRequest {
+    return_url: String,
+    amount: i64,
+    purchase_order_id: String,
+}
+
+#[derive(Deserialize)]
+struct LookupRequest {
+    pidx: String,
+}
+
+#[derive(Deserialize)]
+struct OutcomeRequest {
+    outcome: Outcome,
+}
+
+#[derive(Deserialize)]
+struct PayQuery {
+    outcome: Option<Outcome>,
+}
+
+fn transaction_id(pidx: &str) -> String {
+    format!("MOCK-{}", pidx.get(..12).unwrap_or(pidx))
+}
+
+fn has_key(req: &actix_web::HttpRequest) -> bool {
+    req.headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("Key ") && v.len() > 4)
+}
+
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized().json(serde_json::json!({
+        "detail": "Invalid token.",
+        "status_code": 401
+    }))
+}
+
+#[post("/api/v2/epayment/initiate/")]
+async fn initiate(
+    req: actix_web::HttpRequest,
+    body: web::Json<InitiateRequest>,
+    state: web::Data<MockState>,
+) -> impl Responder {
+    if !has_key(&req) {
+        return unauthorized();
+    }
+    if body.amount < 1000 {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "amount": ["Amount should be greater than Rs. 10, that is 1000 paisa."],
+            "error_key": "validation_error"
+        }));
+    }
+
+    let pidx = uuid::Uuid::new_v4().simple().to_string();
+    let outcome = *state.default_outcome.lock().unwrap();
+    state.payments.lock().unwrap().insert(
+        pidx.clone(),
+        MockPayment {
+            amount: body.amount,
+            purchase_order_id: body.purchase_order_id.clone(),
+            return_url: body.return_url.clone(),
+            outcome,
+            visited: false,
+            refunded: false,
+        },
+    );
+    println!("Initiated {} for {} paisa ({})", pidx, body.amount, body.purchase_order_id);
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "pidx": pidx,
+        "payment_url": format!("{}/pay/{}", state.base_url, pidx),
+        "expires_at": (chrono::Utc::now() + chrono::Duration::minutes(60)).to_rfc3339(),
+        "expires_in": 3600
+    }))
+}
+
+#[post("/api/v2/epayment/lookup/")]
+async fn lookup(
+    req: actix_web::HttpRequest,
+    body: web::Json<LookupRequest>,
+    state: web::Data<MockState>,
+) -> impl Responder {
+    if !has_key(&req) {
+        return unauthorized();
+    }
+
+    let payments = state.payments.lock().unwrap();
+    let Some(payment) = payments.get(&body.pidx) else {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "detail": "Not found.",
+            "error_key": "validation_error"
+        }));
+    };
+
+    let (status, total_amount, transaction_id) = if payment.refunded {
+        ("Refunded", payment.amount, Some(transaction_id(&body.pidx)))
+    } else if !payment.visited {
+        ("Initiated", payment.amount, None)
+    } else {
+        let total_amount = match payment.outcome {
+            Outcome::AmountMismatch => payment.amount - 1000,
+            _ => payment.amount,
+        };
+        let transaction_id = matches!(payment.outcome, Outcome::Completed | Outcome::AmountMismatch)
+            .then(|| transaction_id(&body.pidx));
+        (payment.outcome.lookup_status(), total_amount, transaction_id)
+    };
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "pidx": body.pidx,
+        "total_amount": total_amount,
+        "status": status,
+        "transaction_id": transaction_id,
+        "fee": 0,
+        "refunded": payment.refunded
+    }))
+}
+
+#[post("/api/merchant-transaction/{transaction_id}/refund/")]
+async fn refund(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    state: web::Data<MockState>,
+) -> impl Responder {
+    if !has_key(&req) {
+        return unauthorized();
+    }
+
+    let transaction = path.into_inner();
+    let mut payments = state.payments.lock().unwrap();
+    let payment = payments.iter_mut().find(|(pidx, p)| {
+        transaction_id(pidx) == transaction
+            && p.visited
+            && matches!(p.outcome, Outcome::Completed | Outcome::AmountMismatch)
+    });
+
+    match payment {
+        Some((_, payment)) if !payment.refunded => {
+            payment.refunded = true;
+            HttpResponse::Ok().json(serde_json::json!({ "detail": "Transaction refund successful." }))
+        }
+        Some(_) => HttpResponse::BadRequest().json(serde_json::json!({
+            "detail": "Transaction already refunded."
+        })),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "detail": "Transaction not found." })),
+    }
+}
+
+/// The customer's side: settles the payment with its scripted outcome, or the one given
+/// as `?outcome=`, and redirects back to the shop with Khalti's callback parameters.
+#[get("/pay/{pidx}")]
+async fn pay(
+    path: web::Path<String>,
+    query: web::Query<PayQuery>,
+    state: web::Data<MockState>,
+) -> impl Responder {
+    let pidx = path.into_inner();
+    let mut payments = state.payments.lock().unwrap();
+    let Some(payment) = payments.get_mut(&pidx) else {
+        return HttpResponse::NotFound().body("Unknown payment");
+    };
+
+    let outcome = query.outcome.unwrap_or(payment.outcome);
+    payment.outcome = outcome;
+    payment.visited = true;
+
+    let completed = matches!(outcome, Outcome::Completed | Outcome::AmountMismatch);
+    let separator = if payment.return_url.contains('?') { '&' } else { '?' };
+    let location = format!(
+        "{}{}pidx={}&status={}&purchase_order_id={}&amount={}&transaction_id={}",
+        payment.return_url,
+        separator,
+        pidx,
+        urlencode(outcome.lookup_status()),
+        urlencode(&payment.purchase_order_id),
+        payment.amount,
+        if completed { transaction_id(&pidx) } else { String::new() },
+    );
+
+    HttpResponse::Found().append_header(("Location", location)).finish()
+}
+
+#[post("/mock/outcome")]
+async fn set_default_outcome(body: web::Json<OutcomeRequest>, state: web::Data<MockState>) -> impl Responder {
+    *state.default_outcome.lock().unwrap() = body.outcome;
+    HttpResponse::NoContent().finish()
+}
+
+/// Scripts one payment, as if the customer had just been through the payment page.
+#[post("/mock/payments/{pidx}")]
+async fn set_payment_outcome(
+    path: web::Path<String>,
+    body: web::Json<OutcomeRequest>,
+    state: web::Data<MockState>,
+) -> impl Responder {
+    match state.payments.lock().unwrap().get_mut(&path.into_inner()) {
+        Some(payment) => {
+            payment.outcome = body.outcome;
+            payment.visited = true;
+            HttpResponse::NoContent().finish()
+        }
+        None => HttpResponse::NotFound().body("Unknown payment"),
+    }
+}
+
+fn urlencode(s: &str) -> String {
+    urlencoding::encode(s).into_owned()
+}
+
+/// Every route of the mock; the app also needs a `web::Data<MockState>`.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(initiate);
+    cfg.service(lookup);
+    cfg.service(refund);
+    cfg.service(pay);
+    cfg.service(set_default_outcome);
+    cfg.service(set_payment_outcome);
+}
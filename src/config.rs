@@ -0,0 +1,430 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Settings read once at startup from `config.toml` (or the file named by `EPASAL_CONFIG`),
+/// with environment variables taking precedence. Shared with handlers as `web::Data<Config>`.
+#[derive(Clone)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    pub cors_origin: String,
+    pub database_url: String,
+    /// Frontend origin, used for links in emails, bot replies and payment redirects.
+    pub base_url: String,
+    /// Public URL of this backend, handed to payment gateways for callbacks.
+    pub backend_url: String,
+    /// Set when the backend sits behind a reverse proxy that writes the client's address
+    /// into `Forwarded` or `X-Forwarded-For`. Only then are those headers believed, e.g.
+    /// when counting failed logins per address; otherwise anyone could send them.
+    pub trusted_proxy: bool,
+    pub smtp: SmtpConfig,
+    pub admin: AdminConfig,
+    pub khalti: KhaltiConfig,
+    /// Only present when a product code and secret key are configured.
+    pub esewa: Option<EsewaConfig>,
+    pub cod: CodConfig,
+    pub llm: LlmConfig,
+}
+
+#[derive(Clone)]
+pub struct SmtpConfig {
+    pub email: String,
+    pub password: String,
+    pub server: String,
+    pub port: u16,
+}
+
+#[derive(Clone)]
+pub struct AdminConfig {
+    pub email: String,
+    pub phone: String,
+    pub require_2fa: bool,
+}
+
+#[derive(Clone)]
+pub struct KhaltiConfig {
+    pub secret_key: String,
+    /// ePayment API base, e.g. `https://a.khalti.com/api/v2`; point it at `mock_khalti` to
+    /// develop offline.
+    pub url: String,
+    /// Merchant transaction API base, which serves refunds.
+    pub refund_url: String,
+}
+
+#[derive(Clone)]
+pub struct EsewaConfig {
+    pub product_code: String,
+    pub secret_key: String,
+    pub form_url: String,
+    pub status_url: String,
+}
+
+#[derive(Clone)]
+pub struct CodConfig {
+    /// Most a customer may owe on uncollected cash-on-delivery orders, unless an admin
+    /// has set their own limit.
+    pub default_limit_paisa: i64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LlmProviderKind {
+    Ollama,
+    /// Any endpoint speaking the OpenAI chat completions API.
+    OpenAi,
+    /// Scripted replies, no network; for tests and local development.
+    Mock,
+    /// Chat is always handed to an admin.
+    Disabled,
+}
+
+impl std::str::FromStr for LlmProviderKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, ()> {
+        match s.to_ascii_lowercase().as_str() {
+            "ollama" => Ok(LlmProviderKind::Ollama),
+            "openai" => Ok(LlmProviderKind::OpenAi),
+            "mock" => Ok(LlmProviderKind::Mock),
+            "none" | "disabled" => Ok(LlmProviderKind::Disabled),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LlmConfig {
+    pub provider: LlmProviderKind,
+    pub url: String,
+    pub model: String,
+    pub api_key: Option<String>,
+}
+
+// (environment variable, dotted key in the config file)
+const KEYS: &[(&str, &str)] = &[
+    ("HOST", "host"),
+    ("PORT", "port"),
+    ("CORS_ORIGIN", "cors_origin"),
+    ("DATABASE_URL", "database_url"),
+    ("BASE_URL", "base_url"),
+    ("BACKEND_URL", "backend_url"),
+    ("TRUSTED_PROXY", "trusted_proxy"),
+    ("SMTP_EMAIL", "smtp.email"),
+    ("SMTP_PASSWORD", "smtp.password"),
+    ("SMTP_SERVER", "smtp.server"),
+    ("SMTP_PORT", "smtp.port"),
+    ("ADMIN_EMAIL", "admin.email"),
+    ("ADMIN_PHONE", "admin.phone"),
+    ("ADMIN_REQUIRE_2FA", "admin.require_2fa"),
+    ("KHALTI_SECRET_KEY", "khalti.secret_key"),
+    ("KHALTI_URL", "khalti.url"),
+    ("KHALTI_REFUND_URL", "khalti.refund_url"),
+    ("ESEWA_PRODUCT_CODE", "esewa.product_code"),
+    ("ESEWA_SECRET_KEY", "esewa.secret_key"),
+    ("ESEWA_FORM_URL", "esewa.form_url"),
+    ("ESEWA_STATUS_URL", "esewa.status_url"),
+    ("COD_DEFAULT_LIMIT", "cod.default_limit"),
+    ("LLM_PROVIDER", "llm.provider"),
+    ("LLM_URL", "llm.url"),
+    ("LLM_MODEL", "llm.model"),
+    ("LLM_API_KEY", "llm.api_key"),
+];
+
+struct RawConfig {
+    values: HashMap<&'static str, String>,
+    errors: Vec<String>,
+}
+
+impl RawConfig {
+    fn required(&mut self, env: &'static str) -> String {
+        match self.values.get(env) {
+            Some(v) => v.clone(),
+            None => {
+                self.errors.push(format!("{} is not set", env));
+                String::new()
+            }
+        }
+    }
+
+    fn optional(&self, env: &'static str, default: &str) -> String {
+        self.values.get(env).cloned().unwrap_or_else(|| default.to_string())
+    }
+
+    fn maybe(&self, env: &'static str) -> Option<String> {
+        self.values.get(env).cloned()
+    }
+
+    fn parsed<T: std::str::FromStr>(&mut self, env: &'static str, default: T) -> T {
+        match self.values.get(env) {
+            None => default,
+            Some(v) => match v.parse() {
+                Ok(parsed) => parsed,
+                Err(_) => {
+                    self.errors.push(format!("{} has an invalid value '{}'", env, v));
+                    default
+                }
+            },
+        }
+    }
+
+    fn url(&mut self, env: &'static str, value: String) -> String {
+        if !value.is_empty() && !value.starts_with("http://") && !value.starts_with("https://") {
+            self.errors.push(format!("{} must start with http:// or https://, got '{}'", env, value));
+        }
+        value.trim_end_matches('/').to_string()
+    }
+}
+
+fn lookup<'a>(table: &'a toml::Table, dotted: &str) -> Option<&'a toml::Value> {
+    let mut parts = dotted.split('.');
+    let mut value = table.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_table()?.get(part)?;
+    }
+    Some(value)
+}
+
+fn read_file() -> Result<toml::Table> {
+    let (path, explicit) = match std::env::var("EPASAL_CONFIG") {
+        Ok(path) => (path, true),
+        Err(_) => ("config.toml".to_string(), false),
+    };
+
+    if !Path::new(&path).exists() {
+        if explicit {
+            bail!("EPASAL_CONFIG points to {}, which does not exist", path);
+        }
+        return Ok(toml::Table::new());
+    }
+
+    let text = std::fs::read_to_string(&path).with_context(|| format!("Failed to read config file {}", path))?;
+    text.parse::<toml::Table>().with_context(|| format!("Invalid config file {}", path))
+}
+
+impl Config {
+    /// Loads `.env`, the config file and the environment, and reports every missing or
+    /// malformed setting at once.
+    pub fn load() -> Result<Config> {
+        dotenvy::dotenv().ok();
+        Self::from_file_and_env()
+    }
+
+    fn from_file_and_env() -> Result<Config> {
+        let file = read_file()?;
+        let mut values = HashMap::new();
+
+        for &(env, key) in KEYS {
+            let from_env = std::env::var(env).ok();
+            let from_file = lookup(&file, key).map(|v| match v {
+                toml::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            });
+
+            if let Some(value) = from_env.or(from_file).filter(|v| !v.trim().is_empty()) {
+                values.insert(env, value.trim().to_string());
+            }
+        }
+
+        let mut raw = RawConfig { values, errors: Vec::new() };
+
+        let default_origin = format!(
+            "http://{}:5173",
+            local_ip_address::local_ip().map(|ip| ip.to_string()).unwrap_or_else(|_| "127.0.0.1".to_string())
+        );
+
+        let base_url = raw.required("BASE_URL");
+        let backend_url = raw.required("BACKEND_URL");
+
+        let llm_provider = raw.parsed("LLM_PROVIDER", LlmProviderKind::Ollama);
+        let (default_llm_url, default_llm_model) = match llm_provider {
+            LlmProviderKind::OpenAi => ("https://api.openai.com/v1", "gpt-4o-mini"),
+            _ => ("http://127.0.0.1:11434", "gemma3"),
+        };
+        let llm_url = raw.optional("LLM_URL", default_llm_url);
+        let llm_api_key = raw.maybe("LLM_API_KEY");
+        if llm_provider == LlmProviderKind::OpenAi && llm_api_key.is_none() {
+            raw.errors.push("LLM_API_KEY must be set when LLM_PROVIDER is openai".to_string());
+        }
+
+        // Configured in rupees like every price a person types in.
+        let cod_default_limit = raw.parsed::<i64>("COD_DEFAULT_LIMIT", 50_000);
+        if cod_default_limit < 0 {
+            raw.errors.push(format!("COD_DEFAULT_LIMIT must not be negative, got '{}'", cod_default_limit));
+        }
+
+        let khalti_url = raw.optional("KHALTI_URL", "https://a.khalti.com/api/v2");
+        let khalti_refund_url = raw.optional("KHALTI_REFUND_URL", "https://khalti.com/api/merchant-transaction");
+
+        let esewa = match (raw.maybe("ESEWA_PRODUCT_CODE"), raw.maybe("ESEWA_SECRET_KEY")) {
+            (Some(product_code), Some(secret_key)) => {
+                // Defaults are eSewa's test environment.
+                let form_url = raw.optional("ESEWA_FORM_URL", "https://rc-epay.esewa.com.np/api/epay/main/v2/form");
+                let status_url = raw.optional("ESEWA_STATUS_URL", "https://rc.esewa.com.np/api/epay/transaction/status/");
+                Some(EsewaConfig {
+                    product_code,
+                    secret_key,
+                    form_url: raw.url("ESEWA_FORM_URL", form_url),
+                    status_url: raw.url("ESEWA_STATUS_URL", status_url),
+                })
+            }
+            (None, None) => None,
+            _ => {
+                raw.errors.push("ESEWA_PRODUCT_CODE and ESEWA_SECRET_KEY must be set together".to_string());
+                None
+            }
+        };
+
+        let config = Config {
+            host: raw.optional("HOST", "0.0.0.0"),
+            port: raw.parsed("PORT", 8080),
+            cors_origin: raw.optional("CORS_ORIGIN", &default_origin),
+            database_url: raw.required("DATABASE_URL"),
+            base_url: raw.url("BASE_URL", base_url),
+            backend_url: raw.url("BACKEND_URL", backend_url),
+            trusted_proxy: raw.parsed("TRUSTED_PROXY", false),
+            smtp: SmtpConfig {
+                email: raw.required("SMTP_EMAIL"),
+                password: raw.required("SMTP_PASSWORD"),
+                server: raw.optional("SMTP_SERVER", "smtp.gmail.com"),
+                port: raw.parsed("SMTP_PORT", 587),
+            },
+            admin: AdminConfig {
+                email: raw.required("ADMIN_EMAIL"),
+                phone: raw.required("ADMIN_PHONE"),
+                require_2fa: raw.parsed("ADMIN_REQUIRE_2FA", false),
+            },
+            khalti: KhaltiConfig {
+                secret_key: raw.required("KHALTI_SECRET_KEY"),
+                url: raw.url("KHALTI_URL", khalti_url),
+                refund_url: raw.url("KHALTI_REFUND_URL", khalti_refund_url),
+            },
+            esewa,
+            cod: CodConfig {
+                default_limit_paisa: cod_default_limit.saturating_mul(100),
+            },
+            llm: LlmConfig {
+                provider: llm_provider,
+                url: raw.url("LLM_URL", llm_url),
+                model: raw.optional("LLM_MODEL", default_llm_model),
+                api_key: llm_api_key,
+            },
+        };
+
+        if !raw.errors.is_empty() {
+            return Err(anyhow!("Invalid configuration:\n  - {}", raw.errors.join("\n  - ")));
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    // The environment belongs to the whole test binary, so only one test may rewrite it at
+    // a time.
+    static ENV: Mutex<()> = Mutex::new(());
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+
+    const REQUIRED: &str = r#"
+        database_url = "postgres://localhost/epasal"
+        base_url = "https://shop.example"
+        backend_url = "https://api.shop.example"
+
+        [smtp]
+        email = "shop@example.com"
+        password = "secret"
+
+        [admin]
+        email = "admin@example.com"
+        phone = "9800000000"
+
+        [khalti]
+        secret_key = "from-file"
+    "#;
+
+    /// Loads `file` as `EPASAL_CONFIG` with only `env` set among the settings. DATABASE_URL
+    /// is left alone, since the database tests running alongside read it.
+    fn load_with(file: &str, env: &[(&str, &str)]) -> Result<Config> {
+        let _guard = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let path = std::env::temp_dir().join(format!(
+            "epasal-config-{}-{}.toml",
+            std::process::id(),
+            FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, file).unwrap();
+
+        let names = KEYS.iter().map(|&(name, _)| name).filter(|&name| name != "DATABASE_URL");
+        let saved: Vec<(&str, Option<String>)> =
+            names.chain(["EPASAL_CONFIG"]).map(|name| (name, std::env::var(name).ok())).collect();
+        for (name, _) in &saved {
+            std::env::remove_var(name);
+        }
+        std::env::set_var("EPASAL_CONFIG", &path);
+        for (name, value) in env {
+            std::env::set_var(name, value);
+        }
+
+        let config = Config::from_file_and_env();
+
+        for (name, value) in saved {
+            match value {
+                Some(value) => std::env::set_var(name, value),
+                None => std::env::remove_var(name),
+            }
+        }
+        std::fs::remove_file(&path).ok();
+        config
+    }
+
+    #[test]
+    fn the_environment_overrides_the_file() {
+        let file = format!(
+            "port = 9000\n{}\n[cod]\ndefault_limit = 1000\n\n[llm]\nprovider = \"openai\"\n",
+            REQUIRED
+        );
+        let config = load_with(&file, &[("PORT", "9100"), ("KHALTI_SECRET_KEY", "from-env"), ("LLM_API_KEY", "sk-test")])
+            .expect("a complete config loads");
+
+        assert_eq!(config.port, 9100);
+        assert_eq!(config.khalti.secret_key, "from-env");
+        assert_eq!(config.smtp.email, "shop@example.com");
+        assert_eq!(config.cod.default_limit_paisa, 100_000);
+        assert!(config.llm.provider == LlmProviderKind::OpenAi);
+        assert_eq!(config.llm.api_key.as_deref(), Some("sk-test"));
+        assert_eq!(config.llm.url, "https://api.openai.com/v1");
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let file = r#"
+            base_url = "shop.example"
+            port = "eighty"
+
+            [cod]
+            default_limit = -5
+
+            [llm]
+            provider = "openai"
+        "#;
+        let error = load_with(file, &[("ESEWA_PRODUCT_CODE", "EPAYTEST")]).err().expect("config is invalid").to_string();
+
+        for problem in [
+            "BASE_URL must start with http:// or https://, got 'shop.example'",
+            "BACKEND_URL is not set",
+            "PORT has an invalid value 'eighty'",
+            "SMTP_EMAIL is not set",
+            "ADMIN_PHONE is not set",
+            "KHALTI_SECRET_KEY is not set",
+            "ESEWA_PRODUCT_CODE and ESEWA_SECRET_KEY must be set together",
+            "COD_DEFAULT_LIMIT must not be negative, got '-5'",
+            "LLM_API_KEY must be set when LLM_PROVIDER is openai",
+        ] {
+            assert!(error.contains(problem), "missing {:?} in:\n{}", problem, error);
+        }
+    }
+}
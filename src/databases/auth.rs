@@ -1,3 +1,6 @@
 pub mod temp_user;
 pub mod tempdb;
-pub mod logindb;
\ No newline at end of file
+pub mod logindb;
+pub mod sessiondb;
+pub mod resetdb;
+pub mod totpdb;
\ No newline at end of file
@@ -1,5 +1,8 @@
-use sqlx::{FromRow, PgPool};
+use sqlx::{FromRow, PgExecutor, PgPool};
 use serde::Deserialize;
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHasher, SaltString};
+use rand_core::OsRng;
 
 #[derive(Debug, Deserialize, FromRow)]
 pub struct LoginUser {
@@ -22,4 +25,123 @@ pub async fn get_user_by_gmail(pool: &PgPool, gmail: &str) -> Result<Option<Logi
     .await;
 
     result
-}
\ No newline at end of file
+}
+
+pub fn hash_password(raw_password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(raw_password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+// Failed logins are counted over this window; past either limit the login is refused until
+// the oldest failure ages out. A successful login resets the per-account count.
+pub const LOCKOUT_WINDOW_MINUTES: i32 = 15;
+pub const MAX_FAILURES_PER_ACCOUNT: i64 = 5;
+pub const MAX_FAILURES_PER_IP: i64 = 20;
+
+// Advisory lock namespaces; attempts against one email, and from one address, are claimed
+// one at a time. The address lock is always taken second, so two claims can't deadlock.
+const EMAIL_LOCK: i32 = 0x6c6f_6765;
+const IP_LOCK: i32 = 0x6c6f_6769;
+
+/// Counts the recent failures against `email` and `ip` and, if neither is over its limit,
+/// records this attempt as a failure before the password is even checked, so parallel
+/// guesses can't all read the same count. Returns the audit row to settle with
+/// `finish_login_attempt`, or `None` if the login is locked out.
+pub async fn claim_login_attempt(pool: &PgPool, email: &str, ip: &str) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    for (namespace, key) in [(EMAIL_LOCK, email), (IP_LOCK, ip)] {
+        sqlx::query("SELECT pg_advisory_xact_lock($1, hashtext($2))")
+            .bind(namespace)
+            .bind(key)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    if recent_failures_for_email(&mut *tx, email).await? >= MAX_FAILURES_PER_ACCOUNT
+        || recent_failures_for_ip(&mut *tx, ip).await? >= MAX_FAILURES_PER_IP
+    {
+        return Ok(None);
+    }
+
+    let id = sqlx::query_scalar::<_, i32>(
+        "INSERT INTO login_audit (email, ip, success) VALUES ($1, $2, FALSE) RETURNING id"
+    )
+    .bind(email)
+    .bind(ip)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(Some(id))
+}
+
+/// Settles a claimed attempt once the password has been checked.
+pub async fn finish_login_attempt(pool: &PgPool, attempt_id: i32, user_id: Option<i32>, success: bool) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE login_audit SET user_id = $2, success = $3 WHERE id = $1")
+        .bind(attempt_id)
+        .bind(user_id)
+        .bind(success)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Drops a claimed attempt that turned out to be neither a failure nor a login yet: the
+/// password was right but a second factor is still owed.
+pub async fn release_login_attempt(pool: &PgPool, attempt_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM login_audit WHERE id = $1")
+        .bind(attempt_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn record_login_attempt(pool: &PgPool, email: &str, user_id: Option<i32>, ip: &str, success: bool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO login_audit (email, user_id, ip, success) VALUES ($1, $2, $3, $4)"
+    )
+    .bind(email)
+    .bind(user_id)
+    .bind(ip)
+    .bind(success)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn recent_failures_for_email(db: impl PgExecutor<'_>, email: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(*) FROM login_audit
+        WHERE email = $1 AND NOT success
+          AND created_at > NOW() - make_interval(mins => $2)
+          AND created_at > COALESCE(
+              (SELECT MAX(created_at) FROM login_audit WHERE email = $1 AND success),
+              'epoch'
+          )
+        "#
+    )
+    .bind(email)
+    .bind(LOCKOUT_WINDOW_MINUTES)
+    .fetch_one(db)
+    .await
+}
+
+async fn recent_failures_for_ip(db: impl PgExecutor<'_>, ip: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(*) FROM login_audit
+        WHERE ip = $1 AND NOT success
+          AND created_at > NOW() - make_interval(mins => $2)
+        "#
+    )
+    .bind(ip)
+    .bind(LOCKOUT_WINDOW_MINUTES)
+    .fetch_one(db)
+    .await
+}
@@ -0,0 +1,141 @@
+use sqlx::PgPool;
+use crate::databases::auth::sessiondb::{generate_token, hash_token};
+
+// Reset links are only good for half an hour.
+const RESET_TTL_MINUTES: i32 = 30;
+
+// Reset requests are counted over this window, per email and per address, so the endpoint
+// can't be used to flood an inbox.
+pub const RESET_WINDOW_MINUTES: i32 = 60;
+pub const MAX_RESETS_PER_EMAIL: i64 = 3;
+pub const MAX_RESETS_PER_IP: i64 = 10;
+
+// Advisory lock namespaces, taken email first like the login claims.
+const EMAIL_LOCK: i32 = 0x7265_7365;
+const IP_LOCK: i32 = 0x7265_7369;
+
+/// Records a reset request unless `email` or `ip` has already made too many lately.
+/// Returns `false` when the request should be refused.
+pub async fn claim_reset_request(pool: &PgPool, email: &str, ip: &str) -> Result<bool, sqlx::Error> {
+    let email = email.trim().to_lowercase();
+    let mut tx = pool.begin().await?;
+
+    for (namespace, key) in [(EMAIL_LOCK, email.as_str()), (IP_LOCK, ip)] {
+        sqlx::query("SELECT pg_advisory_xact_lock($1, hashtext($2))")
+            .bind(namespace)
+            .bind(key)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let (email_requests, ip_requests) = sqlx::query_as::<_, (i64, i64)>(
+        r#"
+        SELECT COUNT(*) FILTER (WHERE email = $1), COUNT(*) FILTER (WHERE ip = $2)
+        FROM password_reset_requests
+        WHERE (email = $1 OR ip = $2)
+          AND created_at > NOW() - make_interval(mins => $3)
+        "#
+    )
+    .bind(&email)
+    .bind(ip)
+    .bind(RESET_WINDOW_MINUTES)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if email_requests >= MAX_RESETS_PER_EMAIL || ip_requests >= MAX_RESETS_PER_IP {
+        return Ok(false);
+    }
+
+    sqlx::query("INSERT INTO password_reset_requests (email, ip) VALUES ($1, $2)")
+        .bind(&email)
+        .bind(ip)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+pub async fn create_reset_token(pool: &PgPool, user_id: i32) -> Result<String, sqlx::Error> {
+    let token = generate_token();
+
+    sqlx::query(
+        "INSERT INTO password_resets (token_hash, user_id, expires_at)
+         VALUES ($1, $2, NOW() + make_interval(mins => $3))"
+    )
+    .bind(hash_token(&token))
+    .bind(user_id)
+    .bind(RESET_TTL_MINUTES)
+    .execute(pool)
+    .await?;
+
+    Ok(token)
+}
+
+/// Deletes the token and returns its owner if it was still valid, so a link can't be used twice.
+pub async fn consume_reset_token(pool: &PgPool, token: &str) -> Result<Option<i32>, sqlx::Error> {
+    sqlx::query_scalar::<_, i32>(
+        "DELETE FROM password_resets
+         WHERE token_hash = $1 AND expires_at > NOW()
+         RETURNING user_id"
+    )
+    .bind(hash_token(token))
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn delete_user_reset_tokens(pool: &PgPool, user_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM password_resets WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[sqlx::test(migrations = false)]
+    async fn a_reset_token_works_once(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let user_id = testutil::create_user(&pool, "user").await;
+        let token = create_reset_token(&pool, user_id).await.unwrap();
+
+        assert_eq!(consume_reset_token(&pool, "not-a-token").await.unwrap(), None);
+        assert_eq!(consume_reset_token(&pool, &token).await.unwrap(), Some(user_id));
+        assert_eq!(consume_reset_token(&pool, &token).await.unwrap(), None);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn an_expired_reset_token_is_refused(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let user_id = testutil::create_user(&pool, "user").await;
+        let token = create_reset_token(&pool, user_id).await.unwrap();
+
+        sqlx::query("UPDATE password_resets SET expires_at = NOW() - INTERVAL '1 minute'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(consume_reset_token(&pool, &token).await.unwrap(), None);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn reset_requests_are_throttled_per_email_and_address(pool: PgPool) {
+        testutil::setup(&pool).await;
+
+        for _ in 0..MAX_RESETS_PER_EMAIL {
+            assert!(claim_reset_request(&pool, "someone@example.com", "1.1.1.1").await.unwrap());
+        }
+        // Case and spacing don't make it a different inbox.
+        assert!(!claim_reset_request(&pool, " Someone@Example.com ", "2.2.2.2").await.unwrap());
+
+        for n in MAX_RESETS_PER_EMAIL..MAX_RESETS_PER_IP {
+            assert!(claim_reset_request(&pool, &format!("other{}@example.com", n), "1.1.1.1").await.unwrap());
+        }
+        assert!(!claim_reset_request(&pool, "fresh@example.com", "1.1.1.1").await.unwrap());
+        assert!(claim_reset_request(&pool, "fresh@example.com", "2.2.2.2").await.unwrap());
+    }
+}
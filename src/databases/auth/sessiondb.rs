@@ -0,0 +1,155 @@
+use sqlx::{FromRow, PgPool};
+use sha2::{Digest, Sha256};
+use rand::RngCore;
+use rand_core::OsRng;
+
+// Sessions stay valid for a week unless the user logs out earlier.
+const SESSION_TTL_DAYS: i32 = 7;
+
+#[derive(Debug, FromRow)]
+pub struct SessionUser {
+    pub id: i32,
+    pub status: String,
+    pub two_factor_enabled: bool,
+}
+
+// Only the SHA-256 of a token is stored, so a leaked table can't be replayed.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub async fn create_session(pool: &PgPool, user_id: i32) -> Result<String, sqlx::Error> {
+    let token = generate_token();
+
+    sqlx::query(
+        "INSERT INTO sessions (token_hash, user_id, expires_at)
+         VALUES ($1, $2, NOW() + make_interval(days => $3))"
+    )
+    .bind(hash_token(&token))
+    .bind(user_id)
+    .bind(SESSION_TTL_DAYS)
+    .execute(pool)
+    .await?;
+
+    Ok(token)
+}
+
+pub async fn find_session_user(pool: &PgPool, token: &str) -> Result<Option<SessionUser>, sqlx::Error> {
+    sqlx::query_as::<_, SessionUser>(
+        r#"
+        SELECT l.id, l.status, COALESCE(t.enabled, FALSE) AS two_factor_enabled
+        FROM sessions s
+        JOIN logininfo l ON l.id = s.user_id
+        LEFT JOIN user_totp t ON t.user_id = l.id
+        WHERE s.token_hash = $1 AND s.expires_at > NOW()
+        "#
+    )
+    .bind(hash_token(token))
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn delete_session(pool: &PgPool, token: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM sessions WHERE token_hash = $1")
+        .bind(hash_token(token))
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_user_sessions(pool: &PgPool, user_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM sessions WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_other_sessions(pool: &PgPool, user_id: i32, keep_token: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM sessions WHERE user_id = $1 AND token_hash <> $2")
+        .bind(user_id)
+        .bind(hash_token(keep_token))
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn clear_expired_sessions(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM sessions WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    async fn expire(pool: &PgPool, token: &str) {
+        sqlx::query("UPDATE sessions SET expires_at = NOW() - INTERVAL '1 second' WHERE token_hash = $1")
+            .bind(hash_token(token))
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn a_session_lasts_until_it_expires(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let token = create_session(&pool, user).await.unwrap();
+
+        let found = find_session_user(&pool, &token).await.unwrap().expect("fresh session");
+        assert_eq!(found.id, user);
+        assert!(!found.two_factor_enabled);
+
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE token_hash = $1")
+            .bind(&token)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, 0, "the raw token is never stored");
+
+        expire(&pool, &token).await;
+        assert!(find_session_user(&pool, &token).await.unwrap().is_none());
+
+        clear_expired_sessions(&pool).await.unwrap();
+        let left: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE user_id = $1")
+            .bind(user)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(left, 0);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn deleting_sessions(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let kept = create_session(&pool, user).await.unwrap();
+        let other = create_session(&pool, user).await.unwrap();
+        let third = create_session(&pool, user).await.unwrap();
+
+        delete_session(&pool, &third).await.unwrap();
+        assert!(find_session_user(&pool, &third).await.unwrap().is_none());
+        assert!(find_session_user(&pool, &other).await.unwrap().is_some());
+
+        delete_other_sessions(&pool, user, &kept).await.unwrap();
+        assert!(find_session_user(&pool, &other).await.unwrap().is_none());
+        assert!(find_session_user(&pool, &kept).await.unwrap().is_some());
+
+        delete_user_sessions(&pool, user).await.unwrap();
+        assert!(find_session_user(&pool, &kept).await.unwrap().is_none());
+    }
+}
@@ -17,3 +17,14 @@ pub struct TempUser {
     pub password: String,
     pub code: String,
 }
+
+#[derive(sqlx::FromRow)]
+pub struct StoredTempUser {
+    pub name: String,
+    pub number: String,
+    pub gmail: String,
+    pub password: String,
+    pub code: String,
+    pub attempts: i32,
+    pub expired: bool,
+}
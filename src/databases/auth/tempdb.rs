@@ -1,8 +1,12 @@
 use sqlx::PgPool;
-use crate::databases::auth::temp_user::{SignupData, TempUser};
+use crate::databases::auth::temp_user::{SignupData, StoredTempUser, TempUser};
 use uuid::Uuid;
 use rand::Rng;
 
+// Verification codes expire after this many minutes and allow this many guesses.
+pub const OTP_TTL_MINUTES: i32 = 10;
+pub const MAX_OTP_ATTEMPTS: i32 = 5;
+
 pub async fn user_exists(pool: &PgPool, email: &str, number: &str) -> Result<bool, sqlx::Error> {
     let result = sqlx::query(
     "SELECT 1 FROM logininfo WHERE email = $1 OR phoneNumber = $2"
@@ -15,9 +19,21 @@ pub async fn user_exists(pool: &PgPool, email: &str, number: &str) -> Result<boo
     Ok(result.is_some())
 }
 
+pub enum CodeCheck {
+    Valid(StoredTempUser),
+    Invalid { attempts_left: i32 },
+    Expired,
+    TooManyAttempts,
+    NotFound,
+}
+
+fn generate_code() -> String {
+    format!("{:05}", rand::thread_rng().gen_range(10000..99999))
+}
+
 pub async fn insert_temp_user(pool: &PgPool, data: SignupData) -> Result<TempUser, sqlx::Error> {
     let temp_id = format!("signup{}", Uuid::new_v4());
-    let code = format!("{:05}", rand::thread_rng().gen_range(10000..99999));
+    let code = generate_code();
 
     sqlx::query(
     "INSERT INTO temp_users (temp_id, name, number, gmail, password, code)
@@ -63,4 +79,218 @@ pub async fn check_conflict_field(pool: &PgPool, email: &str, number: &str) -> R
     }
 
     Ok("none".to_string()) 
-}
\ No newline at end of file
+}
+
+// Whether another account already uses `email`; the user's own row doesn't count.
+pub async fn email_taken_by_other(pool: &PgPool, email: &str, user_id: i32) -> Result<bool, sqlx::Error> {
+    let taken = sqlx::query("SELECT 1 FROM logininfo WHERE email = $1 AND id <> $2")
+        .bind(email)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .is_some();
+
+    Ok(taken)
+}
+
+// Whether another account already uses `number`; the user's own row doesn't count.
+pub async fn number_taken_by_other(pool: &PgPool, number: &str, user_id: i32) -> Result<bool, sqlx::Error> {
+    let taken = sqlx::query("SELECT 1 FROM logininfo WHERE phoneNumber = $1 AND id <> $2")
+        .bind(number)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .is_some();
+
+    Ok(taken)
+}
+
+// Stages an email change for an existing account; the row carries the user's id
+// so it can never be redeemed through the signup `/verify` route.
+pub async fn insert_email_change(pool: &PgPool, user_id: i32, new_email: &str) -> Result<TempUser, sqlx::Error> {
+    let temp_id = format!("email{}", Uuid::new_v4());
+    let code = generate_code();
+
+    sqlx::query("DELETE FROM temp_users WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    let (name, number, password): (String, String, String) = sqlx::query_as(
+    "INSERT INTO temp_users (temp_id, name, number, gmail, password, code, user_id)
+     SELECT $1, name, phoneNumber, $2, password, $3, id FROM logininfo WHERE id = $4
+     RETURNING name, number, password"
+)
+.bind(&temp_id)
+.bind(new_email)
+.bind(&code)
+.bind(user_id)
+.fetch_one(pool)
+.await?;
+
+    Ok(TempUser {
+        temp_id,
+        name,
+        number,
+        email: new_email.to_string(),
+        password,
+        code,
+    })
+}
+
+/// Counts an attempt against `temp_id` and checks the code. Expired or exhausted
+/// rows are deleted. `owner` must match the row's `user_id` (None for signups).
+/// On success the row is left in place for the caller to promote and delete.
+pub async fn check_temp_code(pool: &PgPool, temp_id: &str, code: &str, owner: Option<i32>) -> Result<CodeCheck, sqlx::Error> {
+    // Count the attempt before comparing so parallel guesses can't slip past the limit.
+    let stored = sqlx::query_as::<_, StoredTempUser>(
+    r#"
+    UPDATE temp_users SET attempts = attempts + 1
+    WHERE temp_id = $1 AND user_id IS NOT DISTINCT FROM $3
+    RETURNING name, number, gmail, password, code, attempts,
+              created_at < NOW() - make_interval(mins => $2) AS expired
+    "#
+)
+.bind(temp_id)
+.bind(OTP_TTL_MINUTES)
+.bind(owner)
+.fetch_optional(pool)
+.await?;
+
+    let Some(stored) = stored else {
+        return Ok(CodeCheck::NotFound);
+    };
+
+    if stored.expired || stored.attempts > MAX_OTP_ATTEMPTS {
+        sqlx::query("DELETE FROM temp_users WHERE temp_id = $1")
+            .bind(temp_id)
+            .execute(pool)
+            .await?;
+
+        return Ok(if stored.expired { CodeCheck::Expired } else { CodeCheck::TooManyAttempts });
+    }
+
+    if !codes_match(&stored.code, code.trim()) {
+        return Ok(CodeCheck::Invalid { attempts_left: MAX_OTP_ATTEMPTS - stored.attempts });
+    }
+
+    Ok(CodeCheck::Valid(stored))
+}
+
+// Compares two codes without short-circuiting on the first differing byte.
+fn codes_match(expected: &str, given: &str) -> bool {
+    let (a, b) = (expected.as_bytes(), given.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    async fn signup(pool: &PgPool) -> TempUser {
+        insert_temp_user(pool, SignupData {
+            name: "Sita".to_string(),
+            number: "9811111111".to_string(),
+            email: "sita@example.com".to_string(),
+            password: "hash".to_string(),
+        })
+        .await
+        .unwrap()
+    }
+
+    fn wrong_code(code: &str) -> String {
+        if code == "00000" { "11111".to_string() } else { "00000".to_string() }
+    }
+
+    async fn row_exists(pool: &PgPool, temp_id: &str) -> bool {
+        sqlx::query("SELECT 1 FROM temp_users WHERE temp_id = $1")
+            .bind(temp_id)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn right_code_is_valid_and_left_for_the_caller(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let temp = signup(&pool).await;
+
+        let check = check_temp_code(&pool, &temp.temp_id, &format!(" {} ", temp.code), None).await.unwrap();
+        let CodeCheck::Valid(stored) = check else { panic!("expected a valid code") };
+        assert_eq!(stored.gmail, "sita@example.com");
+        assert_eq!(stored.attempts, 1);
+        assert!(row_exists(&pool, &temp.temp_id).await);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn wrong_codes_count_down_then_lock_out(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let temp = signup(&pool).await;
+        let wrong = wrong_code(&temp.code);
+
+        for expected_left in (0..MAX_OTP_ATTEMPTS).rev() {
+            match check_temp_code(&pool, &temp.temp_id, &wrong, None).await.unwrap() {
+                CodeCheck::Invalid { attempts_left } => assert_eq!(attempts_left, expected_left),
+                _ => panic!("expected an invalid code"),
+            }
+        }
+
+        // Even the right code is refused once the guesses are used up, and the row is gone.
+        let check = check_temp_code(&pool, &temp.temp_id, &temp.code, None).await.unwrap();
+        assert!(matches!(check, CodeCheck::TooManyAttempts));
+        assert!(!row_exists(&pool, &temp.temp_id).await);
+        assert!(matches!(
+            check_temp_code(&pool, &temp.temp_id, &temp.code, None).await.unwrap(),
+            CodeCheck::NotFound
+        ));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn expired_code_is_refused_and_deleted(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let temp = signup(&pool).await;
+        sqlx::query("UPDATE temp_users SET created_at = NOW() - make_interval(mins => $2 + 1) WHERE temp_id = $1")
+            .bind(&temp.temp_id)
+            .bind(OTP_TTL_MINUTES)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let check = check_temp_code(&pool, &temp.temp_id, &temp.code, None).await.unwrap();
+        assert!(matches!(check, CodeCheck::Expired));
+        assert!(!row_exists(&pool, &temp.temp_id).await);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn email_change_codes_only_work_for_their_owner(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let owner = testutil::create_user(&pool, "user").await;
+        let other = testutil::create_user(&pool, "user").await;
+        let temp = insert_email_change(&pool, owner, "new@example.com").await.unwrap();
+
+        for wrong_owner in [None, Some(other)] {
+            assert!(matches!(
+                check_temp_code(&pool, &temp.temp_id, &temp.code, wrong_owner).await.unwrap(),
+                CodeCheck::NotFound
+            ));
+        }
+        assert!(matches!(
+            check_temp_code(&pool, &temp.temp_id, &temp.code, Some(owner)).await.unwrap(),
+            CodeCheck::Valid(_)
+        ));
+    }
+
+    #[test]
+    fn codes_match_needs_every_byte() {
+        assert!(codes_match("12345", "12345"));
+        assert!(!codes_match("12345", "12346"));
+        assert!(!codes_match("12345", "1234"));
+        assert!(!codes_match("12345", "123456"));
+        assert!(!codes_match("12345", ""));
+    }
+}
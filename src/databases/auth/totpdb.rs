@@ -0,0 +1,148 @@
+use sqlx::{FromRow, PgPool};
+use crate::databases::auth::sessiondb::{generate_token, hash_token};
+
+// A password-verified login has five minutes and five tries to supply its second factor.
+const CHALLENGE_TTL_MINUTES: i32 = 5;
+pub const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+#[derive(Debug, FromRow)]
+pub struct UserTotp {
+    pub secret: String,
+    pub enabled: bool,
+}
+
+pub async fn get_user_totp(pool: &PgPool, user_id: i32) -> Result<Option<UserTotp>, sqlx::Error> {
+    sqlx::query_as::<_, UserTotp>("SELECT secret, enabled FROM user_totp WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn is_totp_enabled(pool: &PgPool, user_id: i32) -> Result<bool, sqlx::Error> {
+    Ok(get_user_totp(pool, user_id).await?.map(|t| t.enabled).unwrap_or(false))
+}
+
+// Starting a new enrolment replaces any unconfirmed secret; an enabled one must be disabled first.
+pub async fn store_pending_secret(pool: &PgPool, user_id: i32, secret: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO user_totp (user_id, secret, enabled)
+        VALUES ($1, $2, FALSE)
+        ON CONFLICT (user_id) DO UPDATE
+        SET secret = EXCLUDED.secret, last_used_step = 0, created_at = NOW()
+        WHERE user_totp.enabled = FALSE
+        "#
+    )
+    .bind(user_id)
+    .bind(secret)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Records `step` as used. Returns false if that step (or a later one) was already used,
+/// which is how a code is kept single-use.
+pub async fn mark_step_used(pool: &PgPool, user_id: i32, step: u64) -> Result<bool, sqlx::Error> {
+    let updated = sqlx::query(
+        "UPDATE user_totp SET last_used_step = $2 WHERE user_id = $1 AND last_used_step < $2"
+    )
+    .bind(user_id)
+    .bind(step as i64)
+    .execute(pool)
+    .await?;
+
+    Ok(updated.rows_affected() == 1)
+}
+
+pub async fn enable_totp(pool: &PgPool, user_id: i32, recovery_codes: &[String]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("UPDATE user_totp SET enabled = TRUE WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for code in recovery_codes {
+        sqlx::query("INSERT INTO totp_recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(hash_token(code))
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await
+}
+
+pub async fn disable_totp(pool: &PgPool, user_id: i32) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM user_totp WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await
+}
+
+/// Burns a recovery code. Returns false if it doesn't exist or was already used.
+pub async fn use_recovery_code(pool: &PgPool, user_id: i32, code: &str) -> Result<bool, sqlx::Error> {
+    let updated = sqlx::query(
+        "UPDATE totp_recovery_codes SET used_at = NOW()
+         WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL"
+    )
+    .bind(user_id)
+    .bind(hash_token(code))
+    .execute(pool)
+    .await?;
+
+    Ok(updated.rows_affected() == 1)
+}
+
+pub async fn create_login_challenge(pool: &PgPool, user_id: i32) -> Result<String, sqlx::Error> {
+    let token = generate_token();
+
+    sqlx::query(
+        "INSERT INTO login_challenges (token_hash, user_id, expires_at)
+         VALUES ($1, $2, NOW() + make_interval(mins => $3))"
+    )
+    .bind(hash_token(&token))
+    .bind(user_id)
+    .bind(CHALLENGE_TTL_MINUTES)
+    .execute(pool)
+    .await?;
+
+    Ok(token)
+}
+
+/// Counts an attempt against a pending challenge and returns its user and attempt number,
+/// or None if the challenge is unknown or expired.
+pub async fn attempt_login_challenge(pool: &PgPool, token: &str) -> Result<Option<(i32, i32)>, sqlx::Error> {
+    sqlx::query_as::<_, (i32, i32)>(
+        "UPDATE login_challenges SET attempts = attempts + 1
+         WHERE token_hash = $1 AND expires_at > NOW()
+         RETURNING user_id, attempts"
+    )
+    .bind(hash_token(token))
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn delete_login_challenge(pool: &PgPool, token: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM login_challenges WHERE token_hash = $1 OR expires_at <= NOW()")
+        .bind(hash_token(token))
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use anyhow::{anyhow, bail, Context, Result};
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, PgPool};
+use std::{fs, path::Path};
+
+// Any fixed key works; it only has to be the same for every instance of the backend.
+const MIGRATION_LOCK_KEY: i64 = 0x6550_6173_616c;
+
+pub struct Migration {
+    pub version: i32,
+    pub name: String,
+    pub sql: String,
+    pub checksum: String,
+}
+
+/// Reads `databases/migrations/NNNN_name.sql`, ordered by the numeric prefix.
+pub fn load_migrations() -> Result<Vec<Migration>> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("databases/migrations");
+    let mut migrations = Vec::new();
+
+    for entry in fs::read_dir(&dir).with_context(|| format!("Failed to read migrations dir: {:?}", dir))? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("sql") {
+            continue;
+        }
+
+        let file_name = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string();
+        let (version, name) = file_name
+            .split_once('_')
+            .and_then(|(v, n)| v.parse::<i32>().ok().map(|v| (v, n.to_string())))
+            .ok_or_else(|| anyhow!("Migration file {:?} must be named NNNN_description.sql", path))?;
+
+        let sql = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read migration file: {:?}", path))?;
+        let checksum = hex::encode(Sha256::digest(sql.as_bytes()));
+
+        migrations.push(Migration { version, name, sql, checksum });
+    }
+
+    migrations.sort_by_key(|m| m.version);
+
+    if let Some(pair) = migrations.windows(2).find(|w| w[0].version == w[1].version) {
+        bail!("Duplicate migration version {}: '{}' and '{}'", pair[0].version, pair[0].name, pair[1].name);
+    }
+
+    Ok(migrations)
+}
+
+/// Applies every migration that isn't recorded in `schema_migrations`, each in its own
+/// transaction. Fails if an already-applied file has been edited since.
+pub async fn run_migrations(pool: &PgPool) -> Result<()> {
+    let migrations = load_migrations()?;
+
+    // One connection for the whole run so the advisory lock covers it.
+    let mut conn = pool.acquire().await.context("Failed to acquire connection for migrations")?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            checksum TEXT NOT NULL,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )",
+    )
+    .await
+    .context("Failed to create schema_migrations table")?;
+
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await
+        .context("Failed to take migration lock")?;
+
+    let result = apply_pending(&mut conn, &migrations).await;
+
+    sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await
+        .context("Failed to release migration lock")?;
+
+    result
+}
+
+async fn apply_pending(conn: &mut sqlx::PgConnection, migrations: &[Migration]) -> Result<()> {
+    let applied: Vec<(i32, String)> = sqlx::query_as("SELECT version, checksum FROM schema_migrations")
+        .fetch_all(&mut *conn)
+        .await
+        .context("Failed to read schema_migrations")?;
+
+    let mut pending = 0;
+
+    for migration in migrations {
+        if let Some((_, checksum)) = applied.iter().find(|(v, _)| *v == migration.version) {
+            if *checksum != migration.checksum {
+                bail!(
+                    "Migration {:04}_{} was modified after it was applied; add a new migration instead",
+                    migration.version,
+                    migration.name
+                );
+            }
+            continue;
+        }
+
+        println!("Applying migration {:04}_{}...", migration.version, migration.name);
+
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        tx.execute(migration.sql.as_str())
+            .await
+            .with_context(|| format!("Migration {:04}_{} failed", migration.version, migration.name))?;
+        sqlx::query("INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)")
+            .bind(migration.version)
+            .bind(&migration.name)
+            .bind(&migration.checksum)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        pending += 1;
+    }
+
+    if pending == 0 {
+        println!("Database schema is up to date.");
+    } else {
+        println!("Applied {} migration(s).", pending);
+    }
+
+    Ok(())
+}
+
+/// Prints each migration with whether it has been applied, for `ePasal migrate status`.
+pub async fn print_status(pool: &PgPool) -> Result<()> {
+    let migrations = load_migrations()?;
+
+    // A database that has never been migrated has no table yet; everything is pending.
+    let has_table: bool = sqlx::query_scalar("SELECT to_regclass('schema_migrations') IS NOT NULL")
+        .fetch_one(pool)
+        .await
+        .context("Failed to look for schema_migrations")?;
+
+    let applied: Vec<(i32, chrono::DateTime<chrono::Utc>)> = if has_table {
+        sqlx::query_as("SELECT version, applied_at FROM schema_migrations")
+            .fetch_all(pool)
+            .await
+            .context("Failed to read schema_migrations")?
+    } else {
+        Vec::new()
+    };
+
+    for migration in &migrations {
+        match applied.iter().find(|(v, _)| *v == migration.version) {
+            Some((_, at)) => println!("  [x] {:04}_{} (applied {})", migration.version, migration.name, at),
+            None => println!("  [ ] {:04}_{}", migration.version, migration.name),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test(migrations = false)]
+    async fn status_works_before_and_after_migrating(pool: PgPool) {
+        print_status(&pool).await.unwrap();
+        run_migrations(&pool).await.unwrap();
+        print_status(&pool).await.unwrap();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn status_reports_an_unreadable_history(pool: PgPool) {
+        pool.execute("CREATE TABLE schema_migrations (version TEXT)").await.unwrap();
+        assert!(print_status(&pool).await.is_err());
+    }
+}
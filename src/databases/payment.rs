@@ -0,0 +1,4 @@
+pub mod paymentdb;
+pub mod coddb;
+pub mod refunddb;
+pub mod reconciliationdb;
@@ -0,0 +1,109 @@
+use sqlx::{PgExecutor, PgPool};
+use crate::databases::payment::paymentdb::{insert_payment, transition_payment, PaymentStatus};
+use crate::databases::shop::cartdb::{remove_paid_items, CartLine};
+use crate::databases::shop::orderdb::create_order_from_payment;
+use crate::databases::shop::stockdb::OutOfStock;
+
+/// Cash-on-delivery payments use the payments table like any gateway. They stay pending
+/// until an admin records the cash as collected or the delivery as failed.
+pub const COD_GATEWAY: &str = "cod";
+
+pub enum CodCheckout {
+    Placed { order_id: i32 },
+    /// The order would take the customer's uncollected COD total past their limit.
+    OverLimit { limit_paisa: i64, outstanding_paisa: i64 },
+    OutOfStock(OutOfStock),
+}
+
+/// The customer's own limit if an admin set one, otherwise `default_paisa`.
+pub async fn get_cod_limit(db: impl PgExecutor<'_>, user_id: i32, default_paisa: i64) -> Result<i64, sqlx::Error> {
+    let limit = sqlx::query_scalar::<_, i64>("SELECT limit_paisa FROM cod_limits WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(db)
+        .await?;
+
+    Ok(limit.unwrap_or(default_paisa))
+}
+
+/// `None` removes the customer's own limit, putting them back on the default.
+pub async fn set_cod_limit(
+    pool: &PgPool,
+    user_id: i32,
+    limit_paisa: Option<i64>,
+    updated_by: i32,
+) -> Result<(), sqlx::Error> {
+    match limit_paisa {
+        Some(limit) => {
+            sqlx::query(
+                "INSERT INTO cod_limits (user_id, limit_paisa, updated_by)
+                 VALUES ($1, $2, $3)
+                 ON CONFLICT (user_id) DO UPDATE
+                 SET limit_paisa = EXCLUDED.limit_paisa, updated_by = EXCLUDED.updated_by, updated_at = NOW()"
+            )
+            .bind(user_id)
+            .bind(limit)
+            .bind(updated_by)
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM cod_limits WHERE user_id = $1")
+                .bind(user_id)
+                .execute(pool)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// What the customer owes on COD orders that haven't been collected yet.
+pub async fn outstanding_cod_paisa(db: impl PgExecutor<'_>, user_id: i32) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        "SELECT COALESCE(SUM(amount_paisa), 0)::BIGINT FROM payments
+         WHERE user_id = $1 AND gateway = $2 AND status = 'pending'"
+    )
+    .bind(user_id)
+    .bind(COD_GATEWAY)
+    .fetch_one(db)
+    .await
+}
+
+/// Places a COD order straight away: records the payment as pending, holds the stock
+/// with no expiry and takes the lines out of the cart. The limit check and the order
+/// are made under a lock on the customer, so parallel checkouts can't both squeeze
+/// under the limit.
+pub async fn place_cod_order(
+    pool: &PgPool,
+    user_id: i32,
+    order_ref: &str,
+    lines: &[CartLine],
+    amount_paisa: i64,
+    default_limit_paisa: i64,
+) -> Result<CodCheckout, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("SELECT id FROM logininfo WHERE id = $1 FOR UPDATE")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let limit_paisa = get_cod_limit(&mut *tx, user_id, default_limit_paisa).await?;
+    let outstanding_paisa = outstanding_cod_paisa(&mut *tx, user_id).await?;
+
+    if outstanding_paisa + amount_paisa > limit_paisa {
+        return Ok(CodCheckout::OverLimit { limit_paisa, outstanding_paisa });
+    }
+
+    let payment = match insert_payment(&mut tx, user_id, COD_GATEWAY, order_ref, lines, amount_paisa, None).await? {
+        Ok(payment) => payment,
+        Err(out_of_stock) => return Ok(CodCheckout::OutOfStock(out_of_stock)),
+    };
+
+    transition_payment(&mut tx, payment.id, PaymentStatus::Pending, None, Some("cash on delivery")).await?;
+    let order_id = create_order_from_payment(&mut tx, payment.id).await?;
+    remove_paid_items(&mut tx, user_id, payment.id).await?;
+
+    tx.commit().await?;
+    Ok(CodCheckout::Placed { order_id })
+}
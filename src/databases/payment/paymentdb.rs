@@ -0,0 +1,374 @@
+use serde::Serialize;
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::databases::shop::cartdb::CartLine;
+use crate::databases::shop::stockdb::{consume_reservation, release_reservation, reserve_stock, OutOfStock};
+
+/// Lifecycle of a payment. `Initiated` is ours alone; `Pending` means the gateway has
+/// accepted it and is waiting on the customer. Everything else is final, except that a
+/// completed payment can still be refunded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    Initiated,
+    Pending,
+    Completed,
+    Failed,
+    Expired,
+    Refunded,
+    UserCanceled,
+}
+
+impl PaymentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::Initiated => "initiated",
+            PaymentStatus::Pending => "pending",
+            PaymentStatus::Completed => "completed",
+            PaymentStatus::Failed => "failed",
+            PaymentStatus::Expired => "expired",
+            PaymentStatus::Refunded => "refunded",
+            PaymentStatus::UserCanceled => "user_canceled",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<PaymentStatus> {
+        match s {
+            "initiated" => Some(PaymentStatus::Initiated),
+            "pending" => Some(PaymentStatus::Pending),
+            "completed" => Some(PaymentStatus::Completed),
+            "failed" => Some(PaymentStatus::Failed),
+            "expired" => Some(PaymentStatus::Expired),
+            "refunded" => Some(PaymentStatus::Refunded),
+            "user_canceled" => Some(PaymentStatus::UserCanceled),
+            _ => None,
+        }
+    }
+
+    pub fn is_final(&self) -> bool {
+        !matches!(self, PaymentStatus::Initiated | PaymentStatus::Pending)
+    }
+
+    pub fn can_transition_to(&self, next: PaymentStatus) -> bool {
+        use PaymentStatus::*;
+        match self {
+            Initiated => matches!(next, Pending | Completed | Failed | Expired | UserCanceled),
+            Pending => matches!(next, Completed | Failed | Expired | UserCanceled),
+            Completed => next == Refunded,
+            Failed | Expired | Refunded | UserCanceled => false,
+        }
+    }
+}
+
+#[derive(FromRow, Serialize)]
+pub struct Payment {
+    pub id: i32,
+    pub user_id: i32,
+    pub gateway: String,
+    pub gateway_ref: Option<String>,
+    pub order_ref: String,
+    pub amount_paisa: i64,
+    pub currency: String,
+    pub status: String,
+    pub transaction_id: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl Payment {
+    pub fn status(&self) -> PaymentStatus {
+        // The CHECK constraint keeps the column to known values.
+        PaymentStatus::parse(&self.status).unwrap_or(PaymentStatus::Failed)
+    }
+}
+
+pub enum Transition {
+    Applied,
+    /// The payment was already in the requested status.
+    Unchanged,
+    /// Not a legal move from the payment's current status.
+    Rejected(PaymentStatus),
+}
+
+/// Records a new payment with its lines and reserves their stock, all or nothing.
+/// `reservation_ttl` is how long the stock is held if the payment never settles.
+pub async fn create_payment(
+    pool: &PgPool,
+    user_id: i32,
+    gateway: &str,
+    order_ref: &str,
+    lines: &[CartLine],
+    amount_paisa: i64,
+    reservation_ttl: Option<i32>,
+) -> Result<Result<Payment, OutOfStock>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // Dropping the transaction on the error path rolls the payment back too.
+    let payment = insert_payment(&mut tx, user_id, gateway, order_ref, lines, amount_paisa, reservation_ttl).await?;
+    if payment.is_ok() {
+        tx.commit().await?;
+    }
+    Ok(payment)
+}
+
+/// `create_payment` inside the caller's transaction. On `OutOfStock` the caller must
+/// roll back, as the payment row has already been written.
+pub async fn insert_payment(
+    conn: &mut PgConnection,
+    user_id: i32,
+    gateway: &str,
+    order_ref: &str,
+    lines: &[CartLine],
+    amount_paisa: i64,
+    reservation_ttl: Option<i32>,
+) -> Result<Result<Payment, OutOfStock>, sqlx::Error> {
+    let payment = sqlx::query_as::<_, Payment>(
+        "INSERT INTO payments (user_id, gateway, order_ref, amount_paisa, status)
+         VALUES ($1, $2, $3, $4, 'initiated')
+         RETURNING *"
+    )
+    .bind(user_id)
+    .bind(gateway)
+    .bind(order_ref)
+    .bind(amount_paisa)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    for line in lines {
+        sqlx::query(
+            "INSERT INTO payment_items (payment_id, laptop_id, quantity, unit_price)
+             VALUES ($1, $2, $3, $4)"
+        )
+        .bind(payment.id)
+        .bind(line.laptop_id)
+        .bind(line.quantity)
+        .bind(&line.unit_price)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    if let Err(out_of_stock) = reserve_stock(&mut *conn, payment.id, lines, reservation_ttl).await? {
+        return Ok(Err(out_of_stock));
+    }
+
+    log_transition(conn, payment.id, None, PaymentStatus::Initiated, None).await?;
+
+    Ok(Ok(payment))
+}
+
+pub async fn set_gateway_ref(pool: &PgPool, payment_id: i32, gateway_ref: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE payments SET gateway_ref = $2, updated_at = NOW() WHERE id = $1")
+        .bind(payment_id)
+        .bind(gateway_ref)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_payment(pool: &PgPool, payment_id: i32) -> Result<Option<Payment>, sqlx::Error> {
+    sqlx::query_as::<_, Payment>("SELECT * FROM payments WHERE id = $1")
+        .bind(payment_id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Payments through any of `gateways` that are still waiting on the gateway and were
+/// started more than `older_than_minutes` ago, oldest first.
+pub async fn list_unsettled_payments(
+    pool: &PgPool,
+    gateways: &[&str],
+    older_than_minutes: i32,
+) -> Result<Vec<Payment>, sqlx::Error> {
+    sqlx::query_as::<_, Payment>(
+        "SELECT * FROM payments
+         WHERE status IN ('initiated', 'pending')
+           AND gateway = ANY($1)
+           AND created_at < NOW() - make_interval(mins => $2)
+         ORDER BY created_at"
+    )
+    .bind(gateways)
+    .bind(older_than_minutes)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn find_user_payment(
+    pool: &PgPool,
+    gateway: &str,
+    order_ref: &str,
+    user_id: i32,
+) -> Result<Option<Payment>, sqlx::Error> {
+    sqlx::query_as::<_, Payment>(
+        "SELECT * FROM payments WHERE gateway = $1 AND order_ref = $2 AND user_id = $3"
+    )
+    .bind(gateway)
+    .bind(order_ref)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Moves a payment to `to` if the state machine allows it, recording the transition.
+/// Locks the row, so concurrent callers see `Unchanged` rather than applying it twice;
+/// run any side effects of the new status in the same transaction. The payment's stock
+/// reservation is consumed on completion and released on any other final status.
+pub async fn transition_payment(
+    conn: &mut PgConnection,
+    payment_id: i32,
+    to: PaymentStatus,
+    transaction_id: Option<&str>,
+    reason: Option<&str>,
+) -> Result<Transition, sqlx::Error> {
+    let current: String = sqlx::query_scalar("SELECT status FROM payments WHERE id = $1 FOR UPDATE")
+        .bind(payment_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    let from = PaymentStatus::parse(&current).unwrap_or(PaymentStatus::Failed);
+
+    if from == to {
+        return Ok(Transition::Unchanged);
+    }
+    if !from.can_transition_to(to) {
+        return Ok(Transition::Rejected(from));
+    }
+
+    sqlx::query(
+        "UPDATE payments
+         SET status = $2, transaction_id = COALESCE($3, transaction_id), updated_at = NOW()
+         WHERE id = $1"
+    )
+    .bind(payment_id)
+    .bind(to.as_str())
+    .bind(transaction_id)
+    .execute(&mut *conn)
+    .await?;
+
+    match to {
+        PaymentStatus::Completed => consume_reservation(&mut *conn, payment_id).await?,
+        PaymentStatus::Failed | PaymentStatus::Expired | PaymentStatus::UserCanceled => {
+            release_reservation(&mut *conn, payment_id).await?
+        }
+        PaymentStatus::Initiated | PaymentStatus::Pending | PaymentStatus::Refunded => {}
+    }
+
+    log_transition(conn, payment_id, Some(from), to, reason).await?;
+
+    Ok(Transition::Applied)
+}
+
+/// `transition_payment` in a transaction of its own, for changes with no other side effects.
+pub async fn apply_transition(
+    pool: &PgPool,
+    payment_id: i32,
+    to: PaymentStatus,
+    reason: Option<&str>,
+) -> Result<Transition, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let transition = transition_payment(&mut tx, payment_id, to, None, reason).await?;
+    tx.commit().await?;
+    Ok(transition)
+}
+
+async fn log_transition(
+    conn: &mut PgConnection,
+    payment_id: i32,
+    from: Option<PaymentStatus>,
+    to: PaymentStatus,
+    reason: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO payment_transitions (payment_id, from_status, to_status, reason)
+         VALUES ($1, $2, $3, $4)"
+    )
+    .bind(payment_id)
+    .bind(from.map(|s| s.as_str()))
+    .bind(to.as_str())
+    .bind(reason)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::databases::shop::orderdb::OrderStatus;
+    use crate::testutil;
+    use PaymentStatus::*;
+
+    const ALL: [PaymentStatus; 7] = [Initiated, Pending, Completed, Failed, Expired, Refunded, UserCanceled];
+
+    // Every move the state machine allows; anything else between two different statuses is refused.
+    const ALLOWED: &[(PaymentStatus, PaymentStatus)] = &[
+        (Initiated, Pending),
+        (Initiated, Completed),
+        (Initiated, Failed),
+        (Initiated, Expired),
+        (Initiated, UserCanceled),
+        (Pending, Completed),
+        (Pending, Failed),
+        (Pending, Expired),
+        (Pending, UserCanceled),
+        (Completed, Refunded),
+    ];
+
+    #[test]
+    fn status_names_round_trip() {
+        for status in ALL {
+            assert_eq!(PaymentStatus::parse(status.as_str()), Some(status));
+        }
+        assert_eq!(PaymentStatus::parse("Completed"), None);
+    }
+
+    #[test]
+    fn can_transition_to_allows_exactly_the_table() {
+        for from in ALL {
+            for to in ALL {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    ALLOWED.contains(&(from, to)),
+                    "{} -> {}",
+                    from.as_str(),
+                    to.as_str()
+                );
+            }
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn apply_transition_follows_the_table(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Table", 100).await;
+
+        for from in ALL {
+            for to in ALL {
+                let order = testutil::create_order(&pool, user, laptop, "khalti", from, OrderStatus::Placed).await;
+                let case = format!("{} -> {}", from.as_str(), to.as_str());
+
+                let transition = apply_transition(&pool, order.payment_id, to, Some("test")).await.unwrap();
+                let stored = get_payment(&pool, order.payment_id).await.unwrap().unwrap().status();
+
+                match transition {
+                    Transition::Unchanged => assert_eq!(from, to, "{}", case),
+                    Transition::Applied => {
+                        assert!(ALLOWED.contains(&(from, to)), "{} was applied", case);
+                        assert_eq!(stored, to, "{}", case);
+                    }
+                    Transition::Rejected(current) => {
+                        assert!(from != to && !ALLOWED.contains(&(from, to)), "{} was rejected", case);
+                        assert_eq!(current, from, "{}", case);
+                        assert_eq!(stored, from, "{}", case);
+                    }
+                }
+            }
+        }
+
+        let logged: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM payment_transitions WHERE reason = 'test'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(logged, ALLOWED.len() as i64);
+    }
+}
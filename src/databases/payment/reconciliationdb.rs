@@ -0,0 +1,105 @@
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+
+#[derive(FromRow, Serialize)]
+pub struct ReconciliationRun {
+    pub id: i32,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub checked: i32,
+    pub settled: i32,
+    pub unresolved: i32,
+    pub triggered_by: Option<i32>,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct ReconciliationItem {
+    pub payment_id: i32,
+    pub order_ref: String,
+    pub gateway: String,
+    pub amount_paisa: i64,
+    pub previous_status: String,
+    pub outcome: String,
+    pub new_status: Option<String>,
+    pub detail: Option<String>,
+}
+
+pub async fn start_run(pool: &PgPool, triggered_by: Option<i32>) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar("INSERT INTO reconciliation_runs (triggered_by) VALUES ($1) RETURNING id")
+        .bind(triggered_by)
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn add_item(
+    pool: &PgPool,
+    run_id: i32,
+    payment_id: i32,
+    previous_status: &str,
+    outcome: &str,
+    new_status: Option<&str>,
+    detail: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO reconciliation_items (run_id, payment_id, previous_status, outcome, new_status, detail)
+         VALUES ($1, $2, $3, $4, $5, $6)"
+    )
+    .bind(run_id)
+    .bind(payment_id)
+    .bind(previous_status)
+    .bind(outcome)
+    .bind(new_status)
+    .bind(detail)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn finish_run(
+    pool: &PgPool,
+    run_id: i32,
+    checked: i32,
+    settled: i32,
+    unresolved: i32,
+) -> Result<ReconciliationRun, sqlx::Error> {
+    sqlx::query_as::<_, ReconciliationRun>(
+        "UPDATE reconciliation_runs
+         SET finished_at = NOW(), checked = $2, settled = $3, unresolved = $4
+         WHERE id = $1
+         RETURNING *"
+    )
+    .bind(run_id)
+    .bind(checked)
+    .bind(settled)
+    .bind(unresolved)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn list_runs(pool: &PgPool, limit: i64) -> Result<Vec<ReconciliationRun>, sqlx::Error> {
+    sqlx::query_as::<_, ReconciliationRun>("SELECT * FROM reconciliation_runs ORDER BY started_at DESC LIMIT $1")
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn get_run(pool: &PgPool, run_id: i32) -> Result<Option<ReconciliationRun>, sqlx::Error> {
+    sqlx::query_as::<_, ReconciliationRun>("SELECT * FROM reconciliation_runs WHERE id = $1")
+        .bind(run_id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn get_run_items(pool: &PgPool, run_id: i32) -> Result<Vec<ReconciliationItem>, sqlx::Error> {
+    sqlx::query_as::<_, ReconciliationItem>(
+        "SELECT i.payment_id, p.order_ref, p.gateway, p.amount_paisa, i.previous_status, i.outcome, i.new_status, i.detail
+         FROM reconciliation_items i
+         JOIN payments p ON p.id = i.payment_id
+         WHERE i.run_id = $1
+         ORDER BY p.created_at"
+    )
+    .bind(run_id)
+    .fetch_all(pool)
+    .await
+}
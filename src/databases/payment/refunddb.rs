@@ -0,0 +1,150 @@
+use serde::Serialize;
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::databases::payment::paymentdb::{transition_payment, Payment, PaymentStatus, Transition};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RefundMethod {
+    /// Sent back through the gateway the payment came in on.
+    Gateway,
+    /// Paid back outside the system, e.g. by bank transfer or in cash.
+    Manual,
+}
+
+impl RefundMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RefundMethod::Gateway => "gateway",
+            RefundMethod::Manual => "manual",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<RefundMethod> {
+        match s {
+            "gateway" => Some(RefundMethod::Gateway),
+            "manual" => Some(RefundMethod::Manual),
+            _ => None,
+        }
+    }
+}
+
+pub enum RefundClaim {
+    /// The refund is ours to send; finish with `record_refund` or give it up with
+    /// `release_refund`.
+    Claimed,
+    AlreadyRefunded,
+    /// Someone else's refund for this payment hasn't been recorded yet.
+    InProgress,
+    /// The payment isn't one that can be refunded.
+    Rejected(PaymentStatus),
+}
+
+/// Claims a completed payment for a full refund before any money moves, writing the
+/// refund row as `sending`. Commit the claim before asking the gateway for the money.
+pub async fn claim_refund(
+    conn: &mut PgConnection,
+    payment: &Payment,
+    method: RefundMethod,
+    reference: Option<&str>,
+    return_id: Option<i32>,
+    created_by: i32,
+) -> Result<RefundClaim, sqlx::Error> {
+    let current: String = sqlx::query_scalar("SELECT status FROM payments WHERE id = $1 FOR UPDATE")
+        .bind(payment.id)
+        .fetch_one(&mut *conn)
+        .await?;
+    match PaymentStatus::parse(&current).unwrap_or(PaymentStatus::Failed) {
+        PaymentStatus::Completed => {}
+        PaymentStatus::Refunded => return Ok(RefundClaim::AlreadyRefunded),
+        other => return Ok(RefundClaim::Rejected(other)),
+    }
+
+    let claimed = sqlx::query(
+        "INSERT INTO refunds (payment_id, return_id, amount_paisa, method, reference, created_by, status)
+         VALUES ($1, $2, $3, $4, $5, $6, 'sending')
+         ON CONFLICT (payment_id) DO NOTHING"
+    )
+    .bind(payment.id)
+    .bind(return_id)
+    .bind(payment.amount_paisa)
+    .bind(method.as_str())
+    .bind(reference)
+    .bind(created_by)
+    .execute(conn)
+    .await?
+    .rows_affected() == 1;
+
+    Ok(if claimed { RefundClaim::Claimed } else { RefundClaim::InProgress })
+}
+
+/// Gives up a claim whose money never left, e.g. because the gateway turned it down.
+pub async fn release_refund(conn: &mut PgConnection, payment_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM refunds WHERE payment_id = $1 AND status = 'sending'")
+        .bind(payment_id)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Marks a claimed payment refunded in full once the money has actually gone back. The
+/// claim is settled as `sent` unless the payment has moved somewhere a refund can't
+/// follow, in which case it stays `sending` until an admin resolves it as stuck.
+pub async fn record_refund(
+    conn: &mut PgConnection,
+    payment: &Payment,
+    method: RefundMethod,
+    created_by: i32,
+) -> Result<Transition, sqlx::Error> {
+    let reason = format!("{} refund by admin {}", method.as_str(), created_by);
+    let transition = transition_payment(&mut *conn, payment.id, PaymentStatus::Refunded, None, Some(&reason)).await?;
+
+    if matches!(transition, Transition::Applied | Transition::Unchanged) {
+        sqlx::query("UPDATE refunds SET status = 'sent' WHERE payment_id = $1 AND status = 'sending'")
+            .bind(payment.id)
+            .execute(conn)
+            .await?;
+    }
+
+    Ok(transition)
+}
+
+// A claim still `sending` after this long has been abandoned: the request that made it
+// died before asking the gateway, or couldn't record what the gateway did.
+pub const STUCK_REFUND_MINUTES: i32 = 15;
+
+#[derive(FromRow, Serialize)]
+pub struct StuckRefund {
+    pub payment_id: i32,
+    pub return_id: Option<i32>,
+    pub order_ref: String,
+    pub gateway: String,
+    pub amount_paisa: i64,
+    pub method: String,
+    pub reference: Option<String>,
+    pub created_by: Option<i32>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+const SELECT_STUCK_REFUNDS: &str =
+    "SELECT r.payment_id, r.return_id, p.order_ref, p.gateway, r.amount_paisa, r.method, r.reference,
+            r.created_by, r.created_at
+     FROM refunds r
+     JOIN payments p ON p.id = r.payment_id
+     WHERE r.status = 'sending' AND r.created_at < NOW() - make_interval(mins => $1)";
+
+pub async fn list_stuck_refunds(pool: &PgPool) -> Result<Vec<StuckRefund>, sqlx::Error> {
+    sqlx::query_as::<_, StuckRefund>(&format!("{} ORDER BY r.created_at", SELECT_STUCK_REFUNDS))
+        .bind(STUCK_REFUND_MINUTES)
+        .fetch_all(pool)
+        .await
+}
+
+/// Locks an abandoned claim on `payment_id` for resolving. `None` if there isn't one,
+/// including a claim that is still young enough to be in flight.
+pub async fn lock_stuck_refund(conn: &mut PgConnection, payment_id: i32) -> Result<Option<StuckRefund>, sqlx::Error> {
+    sqlx::query_as::<_, StuckRefund>(&format!("{} AND r.payment_id = $2 FOR UPDATE OF r", SELECT_STUCK_REFUNDS))
+        .bind(STUCK_REFUND_MINUTES)
+        .bind(payment_id)
+        .fetch_optional(conn)
+        .await
+}
@@ -0,0 +1,4 @@
+pub mod cartdb;
+pub mod orderdb;
+pub mod stockdb;
+pub mod returndb;
@@ -0,0 +1,192 @@
+use num_traits::cast::ToPrimitive;
+use sqlx::types::BigDecimal;
+use sqlx::{FromRow, PgConnection, PgPool};
+
+/// A cart line joined with the product's current price and stock.
+#[derive(FromRow)]
+pub struct CartLine {
+    pub laptop_id: i32,
+    pub display_name: String,
+    pub quantity: i32,
+    pub unit_price: BigDecimal,
+    pub stock: i32,
+}
+
+impl CartLine {
+    pub fn line_total_paisa(&self) -> Option<i64> {
+        to_paisa(&self.unit_price).map(|unit| unit * self.quantity as i64)
+    }
+}
+
+pub enum CartUpdate {
+    Updated,
+    ProductNotFound,
+    InsufficientStock { available: i32 },
+}
+
+/// Converts an NPR amount to paisa, the unit payment gateways charge in.
+pub fn to_paisa(amount: &BigDecimal) -> Option<i64> {
+    (amount * BigDecimal::from(100)).round(0).to_i64()
+}
+
+/// Sums the lines in paisa, or `None` if any price can't be represented.
+pub fn total_paisa(lines: &[CartLine]) -> Option<i64> {
+    lines.iter().try_fold(0i64, |sum, line| sum.checked_add(line.line_total_paisa()?))
+}
+
+pub async fn get_cart(pool: &PgPool, user_id: i32) -> Result<Vec<CartLine>, sqlx::Error> {
+    sqlx::query_as::<_, CartLine>(
+        "SELECT c.laptop_id, l.display_name, c.quantity, l.show_price AS unit_price,
+                COALESCE(l.quantity, 0) AS stock
+         FROM cart_items c
+         JOIN laptop_details l ON l.id = c.laptop_id
+         WHERE c.user_id = $1
+         ORDER BY c.added_at"
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// A single product as a one-line cart, for buying without going through the cart.
+pub async fn single_item(pool: &PgPool, laptop_id: i32, quantity: i32) -> Result<Option<CartLine>, sqlx::Error> {
+    sqlx::query_as::<_, CartLine>(
+        "SELECT id AS laptop_id, display_name, $2 AS quantity, show_price AS unit_price,
+                COALESCE(quantity, 0) AS stock
+         FROM laptop_details
+         WHERE id = $1"
+    )
+    .bind(laptop_id)
+    .bind(quantity)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Adds `quantity` to whatever is already in the cart for this product.
+pub async fn add_to_cart(pool: &PgPool, user_id: i32, laptop_id: i32, quantity: i32) -> Result<CartUpdate, sqlx::Error> {
+    upsert_line(pool, user_id, laptop_id, quantity, "cart_items.quantity::BIGINT + EXCLUDED.quantity").await
+}
+
+pub async fn set_cart_quantity(pool: &PgPool, user_id: i32, laptop_id: i32, quantity: i32) -> Result<CartUpdate, sqlx::Error> {
+    upsert_line(pool, user_id, laptop_id, quantity, "EXCLUDED.quantity").await
+}
+
+// Writes the line as `new_quantity` (an expression over the existing row and `EXCLUDED`)
+// only if the product has that many in stock. It is one statement, so concurrent adds
+// each see the other's increment instead of overwriting it.
+async fn upsert_line(
+    pool: &PgPool,
+    user_id: i32,
+    laptop_id: i32,
+    quantity: i32,
+    new_quantity: &str,
+) -> Result<CartUpdate, sqlx::Error> {
+    let (available, written) = sqlx::query_as::<_, (Option<i32>, Option<i32>)>(&format!(
+        "WITH stock AS (
+             SELECT COALESCE(quantity, 0) AS available FROM laptop_details WHERE id = $2
+         ),
+         line AS (
+             INSERT INTO cart_items (user_id, laptop_id, quantity)
+             SELECT $1, $2, $3 FROM stock WHERE $3 <= available
+             ON CONFLICT (user_id, laptop_id) DO UPDATE SET quantity = {new_quantity}
+             WHERE {new_quantity} <= (SELECT available FROM stock)
+             RETURNING quantity
+         )
+         SELECT (SELECT available FROM stock), (SELECT quantity FROM line)"
+    ))
+    .bind(user_id)
+    .bind(laptop_id)
+    .bind(quantity)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(match (available, written) {
+        (None, _) => CartUpdate::ProductNotFound,
+        (Some(available), None) => CartUpdate::InsufficientStock { available },
+        (Some(_), Some(_)) => CartUpdate::Updated,
+    })
+}
+
+/// Returns false if the product wasn't in the cart.
+pub async fn remove_from_cart(pool: &PgPool, user_id: i32, laptop_id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM cart_items WHERE user_id = $1 AND laptop_id = $2")
+        .bind(user_id)
+        .bind(laptop_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn clear_cart(pool: &PgPool, user_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM cart_items WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Drops the lines a completed payment covered, leaving anything added since.
+pub async fn remove_paid_items(conn: &mut PgConnection, user_id: i32, payment_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "DELETE FROM cart_items
+         WHERE user_id = $1
+           AND laptop_id IN (SELECT laptop_id FROM payment_items WHERE payment_id = $2)"
+    )
+    .bind(user_id)
+    .bind(payment_id)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    async fn cart_quantity(pool: &PgPool, user_id: i32, laptop_id: i32) -> Option<i32> {
+        sqlx::query_scalar("SELECT quantity FROM cart_items WHERE user_id = $1 AND laptop_id = $2")
+            .bind(user_id)
+            .bind(laptop_id)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn concurrent_adds_all_count(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Cart", 10).await;
+
+        let adds = (0..6).map(|_| add_to_cart(&pool, user, laptop, 1));
+        for added in futures::future::join_all(adds).await {
+            assert!(matches!(added.unwrap(), CartUpdate::Updated));
+        }
+        assert_eq!(cart_quantity(&pool, user, laptop).await, Some(6));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn the_cart_never_holds_more_than_the_stock(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Cart", 3).await;
+
+        assert!(matches!(add_to_cart(&pool, user, laptop, 4).await.unwrap(), CartUpdate::InsufficientStock { available: 3 }));
+        assert_eq!(cart_quantity(&pool, user, laptop).await, None);
+
+        assert!(matches!(add_to_cart(&pool, user, laptop, 2).await.unwrap(), CartUpdate::Updated));
+        assert!(matches!(add_to_cart(&pool, user, laptop, 2).await.unwrap(), CartUpdate::InsufficientStock { available: 3 }));
+        assert!(matches!(add_to_cart(&pool, user, laptop, i32::MAX).await.unwrap(), CartUpdate::InsufficientStock { .. }));
+        assert_eq!(cart_quantity(&pool, user, laptop).await, Some(2));
+
+        assert!(matches!(set_cart_quantity(&pool, user, laptop, 4).await.unwrap(), CartUpdate::InsufficientStock { available: 3 }));
+        assert!(matches!(set_cart_quantity(&pool, user, laptop, 3).await.unwrap(), CartUpdate::Updated));
+        assert_eq!(cart_quantity(&pool, user, laptop).await, Some(3));
+
+        assert!(matches!(add_to_cart(&pool, user, 999_999, 1).await.unwrap(), CartUpdate::ProductNotFound));
+    }
+}
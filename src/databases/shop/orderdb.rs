@@ -0,0 +1,372 @@
+use serde::Serialize;
+use sqlx::types::BigDecimal;
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::databases::payment::paymentdb::{transition_payment, PaymentStatus};
+
+/// Fulfilment of a paid order. Placed and packed orders can still be cancelled; a
+/// delivered order can come back as returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    Placed,
+    Packed,
+    Shipped,
+    Delivered,
+    Cancelled,
+    Returned,
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Placed => "placed",
+            OrderStatus::Packed => "packed",
+            OrderStatus::Shipped => "shipped",
+            OrderStatus::Delivered => "delivered",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Returned => "returned",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<OrderStatus> {
+        match s {
+            "placed" => Some(OrderStatus::Placed),
+            "packed" => Some(OrderStatus::Packed),
+            "shipped" => Some(OrderStatus::Shipped),
+            "delivered" => Some(OrderStatus::Delivered),
+            "cancelled" => Some(OrderStatus::Cancelled),
+            "returned" => Some(OrderStatus::Returned),
+            _ => None,
+        }
+    }
+
+    pub fn can_transition_to(&self, next: OrderStatus) -> bool {
+        use OrderStatus::*;
+        match self {
+            Placed => matches!(next, Packed | Cancelled),
+            Packed => matches!(next, Shipped | Cancelled),
+            Shipped => next == Delivered,
+            Delivered => next == Returned,
+            Cancelled | Returned => false,
+        }
+    }
+}
+
+#[derive(FromRow, Serialize)]
+pub struct Order {
+    pub id: i32,
+    pub user_id: i32,
+    pub payment_id: i32,
+    pub order_ref: String,
+    pub total_paisa: i64,
+    pub status: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(FromRow)]
+pub struct OrderItem {
+    pub laptop_id: i32,
+    pub display_name: String,
+    pub face_image_url: Option<String>,
+    pub quantity: i32,
+    pub unit_price: BigDecimal,
+}
+
+pub enum OrderTransition {
+    Applied(Order),
+    Unchanged,
+    Rejected(OrderStatus),
+    /// Cash on delivery that hasn't been collected yet.
+    AwaitingPayment,
+    /// The order is paid for; cancelling it means refunding the payment.
+    NeedsRefund,
+    NotFound,
+}
+
+/// Turns a completed payment into an order with the same lines. Call it in the
+/// transaction that completes the payment.
+pub async fn create_order_from_payment(conn: &mut PgConnection, payment_id: i32) -> Result<i32, sqlx::Error> {
+    let order_id: i32 = sqlx::query_scalar(
+        "INSERT INTO orders (user_id, payment_id, order_ref, total_paisa)
+         SELECT user_id, id, order_ref, amount_paisa FROM payments WHERE id = $1
+         RETURNING id"
+    )
+    .bind(payment_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO order_items (order_id, laptop_id, quantity, unit_price)
+         SELECT $1, laptop_id, quantity, unit_price FROM payment_items WHERE payment_id = $2"
+    )
+    .bind(order_id)
+    .bind(payment_id)
+    .execute(&mut *conn)
+    .await?;
+
+    log_status(conn, order_id, None, OrderStatus::Placed, None).await?;
+
+    Ok(order_id)
+}
+
+pub async fn list_user_orders(pool: &PgPool, user_id: i32) -> Result<Vec<Order>, sqlx::Error> {
+    sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE user_id = $1 ORDER BY created_at DESC")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+}
+
+/// `None` for another user's order as well as a missing one.
+pub async fn get_user_order(pool: &PgPool, order_id: i32, user_id: i32) -> Result<Option<Order>, sqlx::Error> {
+    sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = $1 AND user_id = $2")
+        .bind(order_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn list_orders(pool: &PgPool, status: Option<OrderStatus>) -> Result<Vec<Order>, sqlx::Error> {
+    sqlx::query_as::<_, Order>(
+        "SELECT * FROM orders WHERE ($1::TEXT IS NULL OR status = $1) ORDER BY created_at DESC"
+    )
+    .bind(status.map(|s| s.as_str()))
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_order_items(pool: &PgPool, order_id: i32) -> Result<Vec<OrderItem>, sqlx::Error> {
+    sqlx::query_as::<_, OrderItem>(
+        "SELECT i.laptop_id, l.display_name, l.face_image_url, i.quantity, i.unit_price
+         FROM order_items i
+         JOIN laptop_details l ON l.id = i.laptop_id
+         WHERE i.order_id = $1
+         ORDER BY l.display_name"
+    )
+    .bind(order_id)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_order(pool: &PgPool, order_id: i32) -> Result<Option<Order>, sqlx::Error> {
+    sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = $1")
+        .bind(order_id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Moves an order along its fulfilment path, recording who did it. Cancelled and
+/// returned orders put their items back in stock. A cash-on-delivery order whose cash
+/// hasn't been collected can't be delivered yet, and cancelling it fails its payment,
+/// which releases the stock. An order whose payment went through is only cancelled by
+/// refunding it (see `cancel_refunded_order`), so the customer is never left without
+/// either the laptop or the money. Locks the order; run related changes in the same
+/// transaction.
+pub async fn transition_order(
+    conn: &mut PgConnection,
+    order_id: i32,
+    to: OrderStatus,
+    changed_by: Option<i32>,
+) -> Result<OrderTransition, sqlx::Error> {
+    let current = sqlx::query_as::<_, (String, String)>(
+        "SELECT o.status, p.status FROM orders o
+         JOIN payments p ON p.id = o.payment_id
+         WHERE o.id = $1
+         FOR UPDATE OF o"
+    )
+    .bind(order_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some((from, payment_status)) = current else {
+        return Ok(OrderTransition::NotFound);
+    };
+    let Some(from) = OrderStatus::parse(&from) else {
+        return Ok(OrderTransition::NotFound);
+    };
+    let payment_status = PaymentStatus::parse(&payment_status);
+    let awaiting_payment = payment_status == Some(PaymentStatus::Pending);
+
+    if from == to {
+        return Ok(OrderTransition::Unchanged);
+    }
+    if !from.can_transition_to(to) {
+        return Ok(OrderTransition::Rejected(from));
+    }
+    if to == OrderStatus::Delivered && awaiting_payment {
+        return Ok(OrderTransition::AwaitingPayment);
+    }
+    if to == OrderStatus::Cancelled && payment_status == Some(PaymentStatus::Completed) {
+        return Ok(OrderTransition::NeedsRefund);
+    }
+
+    let order = set_order_status(&mut *conn, order_id, from, to, changed_by).await?;
+
+    if to == OrderStatus::Cancelled && awaiting_payment {
+        transition_payment(&mut *conn, order.payment_id, PaymentStatus::Failed, None, Some("order cancelled")).await?;
+    } else if matches!(to, OrderStatus::Cancelled | OrderStatus::Returned) {
+        sqlx::query(
+            "UPDATE laptop_details l SET quantity = l.quantity + i.quantity
+             FROM order_items i
+             WHERE i.order_id = $1 AND l.id = i.laptop_id"
+        )
+        .bind(order_id)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(OrderTransition::Applied(order))
+}
+
+/// Cancels the order a payment paid for once the payment has been refunded, if it
+/// hasn't shipped yet, putting its items back in stock. Call it in the transaction that
+/// records the refund. `None` if there is no such order or it is past cancelling.
+pub async fn cancel_refunded_order(
+    conn: &mut PgConnection,
+    payment_id: i32,
+    changed_by: Option<i32>,
+) -> Result<Option<Order>, sqlx::Error> {
+    let order_id = sqlx::query_scalar::<_, i32>("SELECT id FROM orders WHERE payment_id = $1")
+        .bind(payment_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    let Some(order_id) = order_id else {
+        return Ok(None);
+    };
+
+    match transition_order(conn, order_id, OrderStatus::Cancelled, changed_by).await? {
+        OrderTransition::Applied(order) => Ok(Some(order)),
+        _ => Ok(None),
+    }
+}
+
+/// `transition_order` in a transaction of its own.
+pub async fn apply_order_transition(
+    pool: &PgPool,
+    order_id: i32,
+    to: OrderStatus,
+    changed_by: Option<i32>,
+) -> Result<OrderTransition, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let transition = transition_order(&mut tx, order_id, to, changed_by).await?;
+    tx.commit().await?;
+    Ok(transition)
+}
+
+/// Writes a new status and its history entry without consulting the state machine; the
+/// caller holds the order's lock and has decided the move is allowed.
+pub async fn set_order_status(
+    conn: &mut PgConnection,
+    order_id: i32,
+    from: OrderStatus,
+    to: OrderStatus,
+    changed_by: Option<i32>,
+) -> Result<Order, sqlx::Error> {
+    let order = sqlx::query_as::<_, Order>(
+        "UPDATE orders SET status = $2, updated_at = NOW() WHERE id = $1 RETURNING *"
+    )
+    .bind(order_id)
+    .bind(to.as_str())
+    .fetch_one(&mut *conn)
+    .await?;
+
+    log_status(conn, order_id, Some(from), to, changed_by).await?;
+
+    Ok(order)
+}
+
+async fn log_status(
+    conn: &mut PgConnection,
+    order_id: i32,
+    from: Option<OrderStatus>,
+    to: OrderStatus,
+    changed_by: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO order_status_history (order_id, from_status, to_status, changed_by)
+         VALUES ($1, $2, $3, $4)"
+    )
+    .bind(order_id)
+    .bind(from.map(|s| s.as_str()))
+    .bind(to.as_str())
+    .bind(changed_by)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::databases::payment::coddb::COD_GATEWAY;
+    use crate::databases::payment::paymentdb::{apply_transition, get_payment};
+    use crate::testutil;
+
+    async fn order_status(pool: &PgPool, order_id: i32) -> String {
+        get_order(pool, order_id).await.unwrap().unwrap().status
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn paid_orders_are_not_cancelled_without_a_refund(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Paid", 3).await;
+
+        for gateway in ["khalti", "esewa"] {
+            let order =
+                testutil::create_order(&pool, user, laptop, gateway, PaymentStatus::Completed, OrderStatus::Packed).await;
+
+            let transition = apply_order_transition(&pool, order.id, OrderStatus::Cancelled, None).await.unwrap();
+
+            assert!(matches!(transition, OrderTransition::NeedsRefund), "{}", gateway);
+            assert_eq!(order_status(&pool, order.id).await, "packed");
+        }
+        assert_eq!(testutil::laptop_quantity(&pool, laptop).await, 3);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn refunded_orders_are_cancelled_and_restocked(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Refunded", 3).await;
+        let order =
+            testutil::create_order(&pool, user, laptop, "khalti", PaymentStatus::Completed, OrderStatus::Placed).await;
+        apply_transition(&pool, order.payment_id, PaymentStatus::Refunded, None).await.unwrap();
+
+        let mut conn = pool.acquire().await.unwrap();
+        let cancelled = cancel_refunded_order(&mut conn, order.payment_id, None).await.unwrap();
+
+        assert_eq!(cancelled.map(|o| o.id), Some(order.id));
+        assert_eq!(order_status(&pool, order.id).await, "cancelled");
+        assert_eq!(testutil::laptop_quantity(&pool, laptop).await, 4);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn shipped_orders_stay_put_when_refunded(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Shipped", 3).await;
+        let order =
+            testutil::create_order(&pool, user, laptop, "khalti", PaymentStatus::Refunded, OrderStatus::Shipped).await;
+
+        let mut conn = pool.acquire().await.unwrap();
+        assert!(cancel_refunded_order(&mut conn, order.payment_id, None).await.unwrap().is_none());
+        assert_eq!(order_status(&pool, order.id).await, "shipped");
+        assert_eq!(testutil::laptop_quantity(&pool, laptop).await, 3);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn cancelling_cash_on_delivery_fails_the_payment(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Cod", 3).await;
+        let order =
+            testutil::create_order(&pool, user, laptop, COD_GATEWAY, PaymentStatus::Pending, OrderStatus::Placed).await;
+
+        let transition = apply_order_transition(&pool, order.id, OrderStatus::Cancelled, None).await.unwrap();
+
+        assert!(matches!(transition, OrderTransition::Applied(_)));
+        let payment = get_payment(&pool, order.payment_id).await.unwrap().unwrap();
+        assert_eq!(payment.status(), PaymentStatus::Failed);
+    }
+}
@@ -0,0 +1,188 @@
+use serde::Serialize;
+use sqlx::{FromRow, PgConnection, PgPool};
+use crate::databases::shop::orderdb::OrderStatus;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnStatus {
+    Requested,
+    /// Being approved; its refund is on its way out.
+    Refunding,
+    Approved,
+    Rejected,
+}
+
+impl ReturnStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReturnStatus::Requested => "requested",
+            ReturnStatus::Refunding => "refunding",
+            ReturnStatus::Approved => "approved",
+            ReturnStatus::Rejected => "rejected",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<ReturnStatus> {
+        match s {
+            "requested" => Some(ReturnStatus::Requested),
+            "refunding" => Some(ReturnStatus::Refunding),
+            "approved" => Some(ReturnStatus::Approved),
+            "rejected" => Some(ReturnStatus::Rejected),
+            _ => None,
+        }
+    }
+}
+
+#[derive(FromRow, Serialize)]
+pub struct ReturnRequest {
+    pub id: i32,
+    pub order_id: i32,
+    pub order_ref: String,
+    pub user_id: i32,
+    pub reason: String,
+    pub status: String,
+    pub admin_note: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub decided_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub enum ReturnCreate {
+    Created(ReturnRequest),
+    OrderNotFound,
+    NotDelivered(OrderStatus),
+    AlreadyRequested,
+}
+
+const SELECT_RETURNS: &str =
+    "SELECT r.id, r.order_id, o.order_ref, r.user_id, r.reason, r.status, r.admin_note, r.created_at, r.decided_at
+     FROM return_requests r
+     JOIN orders o ON o.id = r.order_id";
+
+/// Opens a return for one of the customer's delivered orders, unless one is already
+/// open, being approved or approved.
+pub async fn create_return_request(
+    pool: &PgPool,
+    order_id: i32,
+    user_id: i32,
+    reason: &str,
+) -> Result<ReturnCreate, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let status = sqlx::query_scalar::<_, String>(
+        "SELECT status FROM orders WHERE id = $1 AND user_id = $2 FOR UPDATE"
+    )
+    .bind(order_id)
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(status) = status.as_deref().and_then(OrderStatus::parse) else {
+        return Ok(ReturnCreate::OrderNotFound);
+    };
+    if status != OrderStatus::Delivered {
+        return Ok(ReturnCreate::NotDelivered(status));
+    }
+
+    let open = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM return_requests WHERE order_id = $1 AND status IN ('requested', 'refunding', 'approved'))"
+    )
+    .bind(order_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if open {
+        return Ok(ReturnCreate::AlreadyRequested);
+    }
+
+    let id: i32 = sqlx::query_scalar(
+        "INSERT INTO return_requests (order_id, user_id, reason) VALUES ($1, $2, $3) RETURNING id"
+    )
+    .bind(order_id)
+    .bind(user_id)
+    .bind(reason)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let request = sqlx::query_as::<_, ReturnRequest>(&format!("{} WHERE r.id = $1", SELECT_RETURNS))
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(ReturnCreate::Created(request))
+}
+
+pub async fn get_return(pool: &PgPool, return_id: i32) -> Result<Option<ReturnRequest>, sqlx::Error> {
+    sqlx::query_as::<_, ReturnRequest>(&format!("{} WHERE r.id = $1", SELECT_RETURNS))
+        .bind(return_id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn list_user_returns(pool: &PgPool, user_id: i32) -> Result<Vec<ReturnRequest>, sqlx::Error> {
+    sqlx::query_as::<_, ReturnRequest>(&format!("{} WHERE r.user_id = $1 ORDER BY r.created_at DESC", SELECT_RETURNS))
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn list_returns(pool: &PgPool, status: Option<ReturnStatus>) -> Result<Vec<ReturnRequest>, sqlx::Error> {
+    sqlx::query_as::<_, ReturnRequest>(&format!(
+        "{} WHERE ($1::TEXT IS NULL OR r.status = $1) ORDER BY r.created_at DESC",
+        SELECT_RETURNS
+    ))
+    .bind(status.map(|s| s.as_str()))
+    .fetch_all(pool)
+    .await
+}
+
+/// Takes an open request for approval, so nobody else can decide it while its refund
+/// goes out. `false` if it isn't open.
+pub async fn claim_return(conn: &mut PgConnection, return_id: i32) -> Result<bool, sqlx::Error> {
+    let claimed = sqlx::query("UPDATE return_requests SET status = 'refunding' WHERE id = $1 AND status = 'requested'")
+        .bind(return_id)
+        .execute(conn)
+        .await?
+        .rows_affected() == 1;
+
+    Ok(claimed)
+}
+
+/// Reopens a claimed request whose refund didn't go through.
+pub async fn release_return(conn: &mut PgConnection, return_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE return_requests SET status = 'requested' WHERE id = $1 AND status = 'refunding'")
+        .bind(return_id)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Approves a request claimed with `claim_return`, or rejects one that is still open.
+/// `false` if it isn't in that state, e.g. someone else decided it first.
+pub async fn decide_return(
+    conn: &mut PgConnection,
+    return_id: i32,
+    decision: ReturnStatus,
+    admin_note: Option<&str>,
+    decided_by: i32,
+) -> Result<bool, sqlx::Error> {
+    let from = match decision {
+        ReturnStatus::Approved => ReturnStatus::Refunding,
+        _ => ReturnStatus::Requested,
+    };
+
+    let decided = sqlx::query(
+        "UPDATE return_requests
+         SET status = $2, admin_note = $3, decided_by = $4, decided_at = NOW()
+         WHERE id = $1 AND status = $5"
+    )
+    .bind(return_id)
+    .bind(decision.as_str())
+    .bind(admin_note)
+    .bind(decided_by)
+    .bind(from.as_str())
+    .execute(conn)
+    .await?
+    .rows_affected() == 1;
+
+    Ok(decided)
+}
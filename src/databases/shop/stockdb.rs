@@ -0,0 +1,214 @@
+use sqlx::{PgConnection, PgPool};
+use crate::databases::shop::cartdb::CartLine;
+
+// Khalti payment links stop working after 60 minutes, so once a reservation is this old
+// the customer can no longer pay for it and the stock can safely go back on sale.
+pub const RESERVATION_TTL_MINUTES: i32 = 60;
+
+pub struct OutOfStock {
+    pub laptop_id: i32,
+    pub display_name: String,
+    pub available: i32,
+}
+
+/// Takes every line's quantity out of stock for `payment_id`, or nothing at all if any
+/// line can't be covered. The conditional decrement means two checkouts can never both
+/// get the last unit. A reservation without a TTL is held until it is explicitly
+/// consumed or released.
+pub async fn reserve_stock(
+    conn: &mut PgConnection,
+    payment_id: i32,
+    lines: &[CartLine],
+    ttl_minutes: Option<i32>,
+) -> Result<Result<(), OutOfStock>, sqlx::Error> {
+    // Every checkout locks laptops in id order, so two carts holding the same laptops
+    // in a different order wait for each other instead of deadlocking.
+    let mut lines: Vec<&CartLine> = lines.iter().collect();
+    lines.sort_by_key(|line| line.laptop_id);
+
+    for line in lines {
+        let reserved = sqlx::query(
+            "UPDATE laptop_details SET quantity = quantity - $2 WHERE id = $1 AND quantity >= $2"
+        )
+        .bind(line.laptop_id)
+        .bind(line.quantity)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() == 1;
+
+        if !reserved {
+            let available = sqlx::query_scalar::<_, i32>(
+                "SELECT COALESCE(quantity, 0) FROM laptop_details WHERE id = $1"
+            )
+            .bind(line.laptop_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .unwrap_or(0);
+
+            return Ok(Err(OutOfStock {
+                laptop_id: line.laptop_id,
+                display_name: line.display_name.clone(),
+                available,
+            }));
+        }
+    }
+
+    sqlx::query(
+        "INSERT INTO stock_reservations (payment_id, expires_at)
+         VALUES ($1, NOW() + make_interval(mins => $2))"
+    )
+    .bind(payment_id)
+    .bind(ttl_minutes)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(Ok(()))
+}
+
+/// Puts a held reservation back into stock. Does nothing if it was already released or
+/// consumed, so it is safe to call more than once.
+pub async fn release_reservation(conn: &mut PgConnection, payment_id: i32) -> Result<(), sqlx::Error> {
+    let released = sqlx::query(
+        "UPDATE stock_reservations SET status = 'released' WHERE payment_id = $1 AND status = 'held'"
+    )
+    .bind(payment_id)
+    .execute(&mut *conn)
+    .await?
+    .rows_affected() == 1;
+
+    if released {
+        sqlx::query(
+            "UPDATE laptop_details l SET quantity = l.quantity + i.quantity
+             FROM payment_items i
+             WHERE i.payment_id = $1 AND l.id = i.laptop_id"
+        )
+        .bind(payment_id)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// The stock was already taken when the reservation was made; this just closes it.
+pub async fn consume_reservation(conn: &mut PgConnection, payment_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE stock_reservations SET status = 'consumed' WHERE payment_id = $1 AND status = 'held'")
+        .bind(payment_id)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Payments whose reservation has run out while still held.
+pub async fn expired_reservations(pool: &PgPool) -> Result<Vec<i32>, sqlx::Error> {
+    sqlx::query_scalar::<_, i32>(
+        "SELECT payment_id FROM stock_reservations WHERE status = 'held' AND expires_at < NOW()"
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use std::time::Duration;
+
+    fn line(laptop_id: i32, quantity: i32) -> CartLine {
+        CartLine {
+            laptop_id,
+            display_name: format!("Laptop {}", laptop_id),
+            quantity,
+            unit_price: 1180.into(),
+            stock: 0,
+        }
+    }
+
+    async fn payment(pool: &PgPool, user_id: i32) -> i32 {
+        sqlx::query_scalar(
+            "INSERT INTO payments (user_id, gateway, order_ref, amount_paisa, status)
+             VALUES ($1, 'khalti', gen_random_uuid()::TEXT, 118000, 'initiated')
+             RETURNING id"
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn is_locked(pool: &PgPool, laptop_id: i32) -> bool {
+        let mut tx = pool.begin().await.unwrap();
+        sqlx::query("SELECT 1 FROM laptop_details WHERE id = $1 FOR UPDATE NOWAIT")
+            .bind(laptop_id)
+            .execute(&mut *tx)
+            .await
+            .is_err()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn short_line_reserves_nothing(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let plenty = testutil::create_laptop(&pool, "Acme", "Plenty", 5).await;
+        let scarce = testutil::create_laptop(&pool, "Acme", "Scarce", 1).await;
+        let payment_id = payment(&pool, user).await;
+
+        let mut tx = pool.begin().await.unwrap();
+        let reserved = reserve_stock(&mut tx, payment_id, &[line(plenty, 2), line(scarce, 2)], Some(60)).await.unwrap();
+        let Err(short) = reserved else { panic!("expected the scarce laptop to run out") };
+        assert_eq!((short.laptop_id, short.available), (scarce, 1));
+        // The caller rolls back, as payment creation does.
+        tx.rollback().await.unwrap();
+
+        assert_eq!(testutil::laptop_quantity(&pool, plenty).await, 5);
+        assert_eq!(testutil::laptop_quantity(&pool, scarce).await, 1);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn laptops_are_locked_in_id_order(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let first = testutil::create_laptop(&pool, "Acme", "First", 5).await;
+        let second = testutil::create_laptop(&pool, "Acme", "Second", 5).await;
+        let payment_id = payment(&pool, user).await;
+
+        // Another checkout is busy with the second laptop.
+        let mut other = pool.begin().await.unwrap();
+        sqlx::query("UPDATE laptop_details SET quantity = quantity - 1 WHERE id = $1")
+            .bind(second)
+            .execute(&mut *other)
+            .await
+            .unwrap();
+
+        // A cart listing the second laptop first must still take the first one's lock
+        // first, and then wait, rather than hold the second and wait for the first.
+        let checkout = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                let mut tx = pool.begin().await.unwrap();
+                let reserved = reserve_stock(&mut tx, payment_id, &[line(second, 1), line(first, 1)], Some(60))
+                    .await
+                    .unwrap();
+                tx.commit().await.unwrap();
+                reserved.is_ok()
+            }
+        });
+
+        let mut first_locked = false;
+        for _ in 0..100 {
+            if is_locked(&pool, first).await {
+                first_locked = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(first_locked, "the first laptop should be locked while waiting for the second");
+        assert!(!checkout.is_finished());
+
+        other.commit().await.unwrap();
+        assert!(checkout.await.unwrap());
+        assert_eq!(testutil::laptop_quantity(&pool, first).await, 4);
+        assert_eq!(testutil::laptop_quantity(&pool, second).await, 3);
+    }
+}
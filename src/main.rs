@@ -1,19 +1,49 @@
+mod config;
 mod routes;
 mod databases;
 mod services;
+#[cfg(test)]
+mod testutil;
+#[cfg(test)]
+#[path = "bin/mock_khalti/mock.rs"]
+mod mock_khalti;
 
 use actix_cors::Cors;
 use actix_web::{App, HttpServer, middleware::Logger, web};
 use local_ip_address::local_ip;
 use sqlx::PgPool;
 use actix_files as fs;
+use config::Config;
+use services::llm::Chatbot;
+use std::time::Duration;
+
+// How often an unreachable chatbot provider is re-probed.
+const CHATBOT_HEALTH_INTERVAL: Duration = Duration::from_secs(60);
+// How often expired stock reservations are released.
+const RESERVATION_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+// How often payments still waiting on their gateway are looked up.
+const RECONCILIATION_INTERVAL: Duration = Duration::from_secs(5 * 60);
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let host = "0.0.0.0";
-    let port = 8080;
+    let config = match Config::load() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("migrate") {
+        if let Err(e) = databases::migrate_command(&config, args.get(2).map(String::as_str)).await {
+            eprintln!("❌ Migration failed: {:?}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-    let pool: PgPool = match databases::setup_backend().await {
+    let pool: PgPool = match databases::setup_backend(&config).await {
         Ok(p) => p,
         Err(e) => {
             eprintln!("❌ Backend setup failed: {:?}", e);
@@ -21,19 +51,73 @@ async fn main() -> std::io::Result<()> {
         }
     };
 
+    let chatbot = web::Data::new(Chatbot::from_config(&config.llm).await);
+    if chatbot.provider().is_some() {
+        let chatbot = chatbot.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(CHATBOT_HEALTH_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                chatbot.refresh().await;
+            }
+        });
+    }
+
+    let config = web::Data::new(config);
+    let gateways = web::Data::new(services::gateway::Gateways::from_config(&config));
+
+    {
+        let (pool, config, gateways) = (pool.clone(), config.clone(), gateways.clone());
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(RESERVATION_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                match services::reservations::release_expired_reservations(&pool, &config, &gateways).await {
+                    Ok(0) => {}
+                    Ok(n) => println!("Released stock for {} expired payment(s).", n),
+                    Err(e) => eprintln!("❌ Failed to release expired reservations: {:?}", e),
+                }
+            }
+        });
+    }
+
+    {
+        let (pool, config, gateways) = (pool.clone(), config.clone(), gateways.clone());
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(RECONCILIATION_INTERVAL);
+            loop {
+                interval.tick().await;
+                match services::reconciliation::run_reconciliation(&pool, &config, &gateways, None).await {
+                    Ok(run) if run.settled > 0 || run.unresolved > 0 => println!(
+                        "Reconciliation run {}: {} checked, {} settled, {} need review.",
+                        run.id, run.checked, run.settled, run.unresolved
+                    ),
+                    Ok(_) => {}
+                    Err(e) => eprintln!("❌ Payment reconciliation failed: {:?}", e),
+                }
+            }
+        });
+    }
+
+    let host = config.host.clone();
+    let port = config.port;
+
     println!("Server running on:");
     println!("  -> http://localhost:{}", port);
     if let Ok(local_ip) = local_ip() {
         println!("  -> http://{}:{}", local_ip, port);
     }
 
-    let local_ip = local_ip().unwrap_or_else(|_| "127.0.0.1".parse().unwrap());
-    let frontend_origin = format!("http://{}:5173", local_ip);
+    let frontend_origin = config.cors_origin.clone();
     env_logger::init();
 
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(config.clone())
+            .app_data(chatbot.clone())
+            .app_data(gateways.clone())
             .wrap(Logger::default())
             .wrap(
                 Cors::default()
@@ -46,21 +130,23 @@ async fn main() -> std::io::Result<()> {
             .configure(routes::auth::init)
             .configure(routes::verify::init)
             .configure(routes::login::init)
+            .configure(routes::password::init)
+            .configure(routes::twofactor::init)
             .configure(routes::user::init)
-            .configure(routes::admin::insert::init)
-            .configure(routes::admin::inventory::init)
+            .configure(routes::cart::init)
+            .configure(routes::orders::init)
+            .configure(routes::returns::init)
+            .configure(routes::admin::init)
             .configure(routes::chats::conversation::init)
-            .configure(routes::admin::chat::init)
             .configure(services::toppicks::init)
             .configure(routes::product::init)
             .configure(services::brandpage::init)
             .configure(services::search::init)
             .configure(services::suggestion::init)
-            .configure(routes::payment::khalti::init)
+            .configure(routes::payment::checkout::init)
             .configure(routes::payment::verifypay::init)
-            .configure(routes::admin::dashboard::init)
     })
-    .bind((host, port))?
+    .bind((host.as_str(), port))?
     .run()
     .await
 }
\ No newline at end of file
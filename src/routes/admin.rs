@@ -1,4 +1,303 @@
 pub mod insert;
 pub mod inventory;
 pub mod chat;
-pub mod dashboard;
\ No newline at end of file
+pub mod dashboard;
+pub mod orders;
+pub mod payments;
+pub mod cod;
+pub mod returns;
+pub mod reconciliation;
+
+use actix_web::middleware::from_fn;
+use actix_web::web;
+use crate::services::session::require_admin;
+
+// Every admin-only route is mounted here, behind `require_admin`.
+// New admin handlers must be registered through one of these scopes.
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api/admin")
+            .wrap(from_fn(require_admin))
+            .configure(chat::init)
+            .configure(dashboard::init)
+            .configure(orders::init)
+            .configure(payments::init)
+            .configure(cod::init)
+            .configure(returns::init)
+            .configure(reconciliation::init),
+    );
+    cfg.service(
+        web::scope("/api/inventory")
+            .wrap(from_fn(require_admin))
+            .configure(inventory::init),
+    );
+    cfg.service(
+        web::scope("/api/insertion")
+            .wrap(from_fn(require_admin))
+            .configure(insert::init),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::dev::{Service, ServiceResponse};
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::{test, App};
+    use sqlx::PgPool;
+    use crate::databases::payment::coddb::COD_GATEWAY;
+    use crate::databases::payment::paymentdb::PaymentStatus;
+    use crate::databases::shop::orderdb::OrderStatus;
+    use crate::services::gateway::Gateways;
+    use crate::testutil;
+
+    const BOUNDARY: &str = "epasal-test-boundary";
+
+    struct Call {
+        method: Method,
+        path: String,
+        body: Body,
+    }
+
+    enum Body {
+        Empty,
+        Json(serde_json::Value),
+        /// A laptop for `/api/insertion`, as the admin form sends it.
+        Laptop,
+    }
+
+    fn call(method: Method, path: String, body: Body) -> Call {
+        Call { method, path, body }
+    }
+
+    fn laptop_form() -> Vec<u8> {
+        let form = serde_json::json!({
+            "brand_name": "Testbrand", "display_name": "Testbrand Route 14", "product_authetication": "Original",
+            "model_name": "Route 14", "model_year": 2024, "product_type": "Laptop", "suitable_for": "Office",
+            "color": "Grey", "ram": 8, "ram_type": "DDR4", "processor": "Core i5", "processor_series": "i5",
+            "processor_generation": "12th", "storage": 512, "storage_type": "SSD", "warranty": "1 year",
+            "graphic": "Integrated", "graphic_ram": 0, "display": "14 inch", "display_type": "IPS",
+            "battery": "50Wh", "power_supply": "65W", "touchscreen": false, "cost_price": 50000.0, "quantity": 1
+        });
+        format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"form\"\r\n\r\n{form}\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"faceImage\"; filename=\"face.jpg\"\r\n\
+             Content-Type: image/jpeg\r\n\r\nnot really a jpeg\r\n--{b}--\r\n",
+            b = BOUNDARY,
+            form = form
+        )
+        .into_bytes()
+    }
+
+    fn request(call: &Call, token: Option<&str>) -> test::TestRequest {
+        let mut req = test::TestRequest::default().method(call.method.clone()).uri(&call.path);
+        if let Some(token) = token {
+            req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+        }
+        match &call.body {
+            Body::Empty => req,
+            Body::Json(json) => req.set_json(json),
+            Body::Laptop => req
+                .insert_header(("Content-Type", format!("multipart/form-data; boundary={}", BOUNDARY)))
+                .set_payload(laptop_form()),
+        }
+    }
+
+    /// The response status, including for requests the middleware turns away with an error.
+    async fn status<S, R, B>(app: &S, req: R) -> StatusCode
+    where
+        S: Service<R, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    {
+        match test::try_call_service(app, req).await {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        }
+    }
+
+    /// One call for every route `init` mounts, each with what it needs to succeed.
+    async fn every_admin_route(pool: &PgPool, customer: i32) -> Vec<Call> {
+        let laptop = testutil::create_laptop(pool, "Testbrand", "Stock 15", 5).await;
+        let cod_order =
+            testutil::create_order(pool, customer, laptop, COD_GATEWAY, PaymentStatus::Pending, OrderStatus::Shipped).await;
+        let placed =
+            testutil::create_order(pool, customer, laptop, "khalti", PaymentStatus::Completed, OrderStatus::Placed).await;
+        let refundable =
+            testutil::create_order(pool, customer, laptop, "khalti", PaymentStatus::Completed, OrderStatus::Placed).await;
+        let stuck =
+            testutil::create_order(pool, customer, laptop, "khalti", PaymentStatus::Completed, OrderStatus::Placed).await;
+        sqlx::query(
+            "INSERT INTO refunds (payment_id, amount_paisa, method, status, created_at)
+             VALUES ($1, 118000, 'gateway', 'sending', NOW() - INTERVAL '1 hour')",
+        )
+        .bind(stuck.payment_id)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let mut returns = Vec::new();
+        for _ in 0..2 {
+            let delivered =
+                testutil::create_order(pool, customer, laptop, "khalti", PaymentStatus::Completed, OrderStatus::Delivered)
+                    .await;
+            let return_id: i32 = sqlx::query_scalar(
+                "INSERT INTO return_requests (order_id, user_id, reason) VALUES ($1, $2, 'Broken') RETURNING id",
+            )
+            .bind(delivered.id)
+            .bind(customer)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+            returns.push(return_id);
+        }
+
+        let run_id: i32 = sqlx::query_scalar("INSERT INTO reconciliation_runs DEFAULT VALUES RETURNING id")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+
+        let manual_refund = serde_json::json!({ "method": "manual", "reference": "BANK-1" });
+
+        vec![
+            call(Method::GET, "/api/admin/users".into(), Body::Empty),
+            call(Method::GET, format!("/api/admin/chats/{}", customer), Body::Empty),
+            call(
+                Method::POST,
+                format!("/api/admin/bot_status/{}", customer),
+                Body::Json(serde_json::json!({ "bot_enabled": false })),
+            ),
+            call(
+                Method::POST,
+                format!("/api/admin/send_message/{}", customer),
+                Body::Json(serde_json::json!({
+                    "user_id": customer.to_string(),
+                    "content": "Hello",
+                    "sender": "admin",
+                    "receiver": "user",
+                    "timestamp": "2026-01-01T00:00:00Z"
+                })),
+            ),
+            call(Method::GET, "/api/admin/dashboard".into(), Body::Empty),
+            call(Method::GET, "/api/admin/orders".into(), Body::Empty),
+            call(
+                Method::POST,
+                format!("/api/admin/orders/{}/status", placed.id),
+                Body::Json(serde_json::json!({ "status": "packed" })),
+            ),
+            call(
+                Method::POST,
+                format!("/api/admin/payments/{}/refund", refundable.payment_id),
+                Body::Json(manual_refund.clone()),
+            ),
+            call(Method::GET, "/api/admin/refunds/stuck".into(), Body::Empty),
+            call(
+                Method::POST,
+                format!("/api/admin/refunds/{}/resolve", stuck.payment_id),
+                Body::Json(serde_json::json!({ "sent": false })),
+            ),
+            call(
+                Method::POST,
+                format!("/api/admin/orders/{}/cod", cod_order.id),
+                Body::Json(serde_json::json!({ "outcome": "collected" })),
+            ),
+            call(Method::GET, format!("/api/admin/users/{}/cod-limit", customer), Body::Empty),
+            call(
+                Method::PUT,
+                format!("/api/admin/users/{}/cod-limit", customer),
+                Body::Json(serde_json::json!({ "limit_paisa": 100000 })),
+            ),
+            call(Method::GET, "/api/admin/returns".into(), Body::Empty),
+            call(
+                Method::POST,
+                format!("/api/admin/returns/{}/approve", returns[0]),
+                Body::Json(manual_refund),
+            ),
+            call(
+                Method::POST,
+                format!("/api/admin/returns/{}/reject", returns[1]),
+                Body::Json(serde_json::json!({ "note": "Used" })),
+            ),
+            call(Method::GET, "/api/admin/reconciliation".into(), Body::Empty),
+            call(Method::GET, format!("/api/admin/reconciliation/{}", run_id), Body::Empty),
+            call(Method::POST, "/api/admin/reconciliation/run".into(), Body::Empty),
+            call(Method::GET, "/api/inventory".into(), Body::Empty),
+            call(
+                Method::PATCH,
+                format!("/api/inventory/{}/cost_price", laptop),
+                Body::Json(serde_json::json!({ "cost_price": 1200 })),
+            ),
+            call(Method::POST, "/api/insertion".into(), Body::Laptop),
+        ]
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn admin_routes_need_an_admin_session(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let config = testutil::test_config();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Gateways::from_config(&config)))
+                .app_data(web::Data::new(config))
+                .configure(init),
+        )
+        .await;
+
+        let customer = testutil::create_user(&pool, "user").await;
+        let admin = testutil::create_user(&pool, "admin").await;
+        let customer_token = testutil::login(&pool, customer).await;
+        let admin_token = testutil::login(&pool, admin).await;
+
+        for call in every_admin_route(&pool, customer).await {
+            let route = format!("{} {}", call.method, call.path);
+
+            assert_eq!(status(&app, request(&call, None).to_request()).await, StatusCode::UNAUTHORIZED, "{} without a session", route);
+            assert_eq!(
+                status(&app, request(&call, Some("not-a-session")).to_request()).await,
+                StatusCode::UNAUTHORIZED,
+                "{} with an unknown session",
+                route
+            );
+            assert_eq!(
+                status(&app, request(&call, Some(&customer_token)).to_request()).await,
+                StatusCode::FORBIDDEN,
+                "{} as a customer",
+                route
+            );
+            assert_eq!(status(&app, request(&call, Some(&admin_token)).to_request()).await, StatusCode::OK, "{} as an admin", route);
+        }
+
+        // The insertion call saved its face image; don't leave it in the working tree.
+        let images: Vec<String> =
+            sqlx::query_scalar("SELECT face_image_url FROM laptop_details WHERE face_image_url IS NOT NULL")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        for image in images {
+            std::fs::remove_file(image).ok();
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn required_two_factor_blocks_unenrolled_admins(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let mut config = testutil::test_config();
+        config.admin.require_2fa = true;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(config))
+                .configure(init),
+        )
+        .await;
+
+        let unenrolled = testutil::create_user(&pool, "admin").await;
+        let enrolled = testutil::create_user(&pool, "admin").await;
+        testutil::enroll_totp(&pool, enrolled).await;
+        let unenrolled_token = testutil::login(&pool, unenrolled).await;
+        let enrolled_token = testutil::login(&pool, enrolled).await;
+
+        let dashboard = call(Method::GET, "/api/admin/dashboard".into(), Body::Empty);
+        assert_eq!(status(&app, request(&dashboard, Some(&unenrolled_token)).to_request()).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(&app, request(&dashboard, Some(&enrolled_token)).to_request()).await, StatusCode::OK);
+    }
+}
@@ -35,7 +35,7 @@ pub struct SendMessagePayload {
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
-#[get("/api/admin/users")]
+#[get("/users")]
 async fn get_users(db: web::Data<PgPool>) -> impl Responder {
     let query = r#"
         SELECT 
@@ -64,7 +64,7 @@ async fn get_users(db: web::Data<PgPool>) -> impl Responder {
 }
 
 
-#[get("/api/admin/chats/{user_id}")]
+#[get("/chats/{user_id}")]
 async fn get_messages(path: web::Path<String>, db: web::Data<PgPool>) -> impl Responder {
     let user_id = path.into_inner();
 
@@ -108,7 +108,7 @@ struct BotStatusPayload {
     bot_enabled: bool,
 }
 
-#[post("/api/admin/bot_status/{user_id}")]
+#[post("/bot_status/{user_id}")]
 async fn update_bot_status(
     path: web::Path<String>,
     db: web::Data<PgPool>,
@@ -139,7 +139,7 @@ async fn update_bot_status(
     }
 }
 
-#[post("/api/admin/send_message/{id}")]
+#[post("/send_message/{id}")]
 pub async fn send_message(
     path: web::Path<String>,
     payload: web::Json<SendMessagePayload>,
@@ -0,0 +1,286 @@
+use actix_web::{get, post, put, web, HttpResponse, Responder};
+use sqlx::PgPool;
+use serde::Deserialize;
+use crate::config::Config;
+use crate::databases::payment::coddb::{get_cod_limit, outstanding_cod_paisa, set_cod_limit, COD_GATEWAY};
+use crate::databases::payment::paymentdb::{get_payment, transition_payment, PaymentStatus, Transition};
+use crate::databases::shop::orderdb::{get_order, get_order_items, set_order_status, Order, OrderStatus};
+use crate::routes::orders::OrderResponse;
+use crate::routes::payment::verifypay::record_laptop_sale;
+use crate::services::email::send_order_status_email;
+use crate::services::session::AuthUser;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CodOutcome {
+    Collected,
+    Failed,
+}
+
+#[derive(Deserialize)]
+struct CodOutcomePayload {
+    outcome: CodOutcome,
+}
+
+/// How recording an outcome went, short of a database error.
+enum Settlement {
+    Recorded(Transition, Option<Order>),
+    /// The order is in a status the outcome can't move it out of.
+    OrderRejected(OrderStatus),
+}
+
+#[derive(Deserialize)]
+struct CodLimitPayload {
+    /// `null` puts the customer back on the default limit.
+    limit_paisa: Option<i64>,
+}
+
+/// Records how a cash-on-delivery order ended. Collected completes the payment, records
+/// the sale and marks the order delivered; failed fails the payment, which puts the
+/// stock back, and cancels the order.
+#[post("/orders/{id}/cod")]
+async fn record_cod_outcome(
+    admin: AuthUser,
+    path: web::Path<i32>,
+    payload: web::Json<CodOutcomePayload>,
+    db: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let order = match get_order(db.get_ref(), path.into_inner()).await {
+        Ok(Some(order)) => order,
+        Ok(None) => return HttpResponse::NotFound().body("Order not found"),
+        Err(e) => {
+            eprintln!("Error fetching order: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to update order");
+        }
+    };
+
+    match get_payment(db.get_ref(), order.payment_id).await {
+        Ok(Some(payment)) if payment.gateway == COD_GATEWAY => {}
+        Ok(_) => return HttpResponse::Conflict().body("Not a cash on delivery order"),
+        Err(e) => {
+            eprintln!("Error fetching payment: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to update order");
+        }
+    }
+
+    let (payment_status, order_status, reason) = match payload.outcome {
+        CodOutcome::Collected => (PaymentStatus::Completed, OrderStatus::Delivered, "cash collected"),
+        CodOutcome::Failed => (PaymentStatus::Failed, OrderStatus::Cancelled, "cash on delivery failed"),
+    };
+    let reason = format!("{} (admin {})", reason, admin.id);
+
+    let settle = async {
+        let mut tx = db.begin().await?;
+
+        let from = sqlx::query_scalar::<_, String>("SELECT status FROM orders WHERE id = $1 FOR UPDATE")
+            .bind(order.id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let from = OrderStatus::parse(&from)
+            .ok_or_else(|| anyhow::anyhow!("order {} has unknown status '{}'", order.id, from))?;
+        // Repeating an outcome that already went through is left to the payment to answer.
+        if from != order_status && !from.can_transition_to(order_status) {
+            return Ok(Settlement::OrderRejected(from));
+        }
+
+        let transition = transition_payment(&mut tx, order.payment_id, payment_status, None, Some(&reason)).await?;
+        let mut updated = None;
+        if matches!(transition, Transition::Applied) {
+            if payment_status == PaymentStatus::Completed {
+                record_laptop_sale(&mut tx, order.payment_id, order.id).await?;
+            }
+            updated = Some(set_order_status(&mut tx, order.id, from, order_status, Some(admin.id)).await?);
+        }
+
+        tx.commit().await?;
+        anyhow::Ok(Settlement::Recorded(transition, updated))
+    };
+
+    let updated = match settle.await {
+        Ok(Settlement::Recorded(Transition::Applied, Some(updated))) => updated,
+        Ok(Settlement::Recorded(Transition::Rejected(current), _)) => {
+            return HttpResponse::Conflict().body(format!("Payment is already {}", current.as_str()));
+        }
+        Ok(Settlement::Recorded(..)) => {
+            return HttpResponse::Ok().body(format!("Payment is already {}", payment_status.as_str()));
+        }
+        Ok(Settlement::OrderRejected(current)) => {
+            return HttpResponse::Conflict().body(format!(
+                "Cannot move an order from {} to {}",
+                current.as_str(),
+                order_status.as_str()
+            ));
+        }
+        Err(e) => {
+            eprintln!("Error recording COD outcome: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to update order");
+        }
+    };
+
+    let email = sqlx::query_scalar::<_, String>("SELECT email FROM logininfo WHERE id = $1")
+        .bind(updated.user_id)
+        .fetch_one(db.get_ref())
+        .await;
+    match email {
+        Ok(email) => {
+            if let Err(e) = send_order_status_email(&config, &email, &updated.order_ref, order_status).await {
+                eprintln!("Failed to send order status email: {}", e);
+            }
+        }
+        Err(e) => eprintln!("Failed to look up order owner: {}", e),
+    }
+
+    let items = get_order_items(db.get_ref(), updated.id).await.ok();
+    HttpResponse::Ok().json(OrderResponse::new(updated, items))
+}
+
+#[get("/users/{id}/cod-limit")]
+async fn get_user_cod_limit(
+    path: web::Path<i32>,
+    db: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+    let limit = get_cod_limit(db.get_ref(), user_id, config.cod.default_limit_paisa).await;
+    let outstanding = outstanding_cod_paisa(db.get_ref(), user_id).await;
+
+    match (limit, outstanding) {
+        (Ok(limit_paisa), Ok(outstanding_paisa)) => HttpResponse::Ok().json(serde_json::json!({
+            "user_id": user_id,
+            "limit_paisa": limit_paisa,
+            "default_limit_paisa": config.cod.default_limit_paisa,
+            "outstanding_paisa": outstanding_paisa
+        })),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Error fetching COD limit: {}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch COD limit")
+        }
+    }
+}
+
+#[put("/users/{id}/cod-limit")]
+async fn update_user_cod_limit(
+    admin: AuthUser,
+    path: web::Path<i32>,
+    payload: web::Json<CodLimitPayload>,
+    db: web::Data<PgPool>,
+) -> impl Responder {
+    if payload.limit_paisa.is_some_and(|limit| limit < 0) {
+        return HttpResponse::BadRequest().body("Limit cannot be negative");
+    }
+
+    match set_cod_limit(db.get_ref(), path.into_inner(), payload.limit_paisa, admin.id).await {
+        Ok(()) => HttpResponse::Ok().body("COD limit updated"),
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => {
+            HttpResponse::NotFound().body("User not found")
+        }
+        Err(e) => {
+            eprintln!("Error updating COD limit: {}", e);
+            HttpResponse::InternalServerError().body("Failed to update COD limit")
+        }
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(record_cod_outcome);
+    cfg.service(get_user_cod_limit);
+    cfg.service(update_user_cod_limit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use crate::testutil;
+
+    struct Case {
+        order: OrderStatus,
+        outcome: &'static str,
+        status: StatusCode,
+        payment_after: PaymentStatus,
+        order_after: OrderStatus,
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn outcomes_follow_the_order_state_machine(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(testutil::test_config()))
+                .configure(init),
+        )
+        .await;
+        let admin = testutil::create_user(&pool, "admin").await;
+        let token = testutil::login(&pool, admin).await;
+        let customer = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Cod", 3).await;
+
+        use OrderStatus::*;
+        let cases = [
+            Case { order: Shipped, outcome: "collected", status: StatusCode::OK, payment_after: PaymentStatus::Completed, order_after: Delivered },
+            Case { order: Placed, outcome: "collected", status: StatusCode::CONFLICT, payment_after: PaymentStatus::Pending, order_after: Placed },
+            Case { order: Packed, outcome: "collected", status: StatusCode::CONFLICT, payment_after: PaymentStatus::Pending, order_after: Packed },
+            Case { order: Placed, outcome: "failed", status: StatusCode::OK, payment_after: PaymentStatus::Failed, order_after: Cancelled },
+            Case { order: Packed, outcome: "failed", status: StatusCode::OK, payment_after: PaymentStatus::Failed, order_after: Cancelled },
+            Case { order: Shipped, outcome: "failed", status: StatusCode::CONFLICT, payment_after: PaymentStatus::Pending, order_after: Shipped },
+            Case { order: Returned, outcome: "collected", status: StatusCode::CONFLICT, payment_after: PaymentStatus::Pending, order_after: Returned },
+        ];
+
+        for case in cases {
+            let order =
+                testutil::create_order(&pool, customer, laptop, COD_GATEWAY, PaymentStatus::Pending, case.order).await;
+            let req = test::TestRequest::post()
+                .uri(&format!("/orders/{}/cod", order.id))
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .set_json(serde_json::json!({ "outcome": case.outcome }))
+                .to_request();
+            let label = format!("{} from {}", case.outcome, case.order.as_str());
+
+            assert_eq!(test::call_service(&app, req).await.status(), case.status, "{}", label);
+            let payment = get_payment(&pool, order.payment_id).await.unwrap().unwrap();
+            assert_eq!(payment.status(), case.payment_after, "{}", label);
+            let order = get_order(&pool, order.id).await.unwrap().unwrap();
+            assert_eq!(order.status, case.order_after.as_str(), "{}", label);
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn unreadable_order_status_is_a_server_error(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(testutil::test_config()))
+                .configure(init),
+        )
+        .await;
+        let admin = testutil::create_user(&pool, "admin").await;
+        let token = testutil::login(&pool, admin).await;
+        let customer = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Cod", 3).await;
+        let order = testutil::create_order(
+            &pool,
+            customer,
+            laptop,
+            COD_GATEWAY,
+            PaymentStatus::Pending,
+            OrderStatus::Shipped,
+        )
+        .await;
+        sqlx::query("ALTER TABLE orders DROP CONSTRAINT orders_status_check").execute(&pool).await.unwrap();
+        sqlx::query("UPDATE orders SET status = 'lost' WHERE id = $1").bind(order.id).execute(&pool).await.unwrap();
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/orders/{}/cod", order.id))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(serde_json::json!({ "outcome": "collected" }))
+            .to_request();
+
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let payment = get_payment(&pool, order.payment_id).await.unwrap().unwrap();
+        assert_eq!(payment.status(), PaymentStatus::Pending);
+    }
+}
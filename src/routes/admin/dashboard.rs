@@ -13,7 +13,7 @@ struct LaptopSummary {
     total_revenue: f64,
 }
 
-#[get("/api/admin/dashboard")]
+#[get("/dashboard")]
 async fn get_dashboard_data(db_pool: web::Data<PgPool>) -> impl Responder {
     let rows = sqlx::query!(
     r#"
@@ -28,6 +28,12 @@ async fn get_dashboard_data(db_pool: web::Data<PgPool>) -> impl Responder {
         laptop_details ld
     LEFT JOIN
         laptops_sold ls ON ld.id = ls.laptop_id
+        -- Refunded sales no longer count towards quantity or revenue.
+        AND NOT EXISTS (
+            SELECT 1 FROM orders o
+            JOIN refunds rf ON rf.payment_id = o.payment_id AND rf.status = 'sent'
+            WHERE o.id = ls.order_id
+        )
     GROUP BY 
         ld.id, ld.brand_name, ld.model_name, ld.face_image_url
     HAVING 
@@ -35,7 +35,7 @@ pub struct LaptopForm {
     pub quantity: i32,
 }
 
-#[post("/api/insertion")]
+#[post("")]
 pub async fn insert_laptop(pool: web::Data<PgPool>, mut multipart: Multipart) -> impl Responder {
     let mut form_data: Option<LaptopForm> = None;
     let mut face_image: Option<Vec<u8>> = None;
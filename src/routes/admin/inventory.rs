@@ -15,7 +15,7 @@ struct InventoryItem {
     cost_price: f32,
 }
 
-#[get("/api/inventory")]
+#[get("")]
 async fn get_inventory(pool: web::Data<PgPool>) -> impl Responder {
     let result = sqlx::query(
         r#"
@@ -84,7 +84,7 @@ struct MessageResponse<'a> {
     message: &'a str,
 }
 
-#[patch("/api/inventory/{id}/cost_price")]
+#[patch("/{id}/cost_price")]
 async fn update_cost_price(
     path: web::Path<i32>,
     json: web::Json<UpdateCostPrice>,
@@ -0,0 +1,100 @@
+use actix_web::{get, post, web, HttpResponse, Responder};
+use sqlx::PgPool;
+use serde::Deserialize;
+use crate::config::Config;
+use crate::databases::shop::orderdb::{apply_order_transition, get_order_items, list_orders, OrderStatus, OrderTransition};
+use crate::routes::orders::OrderResponse;
+use crate::services::email::send_order_status_email;
+use crate::services::session::AuthUser;
+
+#[derive(Deserialize)]
+struct OrderListQuery {
+    status: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UpdateStatusPayload {
+    status: String,
+}
+
+#[get("/orders")]
+async fn get_orders(
+    query: web::Query<OrderListQuery>,
+    db: web::Data<PgPool>,
+) -> impl Responder {
+    let status = match query.status.as_deref().map(OrderStatus::parse) {
+        Some(None) => return HttpResponse::BadRequest().body("Unknown order status"),
+        Some(status) => status,
+        None => None,
+    };
+
+    match list_orders(db.get_ref(), status).await {
+        Ok(orders) => {
+            let orders: Vec<OrderResponse> = orders.into_iter().map(|o| OrderResponse::new(o, None)).collect();
+            HttpResponse::Ok().json(orders)
+        }
+        Err(e) => {
+            eprintln!("Error fetching orders: {}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch orders")
+        }
+    }
+}
+
+#[post("/orders/{id}/status")]
+async fn update_order_status(
+    admin: AuthUser,
+    path: web::Path<i32>,
+    payload: web::Json<UpdateStatusPayload>,
+    db: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let Some(next) = OrderStatus::parse(&payload.status) else {
+        return HttpResponse::BadRequest().body("Unknown order status");
+    };
+
+    let order = match apply_order_transition(db.get_ref(), path.into_inner(), next, Some(admin.id)).await {
+        Ok(OrderTransition::Applied(order)) => order,
+        Ok(OrderTransition::Unchanged) => {
+            return HttpResponse::Ok().body(format!("Order is already {}", next.as_str()));
+        }
+        Ok(OrderTransition::Rejected(current)) => {
+            return HttpResponse::Conflict().body(format!(
+                "Cannot move an order from {} to {}",
+                current.as_str(),
+                next.as_str()
+            ));
+        }
+        Ok(OrderTransition::AwaitingPayment) => {
+            return HttpResponse::Conflict().body("Record the cash as collected to deliver this order");
+        }
+        Ok(OrderTransition::NeedsRefund) => {
+            return HttpResponse::Conflict().body("This order is paid for; refund its payment to cancel it");
+        }
+        Ok(OrderTransition::NotFound) => return HttpResponse::NotFound().body("Order not found"),
+        Err(e) => {
+            eprintln!("Error updating order status: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to update order status");
+        }
+    };
+
+    let email = sqlx::query_scalar::<_, String>("SELECT email FROM logininfo WHERE id = $1")
+        .bind(order.user_id)
+        .fetch_one(db.get_ref())
+        .await;
+    match email {
+        Ok(email) => {
+            if let Err(e) = send_order_status_email(&config, &email, &order.order_ref, next).await {
+                eprintln!("Failed to send order status email: {}", e);
+            }
+        }
+        Err(e) => eprintln!("Failed to look up order owner: {}", e),
+    }
+
+    let items = get_order_items(db.get_ref(), order.id).await.ok();
+    HttpResponse::Ok().json(OrderResponse::new(order, items))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_orders);
+    cfg.service(update_order_status);
+}
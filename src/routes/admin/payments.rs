@@ -0,0 +1,491 @@
+use actix_web::{get, post, web, HttpResponse, Responder};
+use sqlx::PgPool;
+use serde::Deserialize;
+use crate::config::Config;
+use crate::databases::payment::paymentdb::{get_payment, Payment, PaymentStatus, Transition};
+use crate::databases::payment::refunddb::{
+    claim_refund, list_stuck_refunds, lock_stuck_refund, record_refund, release_refund, RefundClaim, RefundMethod,
+};
+use crate::databases::shop::orderdb::{cancel_refunded_order, transition_order, OrderStatus, OrderTransition};
+use crate::databases::shop::returndb::{decide_return, release_return, ReturnStatus};
+use crate::routes::payment::verifypay::notify_payment_status;
+use crate::services::gateway::Gateways;
+use crate::services::session::AuthUser;
+
+/// How an admin is giving the money back. `reference` identifies a manual refund, such
+/// as a bank transfer id.
+#[derive(Deserialize)]
+pub struct RefundPayload {
+    pub method: String,
+    pub reference: Option<String>,
+}
+
+impl Default for RefundPayload {
+    fn default() -> Self {
+        RefundPayload { method: RefundMethod::Gateway.as_str().to_string(), reference: None }
+    }
+}
+
+/// For a gateway refund, asks the payment's gateway to send the money back. Manual
+/// refunds have already happened by the time an admin records them.
+pub async fn send_refund(gateways: &Gateways, payment: &Payment, method: RefundMethod) -> Result<(), HttpResponse> {
+    if method == RefundMethod::Manual {
+        return Ok(());
+    }
+
+    let Some(gateway) = gateways.get(&payment.gateway) else {
+        return Err(HttpResponse::Conflict().body(format!(
+            "{} payments can't be refunded through a gateway; record a manual refund",
+            payment.gateway
+        )));
+    };
+
+    if let Err(e) = gateway.refund(payment, None).await {
+        eprintln!("❌ {} refund failed for payment {}: {:?}", gateway.name(), payment.id, e);
+        return Err(HttpResponse::BadGateway().body(format!("Refund failed: {}", e)));
+    }
+
+    Ok(())
+}
+
+/// Refunds a completed payment in full, through its gateway or as a manual record. The
+/// refund is claimed before the gateway is asked, so it can only be sent once, and the
+/// payment is only marked refunded once the gateway has accepted it. An order
+/// that hasn't shipped yet is cancelled along with it and its items go back in stock;
+/// this is the only way to cancel a paid order.
+#[post("/payments/{id}/refund")]
+async fn refund_payment(
+    admin: AuthUser,
+    path: web::Path<i32>,
+    payload: Option<web::Json<RefundPayload>>,
+    db: web::Data<PgPool>,
+    config: web::Data<Config>,
+    gateways: web::Data<Gateways>,
+) -> impl Responder {
+    let payload = payload.map(|p| p.into_inner()).unwrap_or_default();
+    let Some(method) = RefundMethod::parse(&payload.method) else {
+        return HttpResponse::BadRequest().body("Refund method must be gateway or manual");
+    };
+
+    let payment = match get_payment(db.get_ref(), path.into_inner()).await {
+        Ok(Some(payment)) => payment,
+        Ok(None) => return HttpResponse::NotFound().body("Payment not found"),
+        Err(e) => {
+            eprintln!("Error fetching payment: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to refund payment");
+        }
+    };
+
+    if payment.status() != PaymentStatus::Completed {
+        return HttpResponse::Conflict().body(format!("Cannot refund a {} payment", payment.status().as_str()));
+    }
+
+    let claimed = async {
+        let mut tx = db.begin().await?;
+        let claim = claim_refund(&mut tx, &payment, method, payload.reference.as_deref(), None, admin.id).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(claim)
+    };
+    match claimed.await {
+        Ok(RefundClaim::Claimed) => {}
+        Ok(RefundClaim::AlreadyRefunded) => return HttpResponse::Ok().body("Payment is already refunded"),
+        Ok(RefundClaim::InProgress) => {
+            return HttpResponse::Conflict().body("A refund for this payment is already being sent");
+        }
+        Ok(RefundClaim::Rejected(current)) => {
+            return HttpResponse::Conflict().body(format!("Cannot refund a {} payment", current.as_str()));
+        }
+        Err(e) => {
+            eprintln!("Error claiming refund: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to refund payment");
+        }
+    }
+
+    if let Err(response) = send_refund(&gateways, &payment, method).await {
+        let released = async {
+            let mut conn = db.acquire().await?;
+            release_refund(&mut conn, payment.id).await
+        };
+        if let Err(e) = released.await {
+            eprintln!("❌ Could not release the refund claim on payment {}: {}", payment.id, e);
+        }
+        return response;
+    }
+
+    let recorded = async {
+        let mut tx = db.begin().await?;
+        let transition = record_refund(&mut tx, &payment, method, admin.id).await?;
+        if matches!(transition, Transition::Applied) {
+            cancel_refunded_order(&mut tx, payment.id, Some(admin.id)).await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(transition)
+    };
+
+    match recorded.await {
+        Ok(Transition::Applied) => {
+            notify_payment_status(db.get_ref(), &config, payment.user_id, &payment.order_ref, PaymentStatus::Refunded).await;
+            HttpResponse::Ok().body("Payment refunded")
+        }
+        Ok(Transition::Unchanged) => HttpResponse::Ok().body("Payment is already refunded"),
+        Ok(Transition::Rejected(current)) => {
+            eprintln!("❌ Payment {} was refunded but is now {}; resolve it under /api/admin/refunds/stuck", payment.id, current.as_str());
+            HttpResponse::Conflict().body(format!("Cannot refund a {} payment", current.as_str()))
+        }
+        Err(e) => {
+            eprintln!("❌ Payment {} was refunded but not recorded, resolve it under /api/admin/refunds/stuck: {}", payment.id, e);
+            HttpResponse::InternalServerError().body("Refund sent but could not be recorded")
+        }
+    }
+}
+
+/// Whether the money for a stuck refund actually went out, as the gateway's or the bank's
+/// records show.
+#[derive(Deserialize)]
+struct ResolvePayload {
+    sent: bool,
+}
+
+enum Resolution {
+    NotStuck,
+    Released,
+    Recorded,
+    Conflict(String),
+}
+
+/// Refund claims that were never settled, so the money may or may not have gone out.
+#[get("/refunds/stuck")]
+async fn get_stuck_refunds(db: web::Data<PgPool>) -> impl Responder {
+    match list_stuck_refunds(db.get_ref()).await {
+        Ok(refunds) => HttpResponse::Ok().json(refunds),
+        Err(e) => {
+            eprintln!("Error fetching stuck refunds: {}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch stuck refunds")
+        }
+    }
+}
+
+/// Settles an abandoned refund claim once an admin has checked where the money is. If it
+/// went out, the refund is recorded as it would have been: the payment is refunded and its
+/// return approved, or its unshipped order cancelled. If it didn't, the claim is dropped
+/// and any return goes back to `requested`.
+#[post("/refunds/{payment_id}/resolve")]
+async fn resolve_stuck_refund(
+    admin: AuthUser,
+    path: web::Path<i32>,
+    payload: web::Json<ResolvePayload>,
+    db: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let payment = match get_payment(db.get_ref(), path.into_inner()).await {
+        Ok(Some(payment)) => payment,
+        Ok(None) => return HttpResponse::NotFound().body("Payment not found"),
+        Err(e) => {
+            eprintln!("Error fetching payment: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to resolve refund");
+        }
+    };
+
+    let resolved = async {
+        let mut tx = db.begin().await?;
+        let Some(stuck) = lock_stuck_refund(&mut tx, payment.id).await? else {
+            return Ok(Resolution::NotStuck);
+        };
+
+        if !payload.sent {
+            release_refund(&mut tx, payment.id).await?;
+            if let Some(return_id) = stuck.return_id {
+                release_return(&mut tx, return_id).await?;
+            }
+            tx.commit().await?;
+            return Ok(Resolution::Released);
+        }
+
+        let method = RefundMethod::parse(&stuck.method).unwrap_or(RefundMethod::Manual);
+        let transition = record_refund(&mut tx, &payment, method, admin.id).await?;
+        if let Transition::Rejected(current) = transition {
+            return Ok(Resolution::Conflict(format!("Cannot refund a {} payment", current.as_str())));
+        }
+
+        match stuck.return_id {
+            Some(return_id) => {
+                let order_id: i32 = sqlx::query_scalar("SELECT order_id FROM return_requests WHERE id = $1")
+                    .bind(return_id)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                if !decide_return(&mut tx, return_id, ReturnStatus::Approved, None, admin.id).await?
+                    || !matches!(
+                        transition_order(&mut tx, order_id, OrderStatus::Returned, Some(admin.id)).await?,
+                        OrderTransition::Applied(_)
+                    )
+                {
+                    return Ok(Resolution::Conflict("Return or order changed while resolving; please reload".to_string()));
+                }
+            }
+            None if matches!(transition, Transition::Applied) => {
+                cancel_refunded_order(&mut tx, payment.id, Some(admin.id)).await?;
+            }
+            None => {}
+        }
+
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Resolution::Recorded)
+    };
+
+    match resolved.await {
+        Ok(Resolution::NotStuck) => HttpResponse::NotFound().body("No abandoned refund claim for this payment"),
+        Ok(Resolution::Released) => HttpResponse::Ok().body("Refund claim released"),
+        Ok(Resolution::Recorded) => {
+            notify_payment_status(db.get_ref(), &config, payment.user_id, &payment.order_ref, PaymentStatus::Refunded).await;
+            HttpResponse::Ok().body("Refund recorded")
+        }
+        Ok(Resolution::Conflict(message)) => HttpResponse::Conflict().body(message),
+        Err(e) => {
+            eprintln!("Error resolving refund for payment {}: {}", payment.id, e);
+            HttpResponse::InternalServerError().body("Failed to resolve refund")
+        }
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(refund_payment);
+    cfg.service(get_stuck_refunds);
+    cfg.service(resolve_stuck_refund);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use crate::databases::shop::orderdb::get_order;
+    use crate::databases::shop::returndb::{claim_return, create_return_request, get_return, ReturnCreate};
+    use crate::testutil;
+
+    fn refund_request(payment_id: i32, token: &str, body: serde_json::Value) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(&format!("/payments/{}/refund", payment_id))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(body)
+    }
+
+    async fn refund_rows(pool: &PgPool, payment_id: i32) -> Vec<(String, Option<String>)> {
+        sqlx::query_as("SELECT status, reference FROM refunds WHERE payment_id = $1")
+            .bind(payment_id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn refunding_an_unshipped_order_cancels_it(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let config = testutil::test_config();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Gateways::from_config(&config)))
+                .app_data(web::Data::new(config))
+                .configure(init),
+        )
+        .await;
+        let admin = testutil::create_user(&pool, "admin").await;
+        let token = testutil::login(&pool, admin).await;
+        let customer = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Refund", 3).await;
+        let order =
+            testutil::create_order(&pool, customer, laptop, "khalti", PaymentStatus::Completed, OrderStatus::Packed).await;
+
+        let req = refund_request(order.payment_id, &token, serde_json::json!({ "method": "manual", "reference": "BANK-7" }));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+        assert_eq!(refund_rows(&pool, order.payment_id).await, [("sent".to_string(), Some("BANK-7".to_string()))]);
+
+        let payment = get_payment(&pool, order.payment_id).await.unwrap().unwrap();
+        assert_eq!(payment.status(), PaymentStatus::Refunded);
+        assert_eq!(get_order(&pool, order.id).await.unwrap().unwrap().status, "cancelled");
+        assert_eq!(testutil::laptop_quantity(&pool, laptop).await, 4);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn a_refused_gateway_refund_gives_up_its_claim(pool: PgPool) {
+        testutil::setup(&pool).await;
+        // The test config points Khalti at a closed port, so every gateway refund fails.
+        let config = testutil::test_config();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Gateways::from_config(&config)))
+                .app_data(web::Data::new(config))
+                .configure(init),
+        )
+        .await;
+        let admin = testutil::create_user(&pool, "admin").await;
+        let token = testutil::login(&pool, admin).await;
+        let customer = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Refund", 3).await;
+        let order =
+            testutil::create_order(&pool, customer, laptop, "khalti", PaymentStatus::Completed, OrderStatus::Packed).await;
+
+        let req = refund_request(order.payment_id, &token, serde_json::json!({ "method": "gateway" }));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::BAD_GATEWAY);
+        let payment = get_payment(&pool, order.payment_id).await.unwrap().unwrap();
+        assert_eq!(payment.status(), PaymentStatus::Completed);
+        assert_eq!(get_order(&pool, order.id).await.unwrap().unwrap().status, "packed");
+        assert!(refund_rows(&pool, order.payment_id).await.is_empty());
+
+        // Nothing is left holding the payment, so the refund can be made another way.
+        let req = refund_request(order.payment_id, &token, serde_json::json!({ "method": "manual", "reference": "BANK-8" }));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+        let payment = get_payment(&pool, order.payment_id).await.unwrap().unwrap();
+        assert_eq!(payment.status(), PaymentStatus::Refunded);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn a_claimed_payment_is_not_refunded_twice(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let config = testutil::test_config();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Gateways::from_config(&config)))
+                .app_data(web::Data::new(config))
+                .configure(init),
+        )
+        .await;
+        let admin = testutil::create_user(&pool, "admin").await;
+        let token = testutil::login(&pool, admin).await;
+        let customer = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Refund", 3).await;
+        let order =
+            testutil::create_order(&pool, customer, laptop, "khalti", PaymentStatus::Completed, OrderStatus::Packed).await;
+        let payment = get_payment(&pool, order.payment_id).await.unwrap().unwrap();
+
+        // Another admin's refund, claimed and still out with the gateway.
+        let mut tx = pool.begin().await.unwrap();
+        let claim = claim_refund(&mut tx, &payment, RefundMethod::Gateway, None, None, admin).await.unwrap();
+        assert!(matches!(claim, RefundClaim::Claimed));
+        tx.commit().await.unwrap();
+
+        let req = refund_request(order.payment_id, &token, serde_json::json!({ "method": "manual", "reference": "BANK-9" }));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::CONFLICT);
+        assert_eq!(refund_rows(&pool, order.payment_id).await, [("sending".to_string(), None)]);
+        let payment = get_payment(&pool, order.payment_id).await.unwrap().unwrap();
+        assert_eq!(payment.status(), PaymentStatus::Completed);
+    }
+
+    fn resolve_request(payment_id: i32, token: &str, sent: bool) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(&format!("/refunds/{}/resolve", payment_id))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(serde_json::json!({ "sent": sent }))
+    }
+
+    /// Makes every open claim look as old as one whose request died long ago.
+    async fn abandon_claims(pool: &PgPool) {
+        sqlx::query("UPDATE refunds SET created_at = NOW() - INTERVAL '1 hour' WHERE status = 'sending'")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn an_abandoned_claim_can_be_released(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let config = testutil::test_config();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Gateways::from_config(&config)))
+                .app_data(web::Data::new(config))
+                .configure(init),
+        )
+        .await;
+        let admin = testutil::create_user(&pool, "admin").await;
+        let token = testutil::login(&pool, admin).await;
+        let customer = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Refund", 3).await;
+        let order =
+            testutil::create_order(&pool, customer, laptop, "khalti", PaymentStatus::Completed, OrderStatus::Packed).await;
+        let payment = get_payment(&pool, order.payment_id).await.unwrap().unwrap();
+
+        // Claimed, then the request died before the gateway was asked.
+        let mut tx = pool.begin().await.unwrap();
+        claim_refund(&mut tx, &payment, RefundMethod::Gateway, None, None, admin).await.unwrap();
+        tx.commit().await.unwrap();
+
+        let stuck = || {
+            test::TestRequest::get()
+                .uri("/refunds/stuck")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request()
+        };
+
+        // A fresh claim may still be out with the gateway, so it isn't offered yet.
+        let listed: Vec<serde_json::Value> = test::call_and_read_body_json(&app, stuck()).await;
+        assert!(listed.is_empty());
+        let res = test::call_service(&app, resolve_request(order.payment_id, &token, false).to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        abandon_claims(&pool).await;
+        let listed: Vec<serde_json::Value> = test::call_and_read_body_json(&app, stuck()).await;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0]["payment_id"], order.payment_id);
+        assert_eq!(listed[0]["method"], "gateway");
+
+        let res = test::call_service(&app, resolve_request(order.payment_id, &token, false).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(refund_rows(&pool, order.payment_id).await.is_empty());
+        let payment = get_payment(&pool, order.payment_id).await.unwrap().unwrap();
+        assert_eq!(payment.status(), PaymentStatus::Completed);
+
+        // The payment is free to be refunded again.
+        let req = refund_request(order.payment_id, &token, serde_json::json!({ "method": "manual", "reference": "BANK-10" }));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn a_sent_but_unrecorded_return_refund_can_be_finished(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let config = testutil::test_config();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Gateways::from_config(&config)))
+                .app_data(web::Data::new(config))
+                .configure(init),
+        )
+        .await;
+        let admin = testutil::create_user(&pool, "admin").await;
+        let token = testutil::login(&pool, admin).await;
+        let customer = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Refund", 3).await;
+        let order =
+            testutil::create_order(&pool, customer, laptop, "khalti", PaymentStatus::Completed, OrderStatus::Delivered).await;
+        let payment = get_payment(&pool, order.payment_id).await.unwrap().unwrap();
+        let ReturnCreate::Created(request) = create_return_request(&pool, order.id, customer, "Dead pixels").await.unwrap()
+        else {
+            panic!("return request should be created");
+        };
+
+        // The gateway sent the money, but approving the return never got recorded.
+        let mut tx = pool.begin().await.unwrap();
+        assert!(claim_return(&mut tx, request.id).await.unwrap());
+        claim_refund(&mut tx, &payment, RefundMethod::Gateway, None, Some(request.id), admin).await.unwrap();
+        tx.commit().await.unwrap();
+        abandon_claims(&pool).await;
+
+        let res = test::call_service(&app, resolve_request(order.payment_id, &token, true).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        assert_eq!(refund_rows(&pool, order.payment_id).await, [("sent".to_string(), None)]);
+        let payment = get_payment(&pool, order.payment_id).await.unwrap().unwrap();
+        assert_eq!(payment.status(), PaymentStatus::Refunded);
+        assert_eq!(get_return(&pool, request.id).await.unwrap().unwrap().status, "approved");
+        assert_eq!(get_order(&pool, order.id).await.unwrap().unwrap().status, "returned");
+        assert_eq!(testutil::laptop_quantity(&pool, laptop).await, 4);
+
+        // Nothing is left to resolve.
+        let res = test::call_service(&app, resolve_request(order.payment_id, &token, true).to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,141 @@
+use actix_web::{get, post, web, HttpResponse, Responder};
+use sqlx::PgPool;
+use crate::config::Config;
+use crate::databases::payment::reconciliationdb::{get_run, get_run_items, list_runs};
+use crate::services::gateway::Gateways;
+use crate::services::reconciliation::run_reconciliation;
+use crate::services::session::AuthUser;
+
+// Runs happen every few minutes, so only the recent ones are worth listing.
+const RUN_LIST_LIMIT: i64 = 50;
+
+#[get("/reconciliation")]
+async fn get_runs(db: web::Data<PgPool>) -> impl Responder {
+    match list_runs(db.get_ref(), RUN_LIST_LIMIT).await {
+        Ok(runs) => HttpResponse::Ok().json(runs),
+        Err(e) => {
+            eprintln!("Error fetching reconciliation runs: {}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch reconciliation runs")
+        }
+    }
+}
+
+#[get("/reconciliation/{id}")]
+async fn get_run_report(path: web::Path<i32>, db: web::Data<PgPool>) -> impl Responder {
+    let run_id = path.into_inner();
+
+    let run = match get_run(db.get_ref(), run_id).await {
+        Ok(Some(run)) => run,
+        Ok(None) => return HttpResponse::NotFound().body("Reconciliation run not found"),
+        Err(e) => {
+            eprintln!("Error fetching reconciliation run: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to fetch reconciliation run");
+        }
+    };
+
+    match get_run_items(db.get_ref(), run_id).await {
+        Ok(items) => HttpResponse::Ok().json(serde_json::json!({
+            "run": run,
+            "items": items
+        })),
+        Err(e) => {
+            eprintln!("Error fetching reconciliation items: {}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch reconciliation run")
+        }
+    }
+}
+
+/// Reconciles now instead of waiting for the next scheduled run.
+#[post("/reconciliation/run")]
+async fn start_run(
+    admin: AuthUser,
+    db: web::Data<PgPool>,
+    config: web::Data<Config>,
+    gateways: web::Data<Gateways>,
+) -> impl Responder {
+    let run = match run_reconciliation(db.get_ref(), &config, &gateways, Some(admin.id)).await {
+        Ok(run) => run,
+        Err(e) => {
+            eprintln!("❌ Payment reconciliation failed: {:?}", e);
+            return HttpResponse::InternalServerError().body("Reconciliation failed");
+        }
+    };
+
+    match get_run_items(db.get_ref(), run.id).await {
+        Ok(items) => HttpResponse::Ok().json(serde_json::json!({
+            "run": run,
+            "items": items
+        })),
+        Err(e) => {
+            eprintln!("Error fetching reconciliation items: {}", e);
+            HttpResponse::Ok().json(serde_json::json!({ "run": run }))
+        }
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_runs);
+    cfg.service(start_run);
+    cfg.service(get_run_report);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use crate::databases::payment::paymentdb::PaymentStatus;
+    use crate::databases::shop::orderdb::OrderStatus;
+    use crate::testutil;
+
+    #[sqlx::test(migrations = false)]
+    async fn a_run_started_by_an_admin_is_reported(pool: PgPool) {
+        testutil::setup(&pool).await;
+        // Khalti is unreachable, so the stale payment's lookup fails and needs an admin.
+        let config = testutil::test_config();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Gateways::from_config(&config)))
+                .app_data(web::Data::new(config))
+                .configure(init),
+        )
+        .await;
+        let admin = testutil::create_user(&pool, "admin").await;
+        let token = testutil::login(&pool, admin).await;
+        let customer = testutil::create_user(&pool, "user").await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Reconcile", 3).await;
+        let order =
+            testutil::create_order(&pool, customer, laptop, "khalti", PaymentStatus::Pending, OrderStatus::Placed).await;
+        sqlx::query("UPDATE payments SET created_at = NOW() - INTERVAL '10 minutes' WHERE id = $1")
+            .bind(order.payment_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let req = test::TestRequest::post()
+            .uri("/reconciliation/run")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+        let started: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(started["run"]["triggered_by"], admin);
+        assert_eq!((started["run"]["checked"].as_i64(), started["run"]["unresolved"].as_i64()), (Some(1), Some(1)));
+        assert_eq!(started["items"][0]["payment_id"], order.payment_id);
+        assert_eq!(started["items"][0]["outcome"], "lookup_failed");
+        let run_id = started["run"]["id"].as_i64().unwrap();
+
+        let runs: serde_json::Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri("/reconciliation").to_request()).await;
+        assert_eq!(runs[0]["id"].as_i64(), Some(run_id));
+
+        let report: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get().uri(&format!("/reconciliation/{}", run_id)).to_request(),
+        )
+        .await;
+        assert_eq!(report["items"], started["items"]);
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/reconciliation/999999").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
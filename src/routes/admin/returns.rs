@@ -0,0 +1,356 @@
+use actix_web::{get, post, web, HttpResponse, Responder};
+use sqlx::PgPool;
+use serde::Deserialize;
+use crate::config::Config;
+use crate::databases::payment::paymentdb::{get_payment, Transition};
+use crate::databases::payment::refunddb::{claim_refund, record_refund, release_refund, RefundClaim, RefundMethod};
+use crate::databases::shop::orderdb::{get_order, transition_order, OrderStatus, OrderTransition};
+use crate::databases::shop::returndb::{
+    claim_return, decide_return, get_return, list_returns, release_return, ReturnStatus,
+};
+use crate::routes::admin::payments::{send_refund, RefundPayload};
+use crate::services::email::send_order_status_email;
+use crate::services::gateway::Gateways;
+use crate::services::session::AuthUser;
+
+#[derive(Deserialize)]
+struct ReturnListQuery {
+    status: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ApprovePayload {
+    #[serde(flatten)]
+    refund: RefundPayload,
+    note: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RejectPayload {
+    note: Option<String>,
+}
+
+#[get("/returns")]
+async fn get_returns(
+    query: web::Query<ReturnListQuery>,
+    db: web::Data<PgPool>,
+) -> impl Responder {
+    let status = match query.status.as_deref().map(ReturnStatus::parse) {
+        Some(None) => return HttpResponse::BadRequest().body("Unknown return status"),
+        Some(status) => status,
+        None => None,
+    };
+
+    match list_returns(db.get_ref(), status).await {
+        Ok(returns) => HttpResponse::Ok().json(returns),
+        Err(e) => {
+            eprintln!("Error fetching returns: {}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch returns")
+        }
+    }
+}
+
+/// Accepts a return: the order becomes returned and its items go back into stock, and the
+/// payment is refunded in full, through its gateway or as a manual record. A payment that
+/// was already refunded is left alone. The return and the refund are claimed before the
+/// gateway is asked and handed back if it says no.
+#[post("/returns/{id}/approve")]
+async fn approve_return(
+    admin: AuthUser,
+    path: web::Path<i32>,
+    payload: web::Json<ApprovePayload>,
+    db: web::Data<PgPool>,
+    config: web::Data<Config>,
+    gateways: web::Data<Gateways>,
+) -> impl Responder {
+    let Some(method) = RefundMethod::parse(&payload.refund.method) else {
+        return HttpResponse::BadRequest().body("Refund method must be gateway or manual");
+    };
+
+    let request = match get_return(db.get_ref(), path.into_inner()).await {
+        Ok(Some(request)) => request,
+        Ok(None) => return HttpResponse::NotFound().body("Return not found"),
+        Err(e) => {
+            eprintln!("Error fetching return: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to approve return");
+        }
+    };
+    if request.status != ReturnStatus::Requested.as_str() {
+        return HttpResponse::Conflict().body(format!("Return is already {}", request.status));
+    }
+
+    let loaded = async {
+        let order = get_order(db.get_ref(), request.order_id).await?;
+        let payment = match &order {
+            Some(order) => get_payment(db.get_ref(), order.payment_id).await?,
+            None => None,
+        };
+        Ok::<_, sqlx::Error>(order.zip(payment))
+    };
+    let (order, payment) = match loaded.await {
+        Ok(Some(loaded)) => loaded,
+        Ok(None) => return HttpResponse::NotFound().body("Order not found"),
+        Err(e) => {
+            eprintln!("Error fetching order for return: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to approve return");
+        }
+    };
+
+    if order.status != OrderStatus::Delivered.as_str() {
+        return HttpResponse::Conflict().body(format!("Cannot return an order that is {}", order.status));
+    }
+
+    let claimed = async {
+        let mut tx = db.begin().await?;
+        if !claim_return(&mut tx, request.id).await? {
+            return Ok(None);
+        }
+        let reference = payload.refund.reference.as_deref();
+        let claim = claim_refund(&mut tx, &payment, method, reference, Some(request.id), admin.id).await?;
+        if matches!(claim, RefundClaim::Claimed | RefundClaim::AlreadyRefunded) {
+            tx.commit().await?;
+        }
+        Ok::<_, sqlx::Error>(Some(claim))
+    };
+    let needs_refund = match claimed.await {
+        Ok(Some(RefundClaim::Claimed)) => true,
+        Ok(Some(RefundClaim::AlreadyRefunded)) => false,
+        Ok(Some(RefundClaim::InProgress)) => {
+            return HttpResponse::Conflict().body("A refund for this payment is already being sent");
+        }
+        Ok(Some(RefundClaim::Rejected(current))) => {
+            return HttpResponse::Conflict().body(format!("Cannot refund a {} payment", current.as_str()));
+        }
+        Ok(None) => return HttpResponse::Conflict().body("Return is already being decided"),
+        Err(e) => {
+            eprintln!("Error claiming return: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to approve return");
+        }
+    };
+
+    if needs_refund {
+        if let Err(response) = send_refund(&gateways, &payment, method).await {
+            let released = async {
+                let mut tx = db.begin().await?;
+                release_refund(&mut tx, payment.id).await?;
+                release_return(&mut tx, request.id).await?;
+                tx.commit().await
+            };
+            if let Err(e) = released.await {
+                eprintln!("❌ Could not release the claim on return {}: {}", request.id, e);
+            }
+            return response;
+        }
+    }
+
+    let approve = async {
+        let mut tx = db.begin().await?;
+
+        if !decide_return(&mut tx, request.id, ReturnStatus::Approved, payload.note.as_deref(), admin.id).await? {
+            return Ok(false);
+        }
+        if !matches!(
+            transition_order(&mut tx, order.id, OrderStatus::Returned, Some(admin.id)).await?,
+            OrderTransition::Applied(_)
+        ) {
+            return Ok(false);
+        }
+        if needs_refund
+            && matches!(record_refund(&mut tx, &payment, method, admin.id).await?, Transition::Rejected(_))
+        {
+            return Ok(false);
+        }
+
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    };
+
+    match approve.await {
+        Ok(true) => {}
+        Ok(false) => {
+            if needs_refund {
+                eprintln!(
+                    "❌ Return {} changed while its refund was sent; resolve payment {} under /api/admin/refunds/stuck",
+                    request.id, payment.id
+                );
+            }
+            return HttpResponse::Conflict().body("Return or order changed while approving; please reload");
+        }
+        Err(e) => {
+            eprintln!(
+                "❌ Return {} was refunded but not recorded, resolve payment {} under /api/admin/refunds/stuck: {}",
+                request.id, payment.id, e
+            );
+            return HttpResponse::InternalServerError().body("Failed to record the return");
+        }
+    }
+
+    let email = sqlx::query_scalar::<_, String>("SELECT email FROM logininfo WHERE id = $1")
+        .bind(order.user_id)
+        .fetch_one(db.get_ref())
+        .await;
+    match email {
+        Ok(email) => {
+            if let Err(e) = send_order_status_email(&config, &email, &order.order_ref, OrderStatus::Returned).await {
+                eprintln!("Failed to send order status email: {}", e);
+            }
+        }
+        Err(e) => eprintln!("Failed to look up order owner: {}", e),
+    }
+
+    match get_return(db.get_ref(), request.id).await {
+        Ok(Some(request)) => HttpResponse::Ok().json(request),
+        _ => HttpResponse::Ok().body("Return approved"),
+    }
+}
+
+#[post("/returns/{id}/reject")]
+async fn reject_return(
+    admin: AuthUser,
+    path: web::Path<i32>,
+    payload: web::Json<RejectPayload>,
+    db: web::Data<PgPool>,
+) -> impl Responder {
+    let return_id = path.into_inner();
+
+    let rejected = async {
+        let mut conn = db.acquire().await?;
+        decide_return(&mut conn, return_id, ReturnStatus::Rejected, payload.note.as_deref(), admin.id).await
+    };
+
+    match rejected.await {
+        Ok(true) => match get_return(db.get_ref(), return_id).await {
+            Ok(Some(request)) => HttpResponse::Ok().json(request),
+            _ => HttpResponse::Ok().body("Return rejected"),
+        },
+        Ok(false) => HttpResponse::Conflict().body("Return not found or already decided"),
+        Err(e) => {
+            eprintln!("Error rejecting return: {}", e);
+            HttpResponse::InternalServerError().body("Failed to reject return")
+        }
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_returns);
+    cfg.service(approve_return);
+    cfg.service(reject_return);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use crate::databases::payment::paymentdb::PaymentStatus;
+    use crate::databases::shop::returndb::{create_return_request, ReturnCreate, ReturnRequest};
+    use crate::testutil;
+
+    struct Fixture {
+        token: String,
+        laptop: i32,
+        order_id: i32,
+        payment_id: i32,
+        request: ReturnRequest,
+    }
+
+    /// A delivered Khalti order with an open return request against it.
+    async fn returned_order(pool: &PgPool) -> Fixture {
+        let admin = testutil::create_user(pool, "admin").await;
+        let token = testutil::login(pool, admin).await;
+        let customer = testutil::create_user(pool, "user").await;
+        let laptop = testutil::create_laptop(pool, "Acme", "Return", 3).await;
+        let order =
+            testutil::create_order(pool, customer, laptop, "khalti", PaymentStatus::Completed, OrderStatus::Delivered).await;
+        let ReturnCreate::Created(request) =
+            create_return_request(pool, order.id, customer, "Screen flickers").await.unwrap()
+        else {
+            panic!("return request should be created");
+        };
+        Fixture { token, laptop, order_id: order.id, payment_id: order.payment_id, request }
+    }
+
+    fn decide(path: String, token: &str, body: serde_json::Value) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(&path)
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(body)
+    }
+
+    async fn return_status(pool: &PgPool, id: i32) -> String {
+        get_return(pool, id).await.unwrap().unwrap().status
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn a_refused_gateway_refund_reopens_the_return(pool: PgPool) {
+        testutil::setup(&pool).await;
+        // The test config points Khalti at a closed port, so every gateway refund fails.
+        let config = testutil::test_config();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Gateways::from_config(&config)))
+                .app_data(web::Data::new(config))
+                .configure(init),
+        )
+        .await;
+        let f = returned_order(&pool).await;
+        let approve = format!("/returns/{}/approve", f.request.id);
+
+        let req = decide(approve.clone(), &f.token, serde_json::json!({ "method": "gateway" }));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(return_status(&pool, f.request.id).await, "requested");
+        assert_eq!(get_order(&pool, f.order_id).await.unwrap().unwrap().status, "delivered");
+        let payment = get_payment(&pool, f.payment_id).await.unwrap().unwrap();
+        assert_eq!(payment.status(), PaymentStatus::Completed);
+        let refunds: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM refunds").fetch_one(&pool).await.unwrap();
+        assert_eq!(refunds, 0);
+        assert_eq!(testutil::laptop_quantity(&pool, f.laptop).await, 3);
+
+        let req = decide(approve, &f.token, serde_json::json!({ "method": "manual", "reference": "BANK-1" }));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+        assert_eq!(return_status(&pool, f.request.id).await, "approved");
+        assert_eq!(get_order(&pool, f.order_id).await.unwrap().unwrap().status, "returned");
+        let payment = get_payment(&pool, f.payment_id).await.unwrap().unwrap();
+        assert_eq!(payment.status(), PaymentStatus::Refunded);
+        let refund: (String, Option<i32>) = sqlx::query_as("SELECT status, return_id FROM refunds WHERE payment_id = $1")
+            .bind(f.payment_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(refund, ("sent".to_string(), Some(f.request.id)));
+        assert_eq!(testutil::laptop_quantity(&pool, f.laptop).await, 4);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn a_return_being_refunded_cannot_be_decided_again(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let config = testutil::test_config();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Gateways::from_config(&config)))
+                .app_data(web::Data::new(config))
+                .configure(init),
+        )
+        .await;
+        let f = returned_order(&pool).await;
+        let mut conn = pool.acquire().await.unwrap();
+        assert!(claim_return(&mut conn, f.request.id).await.unwrap());
+        drop(conn);
+
+        let approve = decide(
+            format!("/returns/{}/approve", f.request.id),
+            &f.token,
+            serde_json::json!({ "method": "manual", "reference": "BANK-2" }),
+        );
+        assert_eq!(test::call_service(&app, approve.to_request()).await.status(), StatusCode::CONFLICT);
+        let reject = decide(format!("/returns/{}/reject", f.request.id), &f.token, serde_json::json!({}));
+        assert_eq!(test::call_service(&app, reject.to_request()).await.status(), StatusCode::CONFLICT);
+
+        // It waits for its refund to be recorded, or to be resolved as stuck if it never is.
+        assert_eq!(return_status(&pool, f.request.id).await, "refunding");
+        let payment = get_payment(&pool, f.payment_id).await.unwrap().unwrap();
+        assert_eq!(payment.status(), PaymentStatus::Completed);
+    }
+}
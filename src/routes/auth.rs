@@ -2,15 +2,15 @@ use actix_web::{web, HttpResponse, Responder};
 use crate::databases::auth::temp_user::SignupData;
 use crate::databases::auth::tempdb::{insert_temp_user, user_exists, check_conflict_field};
 use crate::services::email::send_code_email;
+use crate::config::Config;
 use sqlx::PgPool;
 use serde_json::json;
-use rand_core::OsRng;
-use argon2::Argon2;
-use argon2::password_hash::{SaltString, PasswordHasher};
+use crate::databases::auth::logindb::hash_password;
 
 pub async fn signup(
     data: web::Json<SignupData>,
     db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
 ) -> impl Responder {
     let mut user = data.into_inner();
 
@@ -18,10 +18,8 @@ pub async fn signup(
         return HttpResponse::BadRequest().body("Email and password required");
     }
 
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-    let hashed_password = match argon2.hash_password(user.password.as_bytes(), &salt) {
-        Ok(hash) => hash.to_string(),
+    let hashed_password = match hash_password(&user.password) {
+        Ok(hash) => hash,
         Err(e) => return HttpResponse::InternalServerError().body(format!("Hashing error: {}", e)),
     };
 
@@ -47,13 +45,12 @@ match user_exists(&db_pool, &user.email, &user.number).await {
     Ok(false) => {
          match insert_temp_user(&db_pool, user).await {
         Ok(temp_user) => {
-            if let Err(e) = send_code_email(&temp_user.email, &temp_user.code).await {
+            if let Err(e) = send_code_email(&config, &temp_user.email, &temp_user.code).await {
                 return HttpResponse::InternalServerError().body(format!("Email failed: {}", e));
             }
 
             HttpResponse::Ok().json(json!({
-                "temp_id": temp_user.temp_id,
-                "otp": temp_user.code 
+                "temp_id": temp_user.temp_id
             }))
         }
         Err(e) => HttpResponse::InternalServerError().body(format!("DB insert failed: {}", e)),
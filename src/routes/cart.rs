@@ -0,0 +1,198 @@
+use actix_web::{web, HttpResponse, Responder};
+use sqlx::PgPool;
+use serde::{Deserialize, Serialize};
+use num_traits::ToPrimitive;
+use crate::databases::shop::cartdb::{
+    add_to_cart, clear_cart, get_cart, remove_from_cart, set_cart_quantity, total_paisa, CartUpdate,
+};
+use crate::services::session::AuthUser;
+
+#[derive(Deserialize)]
+pub struct AddItemRequest {
+    pub laptop_id: i32,
+    pub quantity: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateItemRequest {
+    pub quantity: i32,
+}
+
+#[derive(Serialize)]
+struct CartItemResponse {
+    laptop_id: i32,
+    display_name: String,
+    quantity: i32,
+    unit_price: f64,
+    line_total: f64,
+    in_stock: i32,
+}
+
+#[derive(Serialize)]
+struct CartResponse {
+    items: Vec<CartItemResponse>,
+    total: f64,
+}
+
+fn cart_update_response(update: Result<CartUpdate, sqlx::Error>) -> HttpResponse {
+    match update {
+        Ok(CartUpdate::Updated) => HttpResponse::Ok().json(serde_json::json!({ "message": "Cart updated" })),
+        Ok(CartUpdate::ProductNotFound) => HttpResponse::NotFound().body("Product not found"),
+        Ok(CartUpdate::InsufficientStock { available }) => HttpResponse::Conflict().json(serde_json::json!({
+            "message": format!("Only {} left in stock", available),
+            "available": available
+        })),
+        Err(e) => {
+            eprintln!("❌ Failed to update cart: {:?}", e);
+            HttpResponse::InternalServerError().body("Database error")
+        }
+    }
+}
+
+/// Prices are the products' current prices; nothing is reserved until checkout.
+pub async fn view_cart(
+    user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let lines = match get_cart(&db_pool, user.id).await {
+        Ok(lines) => lines,
+        Err(e) => {
+            eprintln!("❌ Failed to fetch cart: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    };
+
+    let total = total_paisa(&lines).unwrap_or(0) as f64 / 100.0;
+    let items = lines
+        .into_iter()
+        .map(|line| CartItemResponse {
+            line_total: line.line_total_paisa().unwrap_or(0) as f64 / 100.0,
+            unit_price: line.unit_price.to_f64().unwrap_or(0.0),
+            laptop_id: line.laptop_id,
+            display_name: line.display_name,
+            quantity: line.quantity,
+            in_stock: line.stock,
+        })
+        .collect();
+
+    HttpResponse::Ok().json(CartResponse { items, total })
+}
+
+pub async fn add_item(
+    user: AuthUser,
+    data: web::Json<AddItemRequest>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let quantity = data.quantity.unwrap_or(1);
+    if quantity < 1 {
+        return HttpResponse::BadRequest().body("Quantity must be at least 1");
+    }
+
+    cart_update_response(add_to_cart(&db_pool, user.id, data.laptop_id, quantity).await)
+}
+
+pub async fn update_item(
+    user: AuthUser,
+    path: web::Path<i32>,
+    data: web::Json<UpdateItemRequest>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let laptop_id = path.into_inner();
+
+    // Setting the quantity to zero is the same as removing the line.
+    if data.quantity < 1 {
+        return removal_response(remove_from_cart(&db_pool, user.id, laptop_id).await);
+    }
+
+    cart_update_response(set_cart_quantity(&db_pool, user.id, laptop_id, data.quantity).await)
+}
+
+pub async fn remove_item(
+    user: AuthUser,
+    path: web::Path<i32>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    removal_response(remove_from_cart(&db_pool, user.id, path.into_inner()).await)
+}
+
+fn removal_response(removed: Result<bool, sqlx::Error>) -> HttpResponse {
+    match removed {
+        Ok(true) => HttpResponse::Ok().json(serde_json::json!({ "message": "Removed from cart" })),
+        Ok(false) => HttpResponse::NotFound().body("Item not in cart"),
+        Err(e) => {
+            eprintln!("❌ Failed to remove cart item: {:?}", e);
+            HttpResponse::InternalServerError().body("Database error")
+        }
+    }
+}
+
+pub async fn empty_cart(
+    user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    match clear_cart(&db_pool, user.id).await {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({ "message": "Cart cleared" })),
+        Err(e) => {
+            eprintln!("❌ Failed to clear cart: {:?}", e);
+            HttpResponse::InternalServerError().body("Database error")
+        }
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.route("/api/cart", web::get().to(view_cart));
+    cfg.route("/api/cart", web::delete().to(empty_cart));
+    cfg.route("/api/cart/items", web::post().to(add_item));
+    cfg.route("/api/cart/items/{laptop_id}", web::patch().to(update_item));
+    cfg.route("/api/cart/items/{laptop_id}", web::delete().to(remove_item));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use crate::testutil;
+
+    #[sqlx::test(migrations = false)]
+    async fn adding_to_the_cart(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(App::new().app_data(web::Data::new(pool.clone())).configure(init)).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let token = testutil::login(&pool, user).await;
+        let laptop = testutil::create_laptop(&pool, "Acme", "Cart", 3).await;
+
+        let add = |body: serde_json::Value| {
+            test::TestRequest::post()
+                .uri("/api/cart/items")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .set_json(body)
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, add(serde_json::json!({ "laptop_id": laptop }))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(&app, add(serde_json::json!({ "laptop_id": laptop, "quantity": 1 }))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::call_service(&app, add(serde_json::json!({ "laptop_id": laptop, "quantity": 2 }))).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["available"], 3);
+
+        let resp = test::call_service(&app, add(serde_json::json!({ "laptop_id": laptop, "quantity": 0 }))).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = test::call_service(&app, add(serde_json::json!({ "laptop_id": 999_999 }))).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::get()
+            .uri("/api/cart")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+        let cart: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(cart["items"].as_array().unwrap().len(), 1);
+        assert_eq!(cart["items"][0]["quantity"], 2);
+        assert_eq!(cart["items"][0]["line_total"], 2360.0);
+        assert_eq!(cart["total"], 2360.0);
+    }
+}
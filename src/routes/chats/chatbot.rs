@@ -1,12 +1,23 @@
 use sqlx::{Row, PgPool};
 use chrono::Utc;
-use serde_json::{json, Value};
-use reqwest::Client;
-use std::collections::HashSet;
-use std::env;
-use sqlx::types::BigDecimal;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use crate::config::Config;
+use crate::services::llm::{ChatMessage, LlmProvider};
+use crate::services::catalog::filter::{ProductFilter, RejectedFilter};
+use crate::services::catalog::{CatalogQuery, CatalogSort};
 use num_traits::cast::ToPrimitive;
 
+const ASK_MORE: &str = "Could you please tell me a bit more, like your budget, RAM, storage, or intended use (e.g., gaming, study, editing)? This will help me suggest the best laptops for you.";
+
+/// What the model returns once it has enough to search. Filter values are checked
+/// against the column whitelist separately, so one bad filter doesn't drop the rest.
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum BotAction {
+    Search { filters: Map<String, Value> },
+}
+
 fn extract_json_from_text(text: &str) -> Option<String> {
     let start = text.find('{')?;
     let end = text.rfind('}')?;
@@ -17,7 +28,13 @@ fn extract_json_from_text(text: &str) -> Option<String> {
     }
 }
 
-pub async fn process_bot_message(user_id: &str, user_message: &str, db: &PgPool) -> anyhow::Result<String> {
+pub async fn process_bot_message(
+    user_id: &str,
+    user_message: &str,
+    db: &PgPool,
+    llm: &dyn LlmProvider,
+    config: &Config,
+) -> anyhow::Result<String> {
     let system_prompt = r#"
     You are a backend assistant for an e-commerce laptop store in Nepal. Shop name is epasal.
 
@@ -29,7 +46,8 @@ ONLY return a JSON object like:
     "brand_name": "acer",
     "ram": 16,
     "graphic": "rtx 3050",
-    "show_price": { "lte": 180000 }
+    "show_price": { "lte": 180000 },
+    "storage_type": { "in": ["ssd", "nvme"] }
   }
 }
 
@@ -42,6 +60,9 @@ User filters may include:
   graphic, graphic_ram, display, display_type, touchscreen, power_supply, battery, warranty,
   show_price (in NPR)
 
+A text value matches any product containing it; a number must match exactly. For other comparisons
+use an object with one of: eq, lte, gte (numbers only), in (a list of values), contains (text only).
+
 Once enough filters are collected from the user, return the JSON.
 Otherwise, keep asking clarifying questions to get more filter info.
 
@@ -56,10 +77,7 @@ Until enough information is gathered, keep the conversation friendly and natural
 
     let mut messages = Vec::new();
 
-    messages.push(json!({
-        "role": "system",
-        "content": system_prompt.trim()
-    }));
+    messages.push(ChatMessage::new("system", system_prompt.trim()));
 
     for row in rows {
         let sender: String = row.try_get("sender")?;
@@ -69,197 +87,191 @@ Until enough information is gathered, keep the conversation friendly and natural
             "bot" => "assistant",
             _ => "user",
         };
-        messages.push(json!({
-            "role": role,
-            "content": content
-        }));
+        messages.push(ChatMessage::new(role, &content));
     }
 
-    messages.push(json!({
-        "role": "user",
-        "content": user_message
-    }));
-
-    println!("Calling chat API with current user message...");
-    let client = Client::new();
-    let res = client
-        .post("http://localhost:11434/api/chat")
-        .json(&json!({
-            "model": "gemma3",
-            "messages": messages,
-            "stream": false
-        }))
-        .send()
-        .await?;
+    messages.push(ChatMessage::new("user", user_message));
+
+    println!("Calling {} chat API with current user message...", llm.name());
+    let bot_response = llm.chat(&messages).await?;
 
-    let response_json: Value = res.json().await?;
-    //println!("{}",response_json);
-    let bot_response = response_json["message"]["content"]
-        .as_str()
-        .unwrap_or("Sorry, I didn’t understand that.")
-        .to_string();
-
-    let json_candidate = extract_json_from_text(&bot_response);
-
-if let Some(json_str) = json_candidate {
-    if let Ok(value) = serde_json::from_str::<Value>(&json_str) {
-        if value["action"] == "search" {
-            let filters_obj = value.get("filters").and_then(|f| f.as_object());
-            let filters = match filters_obj {
-                Some(f) => f,
-                None => {
-                    let ask_more = "Could you please give me more details like your budget, RAM, storage, or use case (e.g., gaming, study, editing)?";
-                    sqlx::query("INSERT INTO messages (user_id, content, timestamp, sender, receiver) VALUES ($1, $2, $3, 'bot', 'user')")
-                        .bind(user_id)
-                        .bind(ask_more)
-                        .bind(Utc::now())
-                        .execute(db)
-                        .await?;
-                    return Ok(ask_more.to_string());
-                }
-            };
-
-            let mut query = String::from("SELECT id, display_name, show_price FROM laptop_details WHERE 1=1");
-            let mut args = Vec::new();
-
-            println!("Building SQL query with filters...");
-
-            let numeric_columns = HashSet::from(["ram", "graphic_ram", "show_price", "model_year", "storage"]);
-
-            for (key, val) in filters {
-                if numeric_columns.contains(key.as_str()) {
-                    if val.is_object() {
-                        if let Some(obj) = val.as_object() {
-                            for (op, v) in obj {
-                                let val_num = v.as_i64().unwrap_or(0);
-                                match op.as_str() {
-                                    "lte" => {
-                                        query.push_str(&format!(" AND {} <= ${}", key, args.len() + 1));
-                                        args.push(val_num.to_string());
-                                    }
-                                    "gte" => {
-                                        query.push_str(&format!(" AND {} >= ${}", key, args.len() + 1));
-                                        args.push(val_num.to_string());
-                                    }
-                                    "eq" => {
-                                        query.push_str(&format!(" AND {} = ${}", key, args.len() + 1));
-                                        args.push(val_num.to_string());
-                                    }
-                                    _ => {}
-                                }
-                            }
-                        }
-                    } else if val.is_number() {
-                        let val_num = val.as_i64().unwrap_or(0);
-                        query.push_str(&format!(" AND {} = ${}", key, args.len() + 1));
-                        args.push(val_num.to_string());
-                    } else if val.is_string() {
-                        if let Ok(val_num) = val.as_str().unwrap_or("").parse::<i64>() {
-                            query.push_str(&format!(" AND {} = ${}", key, args.len() + 1));
-                            args.push(val_num.to_string());
-                        }
-                    }
-                } else {
-                    if let Some(val_str) = val.as_str() {
-                        query.push_str(&format!(" AND {} ILIKE ${}", key, args.len() + 1));
-                        args.push(format!("%{}%", val_str));
-                    }
-                }
-            }
-
-            if args.len() <= 2 {
-                let ask_more = "Could you please tell me a bit more, like your budget, RAM, storage, or intended use (e.g., gaming, study, editing)? This will help me suggest the best laptops for you.";
-                sqlx::query("INSERT INTO messages (user_id, content, timestamp, sender, receiver) VALUES ($1, $2, $3, 'bot', 'user')")
-                    .bind(user_id)
-                    .bind(ask_more)
-                    .bind(Utc::now())
-                    .execute(db)
-                    .await?;
-                return Ok(ask_more.to_string());
-            }
-
-            query.push_str(" ORDER BY show_price ASC LIMIT 4");
-
-            let mut q = sqlx::query(&query);
-            for arg in &args {
-    if let Ok(int_val) = arg.parse::<i64>() {
-        q = q.bind(int_val);
-    } 
-    else if let Ok(float_val) = arg.parse::<f64>() {
-        q = q.bind(float_val);
-    } 
+    let Some(action) = extract_json_from_text(&bot_response)
+        .and_then(|json| serde_json::from_str::<Value>(&json).ok())
+        .filter(|value| value["action"] == "search")
     else {
-        q = q.bind(arg);
+        return save_bot_reply(db, user_id, bot_response).await;
+    };
+
+    let filters = match serde_json::from_value::<BotAction>(action) {
+        Ok(BotAction::Search { filters }) => filters,
+        Err(e) => {
+            println!("Ignoring malformed search from the model: {}", e);
+            return save_bot_reply(db, user_id, ASK_MORE.to_string()).await;
+        }
+    };
+
+    let (filters, rejected) = ProductFilter::from_json(&filters);
+    let note = rejected_note(&rejected);
+
+    // Each filter is one condition with one bound value, as each bound argument was when
+    // the search SQL was written by hand; filters that were turned down don't count.
+    if filters.len() <= 2 {
+        return save_bot_reply(db, user_id, with_note(ASK_MORE, &note)).await;
+    }
+
+    println!("Searching the catalog with filters...");
+    let laptops = CatalogQuery {
+        filters,
+        sort: CatalogSort::PriceAsc,
+        limit: Some(4),
+        ..Default::default()
     }
+    .fetch(db)
+    .await?;
+
+    if laptops.is_empty() {
+        let reply = "Sorry, no laptops matched your preferences. Would you like to try different filters?";
+        return save_bot_reply(db, user_id, with_note(reply, &note)).await;
+    }
+
+    // Build the response links
+    println!("Generating response links for found laptops...");
+    let base_url = &config.base_url;
+    let links: Vec<String> = laptops
+        .iter()
+        .map(|laptop| format!(
+            "- [{}]({}/products?id={}) - NPR {:.2}",
+            laptop.display_name,
+            base_url,
+            laptop.id,
+            laptop.show_price.to_f64().unwrap_or(0.0)
+        ))
+        .collect();
+
+    let response_text = format!(
+        "Here are some laptops I found for you:\n{}",
+        links.join("\n")
+    );
+    save_bot_reply(db, user_id, with_note(&response_text, &note)).await
+}
+
+/// Tells the customer (and the model, which sees the conversation next turn) which
+/// filters were left out of the search.
+fn rejected_note(rejected: &[RejectedFilter]) -> Option<String> {
+    if rejected.is_empty() {
+        return None;
+    }
+    let reasons: Vec<String> = rejected
+        .iter()
+        .map(|r| format!("- {}: {}", r.key, r.reason))
+        .collect();
+    Some(format!("I couldn't use some of those details:\n{}", reasons.join("\n")))
 }
 
-            println!("Executing SQL query to fetch laptops...");
-            let rows = q.fetch_all(db).await?;
-
-            if rows.is_empty() {
-                return Ok("Sorry, no laptops matched your preferences. Would you like to try different filters?".to_string());
-            }
-
-            // Build the response links
-            println!("Generating response links for found laptops...");
-            let base_url = env::var("BASE_URL").unwrap_or_else(|_| "https://localhost:5173".to_string());
-            let mut links = Vec::new();
-for row in rows {
-    let id: i32 = row.try_get("id")?;
-    let name: String = row.try_get("display_name")?;
-    let price: BigDecimal = row.try_get("show_price")?;
-    let price_f64 = price.to_f64().unwrap_or(0.0);
-
-    links.push(format!(
-        "- [{}](https://{}/products?id={}) - NPR {:.2}",
-        name,
-        base_url.trim_start_matches("https://"),
-        id,
-        price_f64
-    ));
+fn with_note(reply: &str, note: &Option<String>) -> String {
+    match note {
+        Some(note) => format!("{}\n\n{}", reply, note),
+        None => reply.to_string(),
+    }
 }
 
-let response_text = format!(
-    "Here are some laptops I found for you:\n{}",
-    links.join("\n")
-);
-            // Save bot reply in DB
-            sqlx::query("INSERT INTO messages (user_id, content, timestamp, sender, receiver) VALUES ($1, $2, $3, 'bot', 'user')")
-                .bind(user_id)
-                .bind(&response_text)
-                .bind(Utc::now())
-                .execute(db)
-                .await?;
-
-            return Ok(response_text);
-        } else {
-            sqlx::query("INSERT INTO messages (user_id, content, timestamp, sender, receiver) VALUES ($1, $2, $3, 'bot', 'user')")
-                .bind(user_id)
-                .bind(&bot_response)
-                .bind(Utc::now())
-                .execute(db)
-                .await?;
-
-            return Ok(bot_response);
-        }
-    } else {
-        sqlx::query("INSERT INTO messages (user_id, content, timestamp, sender, receiver) VALUES ($1, $2, $3, 'bot', 'user')")
+async fn save_bot_reply(db: &PgPool, user_id: &str, reply: String) -> anyhow::Result<String> {
+    sqlx::query("INSERT INTO messages (user_id, content, timestamp, sender, receiver) VALUES ($1, $2, $3, 'bot', 'user')")
+        .bind(user_id)
+        .bind(&reply)
+        .bind(Utc::now())
+        .execute(db)
+        .await?;
+
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::llm::ScriptedProvider;
+    use crate::testutil;
+
+    async fn saved_replies(db: &PgPool, user_id: &str) -> Vec<String> {
+        sqlx::query_scalar("SELECT content FROM messages WHERE user_id = $1 AND sender = 'bot' ORDER BY id")
             .bind(user_id)
-            .bind(&bot_response)
-            .bind(Utc::now())
-            .execute(db)
-            .await?;
+            .fetch_all(db)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn a_question_is_passed_on_as_is(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let llm = ScriptedProvider::new(vec!["What is your budget?".to_string()]);
 
-        return Ok(bot_response);
+        let reply = process_bot_message("7", "I need a laptop", &pool, &llm, &testutil::test_config()).await.unwrap();
+
+        assert_eq!(reply, "What is your budget?");
+        assert_eq!(saved_replies(&pool, "7").await, [reply]);
     }
-}else{
-    sqlx::query("INSERT INTO messages (user_id, content, timestamp, sender, receiver) VALUES ($1, $2, $3, 'bot', 'user')")
-    .bind(user_id)
-    .bind(&bot_response)
-    .bind(Utc::now())
-    .execute(db)
-    .await?;
 
-return Ok(bot_response);
+    #[sqlx::test(migrations = false)]
+    async fn a_search_replies_with_matching_laptops(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let matching = testutil::create_laptop(&pool, "Lenovo", "Match", 3).await;
+        let too_little_ram = testutil::create_laptop(&pool, "Lenovo", "Small", 3).await;
+        for (id, ram) in [(matching, 16), (too_little_ram, 8)] {
+            sqlx::query("UPDATE laptop_details SET ram = $2, storage = 512 WHERE id = $1")
+                .bind(id)
+                .bind(ram)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let llm = ScriptedProvider::new(vec![
+            r#"Sure! {"action": "search", "filters": {"ram": 16, "storage": 512, "show_price": {"lte": 200000}}}"#
+                .to_string(),
+        ]);
+
+        let reply = process_bot_message("7", "16GB, 512GB, under 2 lakh", &pool, &llm, &testutil::test_config())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            reply,
+            format!(
+                "Here are some laptops I found for you:\n- [Lenovo Match](http://localhost:5173/products?id={}) - NPR 1180.00",
+                matching
+            )
+        );
+        assert_eq!(saved_replies(&pool, "7").await, [reply]);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn unusable_filters_are_named_in_the_reply(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let llm = ScriptedProvider::new(vec![
+            r#"{"action": "search", "filters": {"ram": 16, "price; DROP TABLE messages": 1}}"#.to_string(),
+        ]);
+
+        let reply = process_bot_message("7", "16GB", &pool, &llm, &testutil::test_config()).await.unwrap();
+
+        assert!(reply.starts_with(ASK_MORE), "{}", reply);
+        assert!(reply.contains("- price; DROP TABLE messages: not a product detail I can filter by"), "{}", reply);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn three_usable_conditions_are_needed_to_search(pool: PgPool) {
+        testutil::setup(&pool).await;
+        testutil::create_laptop(&pool, "Lenovo", "Any", 3).await;
+        let cases = [
+            // Two conditions on one detail still count as two.
+            (r#"{"show_price": {"gte": 100000, "lte": 200000}}"#, true),
+            (r#"{"ram": 16, "show_price": {"gte": 100000, "lte": 200000}}"#, false),
+            (r#"{"ram": 16, "storage": 512, "cost_price": 1000}"#, true),
+            (r#"{"ram": 16, "storage": 512, "graphic": "rtx"}"#, false),
+        ];
+
+        for (filters, asks_more) in cases {
+            let llm = ScriptedProvider::new(vec![format!(r#"{{"action": "search", "filters": {}}}"#, filters)]);
+            let reply = process_bot_message("7", "a laptop", &pool, &llm, &testutil::test_config()).await.unwrap();
+            assert_eq!(reply.starts_with(ASK_MORE), asks_more, "{}: {}", filters, reply);
+        }
+    }
 }
-}
\ No newline at end of file
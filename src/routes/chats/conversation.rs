@@ -1,18 +1,18 @@
 use actix_web::{get, post, web, HttpResponse, Responder};
 use sqlx::PgPool;
 use super::chatbot::process_bot_message;
+use crate::services::session::AuthUser;
+use crate::config::Config;
+use crate::services::llm::Chatbot;
 
 use crate::routes::chats::messages::{Message, NewMessage};
 
 #[get("/messages")]
 pub async fn get_messages(
     db: web::Data<PgPool>,
-    query: web::Query<std::collections::HashMap<String, String>>,
+    user: AuthUser,
 ) -> impl Responder {
-    let user_id = match query.get("userId") {
-        Some(id) => id.clone(),
-        None => return HttpResponse::BadRequest().body("Missing userId"),
-    };
+    let user_id = user.id.to_string();
 
     let result = sqlx::query_as::<_, Message>(
         "SELECT * FROM messages WHERE user_id = $1 ORDER BY timestamp ASC",
@@ -33,14 +33,18 @@ pub async fn get_messages(
 #[post("/messages")]
 pub async fn post_message(
     db: web::Data<PgPool>,
+    user: AuthUser,
     body: web::Json<NewMessage>,
+    config: web::Data<Config>,
+    chatbot: web::Data<Chatbot>,
 ) -> impl Responder {
     let msg = body.into_inner();
+    let user_id = user.id.to_string();
 
     let exists_result = sqlx::query_scalar::<_, Option<String>>(
         "SELECT user_id FROM user_bot_settings WHERE user_id = $1",
     )
-    .bind(&msg.user_id)
+    .bind(&user_id)
     .fetch_optional(db.get_ref())
     .await;
 
@@ -53,7 +57,7 @@ pub async fn post_message(
         let insert_result = sqlx::query(
             "INSERT INTO user_bot_settings (user_id) VALUES ($1)",
         )
-        .bind(&msg.user_id)
+        .bind(&user_id)
         .execute(db.get_ref())
         .await;
 
@@ -66,7 +70,7 @@ pub async fn post_message(
     let bot_enabled_result = sqlx::query_scalar::<_, bool>(
         "SELECT bot_enabled FROM user_bot_settings WHERE user_id = $1",
     )
-    .bind(&msg.user_id)
+    .bind(&user_id)
     .fetch_one(db.get_ref())
     .await;
 
@@ -78,37 +82,55 @@ pub async fn post_message(
         }
     };
 
-    let receiver = if bot_enabled { "bot" } else { "admin" };
+    // With no reachable provider the conversation is handed to an admin.
+    let bot_replies = bot_enabled && chatbot.is_available();
+    let receiver = if bot_replies { "bot" } else { "admin" };
 
     let result = sqlx::query_as::<_, Message>(
         "INSERT INTO messages (user_id, content, timestamp, sender, receiver) 
          VALUES ($1, $2, $3, $4, $5) RETURNING *",
     )
-    .bind(&msg.user_id)
+    .bind(&user_id)
     .bind(&msg.content)
     .bind(msg.timestamp)
-    .bind(&msg.sender)
+    .bind("user")
     .bind(receiver)
     .fetch_one(db.get_ref())
     .await;
 
     match result {
     Ok(saved) => {
-        if bot_enabled {
+        if bot_replies {
             // Fire and forget
-            let user_id = msg.user_id.clone();
+            let user_id = user_id.clone();
             let content = msg.content.clone();
+            let message_id = saved.id;
             let db_clone = db.clone();
+            let config = config.clone();
+            let chatbot = chatbot.clone();
 
             actix_web::rt::spawn(async move {
-                if let Err(e) =process_bot_message(&user_id, &content, db_clone.get_ref()).await {
+                let Some(llm) = chatbot.provider() else { return };
+                if let Err(e) = process_bot_message(&user_id, &content, db_clone.get_ref(), llm, &config).await {
                     eprintln!("Bot processing error: {:?}", e);
+
+                    // If the provider went away, leave the message for an admin to answer.
+                    if !chatbot.refresh().await {
+                        if let Err(e) = sqlx::query("UPDATE messages SET receiver = 'admin' WHERE id = $1")
+                            .bind(message_id)
+                            .execute(db_clone.get_ref())
+                            .await
+                        {
+                            eprintln!("Failed to hand message to admin: {:?}", e);
+                        }
+                    }
                 }
             });
         }
 
         HttpResponse::Ok().json(serde_json::json!({
-            "message": saved
+            "message": saved,
+            "bot_available": bot_replies
         }))
     }
     Err(e) => {
@@ -121,4 +143,50 @@ pub async fn post_message(
 pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(get_messages);
     cfg.service(post_message);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use crate::config::LlmProviderKind;
+    use crate::testutil;
+
+    #[sqlx::test(migrations = false)]
+    async fn unreachable_provider_hands_messages_to_an_admin(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let mut config = testutil::test_config();
+        // Nothing listens on port 1, so the startup health check fails.
+        config.llm.provider = LlmProviderKind::Ollama;
+        let chatbot = Chatbot::from_config(&config.llm).await;
+        assert!(chatbot.provider().is_some());
+        assert!(!chatbot.is_available());
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(chatbot))
+                .configure(init),
+        )
+        .await;
+        let user = testutil::create_user(&pool, "user").await;
+        let token = testutil::login(&pool, user).await;
+
+        let req = test::TestRequest::post()
+            .uri("/messages")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(serde_json::json!({ "content": "Is the Legion in stock?", "timestamp": "2026-01-01T00:00:00Z" }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["bot_available"], false);
+        assert_eq!(body["message"]["receiver"], "admin");
+        let receivers: Vec<String> = sqlx::query_scalar("SELECT receiver FROM messages WHERE user_id = $1")
+            .bind(user.to_string())
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(receivers, ["admin"]);
+    }
+}
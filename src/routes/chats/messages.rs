@@ -13,9 +13,6 @@ pub struct Message {
 
 #[derive(Serialize, Deserialize)]
 pub struct NewMessage {
-    #[serde(rename = "userId")]
-    pub user_id: String,
     pub content: String,
     pub timestamp: DateTime<Utc>,
-    pub sender: String,
 }
\ No newline at end of file
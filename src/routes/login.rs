@@ -1,8 +1,19 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use sqlx::PgPool;
 use serde::Deserialize;
+use std::sync::OnceLock;
 use argon2::{Argon2, PasswordHash, PasswordVerifier};
-use crate::databases::auth::logindb::{get_user_by_gmail};
+use crate::databases::auth::logindb::{
+    claim_login_attempt, finish_login_attempt, get_user_by_gmail, hash_password, record_login_attempt,
+    release_login_attempt, LOCKOUT_WINDOW_MINUTES,
+};
+use crate::databases::auth::sessiondb::{create_session, delete_session, delete_user_sessions};
+use crate::databases::auth::totpdb::{
+    attempt_login_challenge, create_login_challenge, delete_login_challenge, is_totp_enabled, MAX_CHALLENGE_ATTEMPTS,
+};
+use crate::config::Config;
+use crate::routes::twofactor::verify_second_factor;
+use crate::services::session::AuthUser;
 use serde_json::json;
 
 #[derive(Debug, Deserialize)]
@@ -11,32 +22,219 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorLoginRequest {
+    pub challenge: String,
+    pub code: String,
+}
+
+// The address failed logins, and password reset requests, are counted against. The
+// forwarding headers are only believed when `trusted_proxy` says a proxy of ours sets them.
+pub fn client_ip(req: &HttpRequest) -> String {
+    let trusted_proxy = req.app_data::<web::Data<Config>>().is_some_and(|c| c.trusted_proxy);
+    let info = req.connection_info();
+    let ip = if trusted_proxy { info.realip_remote_addr() } else { info.peer_addr() };
+    ip.unwrap_or("unknown").to_string()
+}
+
+fn invalid_credentials() -> HttpResponse {
+    HttpResponse::Unauthorized().body("Invalid credentials")
+}
+
+// Hash checked against when the email is unknown, so both failure paths cost the same.
+fn dummy_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| hash_password("not-a-real-password").unwrap_or_default())
+}
+
 pub async fn login(
+    req: HttpRequest,
     data: web::Json<LoginRequest>,
     db_pool: web::Data<PgPool>,
 ) -> impl Responder {
     let LoginRequest { gmail, password } = data.into_inner();
+    let ip = client_ip(&req);
 
-    match get_user_by_gmail(&db_pool, &gmail).await {
-        Ok(Some(user)) => {
-            let parsed_hash = match PasswordHash::new(&user.hashed_password) {
-                Ok(hash) => hash,
-                Err(_) => return HttpResponse::InternalServerError().body("Password hash parsing failed"),
-            };
+    // Claimed as a failure up front; if it can't be recorded, lockout can't be enforced,
+    // so nobody gets in.
+    let attempt = match claim_login_attempt(&db_pool, &gmail, &ip).await {
+        Ok(Some(attempt)) => attempt,
+        Ok(None) => {
+            return HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", (LOCKOUT_WINDOW_MINUTES * 60).to_string()))
+                .body("Too many failed login attempts, please try again later");
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to record login attempt: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    };
 
-            if Argon2::default().verify_password(password.as_bytes(), &parsed_hash).is_ok() {
-                HttpResponse::Ok().json(json!({
-                    "message": "Login successful",
-                    "user_id": user.id,
-                    "status": user.status
-                }))
-            } else {
-                HttpResponse::Unauthorized().body("Password does not match")
+    let user = match get_user_by_gmail(&db_pool, &gmail).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("❌ DB query error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    };
+
+    let stored_hash = user.as_ref().map(|u| u.hashed_password.as_str()).unwrap_or_else(|| dummy_hash());
+    let parsed_hash = match PasswordHash::new(stored_hash) {
+        Ok(hash) => hash,
+        Err(_) => return HttpResponse::InternalServerError().body("Password hash parsing failed"),
+    };
+    let password_ok = Argon2::default().verify_password(password.as_bytes(), &parsed_hash).is_ok();
+
+    let user = match user {
+        Some(user) if password_ok => user,
+        other => {
+            if let Some(user) = other {
+                if let Err(e) = finish_login_attempt(&db_pool, attempt, Some(user.id), false).await {
+                    eprintln!("❌ Failed to record login attempt: {:?}", e);
+                }
             }
+            return invalid_credentials();
+        }
+    };
+
+    // With TOTP enrolled the password only earns a short-lived challenge; the session,
+    // and the successful audit row, wait for the second factor.
+    match is_totp_enabled(&db_pool, user.id).await {
+        Ok(true) => {
+            if let Err(e) = release_login_attempt(&db_pool, attempt).await {
+                eprintln!("❌ Failed to release login attempt: {:?}", e);
+                return HttpResponse::InternalServerError().body("Database error");
+            }
+            return match create_login_challenge(&db_pool, user.id).await {
+                Ok(challenge) => HttpResponse::Ok().json(json!({
+                    "message": "Two-factor code required",
+                    "two_factor_required": true,
+                    "challenge": challenge
+                })),
+                Err(e) => {
+                    eprintln!("❌ Failed to create login challenge: {:?}", e);
+                    HttpResponse::InternalServerError().body("Database error")
+                }
+            };
         }
-        Ok(None) => HttpResponse::NotFound().body("No user found with that Gmail"),
+        Ok(false) => {}
         Err(e) => {
             eprintln!("❌ DB query error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    }
+
+    if let Err(e) = finish_login_attempt(&db_pool, attempt, Some(user.id), true).await {
+        eprintln!("❌ Failed to record login attempt: {:?}", e);
+        return HttpResponse::InternalServerError().body("Database error");
+    }
+
+    start_session(&db_pool, user.id, &user.status).await
+}
+
+async fn start_session(db_pool: &PgPool, user_id: i32, status: &str) -> HttpResponse {
+    let token = match create_session(db_pool, user_id).await {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("❌ Failed to create session: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    };
+
+    HttpResponse::Ok().json(json!({
+        "message": "Login successful",
+        "token": token,
+        "user_id": user_id,
+        "status": status
+    }))
+}
+
+pub async fn login_two_factor(
+    req: HttpRequest,
+    data: web::Json<TwoFactorLoginRequest>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let ip = client_ip(&req);
+
+    let (user_id, attempts) = match attempt_login_challenge(&db_pool, &data.challenge).await {
+        Ok(Some(found)) => found,
+        Ok(None) => return HttpResponse::Unauthorized().body("Invalid or expired login challenge"),
+        Err(e) => {
+            eprintln!("❌ DB query error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    };
+
+    if attempts > MAX_CHALLENGE_ATTEMPTS {
+        if let Err(e) = delete_login_challenge(&db_pool, &data.challenge).await {
+            eprintln!("❌ Failed to delete login challenge: {:?}", e);
+        }
+        return HttpResponse::TooManyRequests().body("Too many attempts, please log in again");
+    }
+
+    let (email, status) = match sqlx::query_as::<_, (String, String)>(
+        "SELECT email, status FROM logininfo WHERE id = $1"
+    )
+    .bind(user_id)
+    .fetch_one(db_pool.get_ref())
+    .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            eprintln!("❌ DB query error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    };
+
+    match verify_second_factor(&db_pool, user_id, &data.code).await {
+        Ok(true) => {}
+        Ok(false) => {
+            // Wrong codes count toward the same lockout as wrong passwords.
+            if let Err(e) = record_login_attempt(&db_pool, &email, Some(user_id), &ip, false).await {
+                eprintln!("❌ Failed to record login attempt: {:?}", e);
+                return HttpResponse::InternalServerError().body("Database error");
+            }
+            return HttpResponse::Unauthorized().body("Invalid two-factor code");
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to verify two-factor code: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    }
+
+    if let Err(e) = delete_login_challenge(&db_pool, &data.challenge).await {
+        eprintln!("❌ Failed to delete login challenge: {:?}", e);
+    }
+
+    if let Err(e) = record_login_attempt(&db_pool, &email, Some(user_id), &ip, true).await {
+        eprintln!("❌ Failed to record login attempt: {:?}", e);
+        return HttpResponse::InternalServerError().body("Database error");
+    }
+
+    start_session(&db_pool, user_id, &status).await
+}
+
+pub async fn logout(
+    user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    match delete_session(&db_pool, &user.token).await {
+        Ok(()) => HttpResponse::Ok().json(json!({ "message": "Logged out" })),
+        Err(e) => {
+            eprintln!("❌ Failed to delete session: {:?}", e);
+            HttpResponse::InternalServerError().body("Database error")
+        }
+    }
+}
+
+pub async fn logout_all(
+    user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    match delete_user_sessions(&db_pool, user.id).await {
+        Ok(()) => HttpResponse::Ok().json(json!({ "message": "Logged out of all sessions" })),
+        Err(e) => {
+            eprintln!("❌ Failed to delete sessions: {:?}", e);
             HttpResponse::InternalServerError().body("Database error")
         }
     }
@@ -44,4 +242,193 @@ pub async fn login(
 
 pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.route("/login", web::post().to(login));
-}
\ No newline at end of file
+    cfg.route("/login/2fa", web::post().to(login_two_factor));
+    cfg.route("/logout", web::post().to(logout));
+    cfg.route("/logout/all", web::post().to(logout_all));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use crate::databases::auth::logindb::{MAX_FAILURES_PER_ACCOUNT, MAX_FAILURES_PER_IP};
+    use crate::testutil;
+
+    const PASSWORD: &str = "correct horse";
+
+    async fn customer(pool: &PgPool) -> (i32, String) {
+        let id = testutil::create_user(pool, "user").await;
+        let email: String = sqlx::query_scalar("UPDATE logininfo SET password = $2 WHERE id = $1 RETURNING email")
+            .bind(id)
+            .bind(hash_password(PASSWORD).unwrap())
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        (id, email)
+    }
+
+    fn login_request(email: &str, password: &str, forwarded_for: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/login")
+            .peer_addr("10.0.0.1:40000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", forwarded_for))
+            .set_json(json!({ "gmail": email, "password": password }))
+    }
+
+    fn app_config(trusted_proxy: bool) -> web::Data<Config> {
+        let mut config = testutil::test_config();
+        config.trusted_proxy = trusted_proxy;
+        web::Data::new(config)
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn account_locks_after_too_many_wrong_passwords(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(pool.clone())).app_data(app_config(false)).configure(init),
+        )
+        .await;
+        let (_, email) = customer(&pool).await;
+
+        for _ in 0..MAX_FAILURES_PER_ACCOUNT {
+            let res = test::call_service(&app, login_request(&email, "wrong", "1.1.1.1").to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        // Locked out, right password or not.
+        let res = test::call_service(&app, login_request(&email, PASSWORD, "1.1.1.1").to_request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers().get("Retry-After").unwrap(), &(LOCKOUT_WINDOW_MINUTES * 60).to_string());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn parallel_guesses_cannot_outrun_the_lockout(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(pool.clone())).app_data(app_config(false)).configure(init),
+        )
+        .await;
+        let (_, email) = customer(&pool).await;
+
+        let guesses = (0..=MAX_FAILURES_PER_ACCOUNT)
+            .map(|_| test::call_service(&app, login_request(&email, "wrong", "1.1.1.1").to_request()));
+        let statuses: Vec<StatusCode> = futures::future::join_all(guesses).await.iter().map(|r| r.status()).collect();
+
+        let refused = statuses.iter().filter(|&&s| s == StatusCode::TOO_MANY_REQUESTS).count();
+        let rejected = statuses.iter().filter(|&&s| s == StatusCode::UNAUTHORIZED).count();
+        assert_eq!((rejected as i64, refused), (MAX_FAILURES_PER_ACCOUNT, 1), "{:?}", statuses);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn login_is_refused_when_the_attempt_cannot_be_recorded(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(pool.clone())).app_data(app_config(false)).configure(init),
+        )
+        .await;
+        let (_, email) = customer(&pool).await;
+        sqlx::query("ALTER TABLE login_audit RENAME TO login_audit_gone").execute(&pool).await.unwrap();
+
+        let res = test::call_service(&app, login_request(&email, PASSWORD, "1.1.1.1").to_request()).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn a_right_password_clears_its_claim(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(pool.clone())).app_data(app_config(false)).configure(init),
+        )
+        .await;
+        let (id, email) = customer(&pool).await;
+
+        let res = test::call_service(&app, login_request(&email, "wrong", "1.1.1.1").to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = test::call_service(&app, login_request(&email, PASSWORD, "1.1.1.1").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let rows: Vec<(Option<i32>, bool)> = sqlx::query_as("SELECT user_id, success FROM login_audit ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, [(Some(id), false), (Some(id), true)]);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn address_locks_after_too_many_failures_across_accounts(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(pool.clone())).app_data(app_config(true)).configure(init),
+        )
+        .await;
+        let (_, email) = customer(&pool).await;
+
+        // One short of the limit, spread over accounts so none of them locks on its own.
+        for n in 1..MAX_FAILURES_PER_IP {
+            record_login_attempt(&pool, &format!("guess{}@example.com", n), None, "203.0.113.7", false)
+                .await
+                .unwrap();
+        }
+        let res = test::call_service(&app, login_request("guess@example.com", "wrong", "203.0.113.7").to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = test::call_service(&app, login_request(&email, PASSWORD, "203.0.113.7").to_request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // The same account from another address is still let in.
+        let res = test::call_service(&app, login_request(&email, PASSWORD, "198.51.100.2").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn forwarded_address_is_ignored_without_a_trusted_proxy(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(pool.clone())).app_data(app_config(false)).configure(init),
+        )
+        .await;
+        let (_, email) = customer(&pool).await;
+
+        let res = test::call_service(&app, login_request(&email, "wrong", "203.0.113.7").to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let ips: Vec<String> = sqlx::query_scalar("SELECT ip FROM login_audit").fetch_all(&pool).await.unwrap();
+        assert_eq!(ips, ["10.0.0.1"]);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn two_factor_challenge_allows_limited_attempts(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(pool.clone())).app_data(app_config(false)).configure(init),
+        )
+        .await;
+        let (id, email) = customer(&pool).await;
+        testutil::enroll_totp(&pool, id).await;
+
+        let res = test::call_service(&app, login_request(&email, PASSWORD, "1.1.1.1").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["two_factor_required"], true);
+        assert!(body.get("token").is_none());
+        let challenge = body["challenge"].as_str().unwrap().to_string();
+
+        let attempt = || {
+            test::TestRequest::post()
+                .uri("/login/2fa")
+                .set_json(json!({ "challenge": challenge, "code": "000000" }))
+                .to_request()
+        };
+
+        for _ in 0..MAX_CHALLENGE_ATTEMPTS {
+            assert_eq!(test::call_service(&app, attempt()).await.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert_eq!(test::call_service(&app, attempt()).await.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // The spent challenge is gone; the password has to be entered again.
+        let res = test::call_service(&app, attempt()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(test::read_body(res).await, "Invalid or expired login challenge");
+    }
+}
@@ -1,8 +1,13 @@
 pub mod auth;
 pub mod verify;
 pub mod login;
+pub mod password;
+pub mod twofactor;
 pub mod user;
 pub mod admin;
 pub mod chats;
 pub mod product;
-pub mod payment;
\ No newline at end of file
+pub mod payment;
+pub mod cart;
+pub mod orders;
+pub mod returns;
\ No newline at end of file
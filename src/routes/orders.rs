@@ -0,0 +1,96 @@
+use actix_web::{web, HttpResponse, Responder};
+use sqlx::PgPool;
+use serde::Serialize;
+use num_traits::ToPrimitive;
+use crate::databases::shop::orderdb::{get_order_items, get_user_order, list_user_orders, Order, OrderItem};
+use crate::services::session::AuthUser;
+
+#[derive(Serialize)]
+pub struct OrderItemResponse {
+    laptop_id: i32,
+    display_name: String,
+    face_image_url: Option<String>,
+    quantity: i32,
+    unit_price: f64,
+}
+
+#[derive(Serialize)]
+pub struct OrderResponse {
+    id: i32,
+    order_ref: String,
+    status: String,
+    total: f64,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    items: Option<Vec<OrderItemResponse>>,
+}
+
+impl OrderResponse {
+    pub fn new(order: Order, items: Option<Vec<OrderItem>>) -> Self {
+        OrderResponse {
+            id: order.id,
+            order_ref: order.order_ref,
+            status: order.status,
+            total: order.total_paisa as f64 / 100.0,
+            created_at: order.created_at,
+            updated_at: order.updated_at,
+            items: items.map(|items| {
+                items
+                    .into_iter()
+                    .map(|item| OrderItemResponse {
+                        unit_price: item.unit_price.to_f64().unwrap_or(0.0),
+                        laptop_id: item.laptop_id,
+                        display_name: item.display_name,
+                        face_image_url: item.face_image_url,
+                        quantity: item.quantity,
+                    })
+                    .collect()
+            }),
+        }
+    }
+}
+
+pub async fn my_orders(
+    user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    match list_user_orders(&db_pool, user.id).await {
+        Ok(orders) => {
+            let orders: Vec<OrderResponse> = orders.into_iter().map(|o| OrderResponse::new(o, None)).collect();
+            HttpResponse::Ok().json(orders)
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to fetch orders: {:?}", e);
+            HttpResponse::InternalServerError().body("Database error")
+        }
+    }
+}
+
+pub async fn my_order(
+    user: AuthUser,
+    path: web::Path<i32>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let order = match get_user_order(&db_pool, path.into_inner(), user.id).await {
+        Ok(Some(order)) => order,
+        Ok(None) => return HttpResponse::NotFound().body("Order not found"),
+        Err(e) => {
+            eprintln!("❌ Failed to fetch order: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    };
+
+    match get_order_items(&db_pool, order.id).await {
+        Ok(items) => HttpResponse::Ok().json(OrderResponse::new(order, Some(items))),
+        Err(e) => {
+            eprintln!("❌ Failed to fetch order items: {:?}", e);
+            HttpResponse::InternalServerError().body("Database error")
+        }
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.route("/api/orders", web::get().to(my_orders));
+    cfg.route("/api/orders/{id}", web::get().to(my_order));
+}
@@ -0,0 +1,240 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use sqlx::PgPool;
+use serde::Deserialize;
+use serde_json::json;
+use crate::databases::auth::logindb::{get_user_by_gmail, hash_password};
+use crate::databases::auth::resetdb::{
+    claim_reset_request, consume_reset_token, create_reset_token, delete_user_reset_tokens, RESET_WINDOW_MINUTES,
+};
+use crate::databases::auth::sessiondb::delete_user_sessions;
+use crate::services::email::send_password_reset_email;
+use crate::config::Config;
+use crate::routes::login::client_ip;
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+pub async fn forgot_password(
+    req: HttpRequest,
+    data: web::Json<ForgotPasswordRequest>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let email = data.email.trim().to_string();
+
+    // Counted whether or not the account exists, so being throttled gives nothing away either.
+    match claim_reset_request(&db_pool, &email, &client_ip(&req)).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", (RESET_WINDOW_MINUTES * 60).to_string()))
+                .body("Too many reset requests, please try again later");
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to record reset request: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    }
+
+    // The lookup and the email happen after we answer, so the answer is the same, and takes
+    // as long, whether or not the account exists; this can't be used to probe emails.
+    let (db_pool, config) = (db_pool.clone(), config.clone());
+    tokio::spawn(async move { send_reset_link(&db_pool, &config, &email).await });
+
+    HttpResponse::Ok().json(json!({
+        "message": "If that email is registered, a reset link has been sent"
+    }))
+}
+
+async fn send_reset_link(db_pool: &PgPool, config: &Config, email: &str) {
+    let user = match get_user_by_gmail(db_pool, email).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("❌ DB query error: {:?}", e);
+            return;
+        }
+    };
+
+    let token = match create_reset_token(db_pool, user.id).await {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("❌ Failed to store reset token: {:?}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = send_password_reset_email(config, email, &token).await {
+        eprintln!("❌ Failed to send reset email: {}", e);
+    }
+}
+
+pub async fn reset_password(
+    data: web::Json<ResetPasswordRequest>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    if data.password.is_empty() {
+        return HttpResponse::BadRequest().body("Password required");
+    }
+
+    let user_id = match consume_reset_token(&db_pool, &data.token).await {
+        Ok(Some(id)) => id,
+        Ok(None) => return HttpResponse::BadRequest().body("Invalid or expired reset link"),
+        Err(e) => {
+            eprintln!("❌ DB query error: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    };
+
+    let hashed_password = match hash_password(&data.password) {
+        Ok(hash) => hash,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Hashing error: {}", e)),
+    };
+
+    if let Err(e) = sqlx::query("UPDATE logininfo SET password = $1 WHERE id = $2")
+        .bind(&hashed_password)
+        .bind(user_id)
+        .execute(db_pool.get_ref())
+        .await
+    {
+        eprintln!("❌ Failed to update password: {:?}", e);
+        return HttpResponse::InternalServerError().body("Database error");
+    }
+
+    // Old links and every signed-in device are invalid once the password changes.
+    if let Err(e) = delete_user_reset_tokens(&db_pool, user_id).await {
+        eprintln!("❌ Failed to clear reset tokens: {:?}", e);
+    }
+    if let Err(e) = delete_user_sessions(&db_pool, user_id).await {
+        eprintln!("❌ Failed to revoke sessions: {:?}", e);
+        return HttpResponse::InternalServerError().body("Database error");
+    }
+
+    HttpResponse::Ok().json(json!({
+        "message": "Password has been reset, please log in again"
+    }))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.route("/password/forgot", web::post().to(forgot_password));
+    cfg.route("/password/reset", web::post().to(reset_password));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use argon2::{Argon2, PasswordHash, PasswordVerifier};
+    use std::time::Duration;
+    use crate::databases::auth::resetdb::MAX_RESETS_PER_EMAIL;
+    use crate::testutil;
+
+    async fn reset_tokens(pool: &PgPool) -> Vec<i32> {
+        sqlx::query_scalar("SELECT user_id FROM password_resets").fetch_all(pool).await.unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn unknown_emails_get_the_same_answer(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(testutil::test_config()))
+                .configure(init),
+        )
+        .await;
+        let user_id = testutil::create_user(&pool, "user").await;
+        let email: String = sqlx::query_scalar("SELECT email FROM logininfo WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+
+        let mut answers = Vec::new();
+        for email in [email.as_str(), "nobody@example.com"] {
+            let req = test::TestRequest::post().uri("/password/forgot").set_json(json!({ "email": email })).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            answers.push(test::read_body(res).await);
+        }
+        assert_eq!(answers[0], answers[1]);
+
+        // The link is made in the background, and only for the registered account.
+        for _ in 0..50 {
+            if !reset_tokens(&pool).await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(reset_tokens(&pool).await, [user_id]);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn forgot_password_is_throttled(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(testutil::test_config()))
+                .configure(init),
+        )
+        .await;
+        let request = || {
+            test::TestRequest::post()
+                .uri("/password/forgot")
+                .set_json(json!({ "email": "nobody@example.com" }))
+                .to_request()
+        };
+
+        for _ in 0..MAX_RESETS_PER_EMAIL {
+            assert_eq!(test::call_service(&app, request()).await.status(), StatusCode::OK);
+        }
+        let res = test::call_service(&app, request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers().get("Retry-After").unwrap(), &(RESET_WINDOW_MINUTES * 60).to_string());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn a_reset_signs_out_everywhere_and_spends_the_link(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(App::new().app_data(web::Data::new(pool.clone())).configure(init)).await;
+        let user_id = testutil::create_user(&pool, "user").await;
+        testutil::login(&pool, user_id).await;
+        testutil::login(&pool, user_id).await;
+        let token = create_reset_token(&pool, user_id).await.unwrap();
+        let reset = || {
+            test::TestRequest::post()
+                .uri("/password/reset")
+                .set_json(json!({ "token": token, "password": "new password" }))
+                .to_request()
+        };
+
+        assert_eq!(test::call_service(&app, reset()).await.status(), StatusCode::OK);
+
+        let sessions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(sessions, 0);
+        let stored: String = sqlx::query_scalar("SELECT password FROM logininfo WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(Argon2::default().verify_password(b"new password", &PasswordHash::new(&stored).unwrap()).is_ok());
+
+        let res = test::call_service(&app, reset()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(test::read_body(res).await, "Invalid or expired reset link");
+    }
+}
@@ -1,2 +1,2 @@
-pub mod khalti;
+pub mod checkout;
 pub mod verifypay;
\ No newline at end of file
@@ -0,0 +1,322 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::Deserialize;
+use sqlx::PgPool;
+use crate::services::session::AuthUser;
+use crate::config::Config;
+use crate::databases::shop::cartdb::{get_cart, single_item, total_paisa, CartLine};
+use crate::databases::shop::orderdb::OrderStatus;
+use crate::databases::shop::stockdb::{OutOfStock, RESERVATION_TTL_MINUTES};
+use crate::databases::payment::coddb::{place_cod_order, CodCheckout};
+use crate::databases::payment::paymentdb::{
+    apply_transition, create_payment, set_gateway_ref, PaymentStatus,
+};
+use crate::services::email::send_order_status_email;
+use crate::services::gateway::{CheckoutRequest, CustomerInfo, Gateways};
+
+/// Checks out the caller's cart, or just `product_id` for a one-off "buy now". The amount
+/// charged always comes from `laptop_details`, never from the client.
+#[derive(Deserialize, Default)]
+pub struct InitiatePaymentRequest {
+    product_id: Option<String>,
+}
+
+/// Starts a payment with `gateway` (`khalti` or `esewa`). The response tells the frontend
+/// where to send the customer: Khalti's `payment_url`, or eSewa's form URL and fields.
+#[post("/api/payment/{gateway}/initiate")]
+pub async fn initiate_payment(
+    user: AuthUser,
+    path: web::Path<String>,
+    payload: Option<web::Json<InitiatePaymentRequest>>,
+    db: web::Data<PgPool>,
+    gateways: web::Data<Gateways>,
+) -> impl Responder {
+    let Some(gateway) = gateways.get(&path) else {
+        return HttpResponse::NotFound().body("Unknown payment gateway");
+    };
+    let payload = payload.map(|p| p.into_inner()).unwrap_or_default();
+
+    let (lines, amount_paisa) = match checkout_lines(db.get_ref(), user.id, &payload).await {
+        Ok(checkout) => checkout,
+        Err(response) => return response,
+    };
+
+    let product_name = match lines.as_slice() {
+        [line] => line.display_name.clone(),
+        [first, rest @ ..] => format!("{} and {} more", first.display_name, rest.len()),
+        [] => unreachable!(),
+    };
+
+    // Unique per attempt, so a gateway callback can be matched to exactly one payment.
+    let order_ref = format!("EP-{}", uuid::Uuid::new_v4().simple());
+
+    let customer_info = match sqlx::query_as::<_, CustomerInfo>(
+        "SELECT name, email, phoneNumber AS phone FROM logininfo WHERE id = $1"
+    )
+    .bind(user.id)
+    .fetch_one(db.get_ref())
+    .await
+    {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("Failed to load customer info: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to initiate payment");
+        }
+    };
+
+    let payment = match create_payment(
+        db.get_ref(),
+        user.id,
+        gateway.name(),
+        &order_ref,
+        &lines,
+        amount_paisa,
+        Some(RESERVATION_TTL_MINUTES),
+    )
+    .await
+    {
+        Ok(Ok(payment)) => payment,
+        Ok(Err(out_of_stock)) => return out_of_stock_response(&out_of_stock),
+        Err(e) => {
+            eprintln!("Failed to record payment: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to initiate payment");
+        }
+    };
+
+    let request = CheckoutRequest {
+        order_ref: &order_ref,
+        amount_paisa,
+        product_name: &product_name,
+        customer: &customer_info,
+    };
+
+    let checkout = match gateway.initiate(&request).await {
+        Ok(checkout) => checkout,
+        Err(err) => {
+            eprintln!("❌ {} initiate failed: {:?}", gateway.name(), err);
+            fail_payment(db.get_ref(), payment.id, &format!("{} initiate failed", gateway.name())).await;
+            return HttpResponse::BadGateway().body("Failed to initiate payment");
+        }
+    };
+
+    let pending = async {
+        set_gateway_ref(db.get_ref(), payment.id, &checkout.gateway_ref).await?;
+        apply_transition(db.get_ref(), payment.id, PaymentStatus::Pending, None).await
+    };
+    if let Err(e) = pending.await {
+        eprintln!("Failed to store gateway ref for payment {}: {:?}", payment.id, e);
+        return HttpResponse::InternalServerError().body("Failed to initiate payment");
+    }
+
+    let mut response = checkout.response;
+    response["purchase_order_id"] = order_ref.into();
+    HttpResponse::Ok().json(response)
+}
+
+/// Places a cash-on-delivery order for the cart or `product_id`. No gateway is involved:
+/// the order is placed at once and its stock held until an admin records the delivery.
+#[post("/api/payment/cod/initiate")]
+pub async fn initiate_cod_payment(
+    user: AuthUser,
+    payload: Option<web::Json<InitiatePaymentRequest>>,
+    db: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let payload = payload.map(|p| p.into_inner()).unwrap_or_default();
+
+    let (lines, amount_paisa) = match checkout_lines(db.get_ref(), user.id, &payload).await {
+        Ok(checkout) => checkout,
+        Err(response) => return response,
+    };
+
+    let order_ref = format!("EP-{}", uuid::Uuid::new_v4().simple());
+
+    let placed = place_cod_order(
+        db.get_ref(),
+        user.id,
+        &order_ref,
+        &lines,
+        amount_paisa,
+        config.cod.default_limit_paisa,
+    )
+    .await;
+
+    let order_id = match placed {
+        Ok(CodCheckout::Placed { order_id }) => order_id,
+        Ok(CodCheckout::OverLimit { limit_paisa, outstanding_paisa }) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({
+                "message": "This order is over your cash on delivery limit. Please pay online instead.",
+                "limit_paisa": limit_paisa,
+                "outstanding_paisa": outstanding_paisa
+            }));
+        }
+        Ok(CodCheckout::OutOfStock(out_of_stock)) => return out_of_stock_response(&out_of_stock),
+        Err(e) => {
+            eprintln!("Failed to place COD order: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to place order");
+        }
+    };
+
+    let email = sqlx::query_scalar::<_, String>("SELECT email FROM logininfo WHERE id = $1")
+        .bind(user.id)
+        .fetch_one(db.get_ref())
+        .await;
+    match email {
+        Ok(email) => {
+            if let Err(e) = send_order_status_email(&config, &email, &order_ref, OrderStatus::Placed).await {
+                eprintln!("Failed to send order status email: {}", e);
+            }
+        }
+        Err(e) => eprintln!("Failed to look up customer email: {}", e),
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "message": "Order placed",
+        "purchase_order_id": order_ref,
+        "order_id": order_id
+    }))
+}
+
+/// The lines being bought and their total in paisa, or the response to send instead.
+async fn checkout_lines(
+    db: &PgPool,
+    user_id: i32,
+    payload: &InitiatePaymentRequest,
+) -> Result<(Vec<CartLine>, i64), HttpResponse> {
+    let lines = match &payload.product_id {
+        Some(product_id) => {
+            let Ok(laptop_id) = product_id.parse::<i32>() else {
+                return Err(HttpResponse::BadRequest().body("Invalid product id"));
+            };
+            match single_item(db, laptop_id, 1).await {
+                Ok(Some(line)) => Ok(vec![line]),
+                Ok(None) => return Err(HttpResponse::NotFound().body("Product not found")),
+                Err(e) => Err(e),
+            }
+        }
+        None => get_cart(db, user_id).await,
+    };
+
+    let lines = match lines {
+        Ok(lines) if lines.is_empty() => return Err(HttpResponse::BadRequest().body("Your cart is empty")),
+        Ok(lines) => lines,
+        Err(e) => {
+            eprintln!("Failed to load items for payment: {:?}", e);
+            return Err(HttpResponse::InternalServerError().body("Failed to initiate payment"));
+        }
+    };
+
+    if let Some(line) = lines.iter().find(|l| l.quantity > l.stock) {
+        return Err(out_of_stock_response(&OutOfStock {
+            laptop_id: line.laptop_id,
+            display_name: line.display_name.clone(),
+            available: line.stock,
+        }));
+    }
+
+    let Some(amount_paisa) = total_paisa(&lines) else {
+        return Err(HttpResponse::InternalServerError().body("Failed to initiate payment"));
+    };
+
+    Ok((lines, amount_paisa))
+}
+
+fn out_of_stock_response(out_of_stock: &OutOfStock) -> HttpResponse {
+    let message = if out_of_stock.available > 0 {
+        format!("Only {} of {} left in stock", out_of_stock.available, out_of_stock.display_name)
+    } else {
+        format!("{} is out of stock", out_of_stock.display_name)
+    };
+
+    HttpResponse::Conflict().json(serde_json::json!({
+        "message": message,
+        "laptop_id": out_of_stock.laptop_id,
+        "available": out_of_stock.available
+    }))
+}
+
+async fn fail_payment(db: &PgPool, payment_id: i32, reason: &str) {
+    if let Err(e) = apply_transition(db, payment_id, PaymentStatus::Failed, Some(reason)).await {
+        eprintln!("Failed to mark payment {} as failed: {:?}", payment_id, e);
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    // Registered first so it takes `/cod/` before the gateway route does.
+    cfg.service(initiate_cod_payment);
+    cfg.service(initiate_payment);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use crate::databases::shop::cartdb::add_to_cart;
+    use crate::testutil;
+
+    #[sqlx::test(migrations = false)]
+    async fn a_two_line_cart_is_charged_at_server_prices(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let config = testutil::mock_khalti_config();
+        let khalti_url = config.khalti.url.clone();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Gateways::from_config(&config)))
+                .app_data(web::Data::new(config))
+                .configure(init),
+        )
+        .await;
+        let user = testutil::create_user(&pool, "user").await;
+        let token = testutil::login(&pool, user).await;
+        let first = testutil::create_laptop(&pool, "Acme", "First", 5).await;
+        let second = testutil::create_laptop(&pool, "Acme", "Second", 5).await;
+        sqlx::query("UPDATE laptop_details SET cost_price = 2000 WHERE id = $1")
+            .bind(second)
+            .execute(&pool)
+            .await
+            .unwrap();
+        add_to_cart(&pool, user, first, 2).await.unwrap();
+        add_to_cart(&pool, user, second, 1).await.unwrap();
+
+        // Anything the client says about the price is ignored.
+        let req = test::TestRequest::post()
+            .uri("/api/payment/khalti/initiate")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(serde_json::json!({ "amount": 1000, "total": 10 }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let initiated: serde_json::Value = test::read_body_json(resp).await;
+
+        // 2 × Rs. 1180 + 1 × Rs. 2360
+        let expected_paisa = 472_000i64;
+        let (payment_id, amount_paisa): (i32, i64) =
+            sqlx::query_as("SELECT id, amount_paisa FROM payments WHERE order_ref = $1")
+                .bind(initiated["purchase_order_id"].as_str().unwrap())
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(amount_paisa, expected_paisa);
+
+        let items: Vec<(i32, i32)> =
+            sqlx::query_as("SELECT laptop_id, quantity FROM payment_items WHERE payment_id = $1 ORDER BY laptop_id")
+                .bind(payment_id)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(items, vec![(first, 2), (second, 1)]);
+
+        let lookup: serde_json::Value = reqwest::Client::new()
+            .post(format!("{}/epayment/lookup/", khalti_url))
+            .header("Authorization", "Key test")
+            .json(&serde_json::json!({ "pidx": initiated["pidx"] }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(lookup["total_amount"], expected_paisa);
+    }
+}
@@ -1,50 +1,132 @@
 use actix_web::{post, web, HttpResponse, Responder};
 use serde::Deserialize;
 use sqlx::PgPool;
+use crate::services::session::AuthUser;
+use crate::config::{Config, SmtpConfig};
+use crate::databases::payment::paymentdb::{
+    find_user_payment, transition_payment, Payment, PaymentStatus, Transition,
+};
+use crate::databases::shop::cartdb::remove_paid_items;
+use crate::databases::shop::orderdb::{create_order_from_payment, OrderStatus};
+use crate::services::email::{send_email, send_order_status_email};
+use crate::services::gateway::{GatewayCallback, Gateways};
+use crate::services::reconciliation::{reconcile_payment, Reconciled};
+use crate::services::gateway::khalti::KHALTI_GATEWAY;
 
+/// The query parameters the gateway redirected the customer back with, passed on by the
+/// frontend: `pidx` and `purchase_order_id` for Khalti, `data` for eSewa.
 #[derive(Deserialize)]
 pub struct VerifyPaymentRequest {
-    pidx: String,
-    status: String,
+    #[serde(default = "default_gateway")]
+    gateway: String,
+    #[serde(flatten)]
+    callback: GatewayCallback,
 }
 
+fn default_gateway() -> String {
+    KHALTI_GATEWAY.to_string()
+}
+
+/// Confirms a payment with its gateway; whatever status the client reports is ignored.
+/// The payment only moves forward through its state machine, so repeating the call is
+/// harmless and a sale is recorded at most once.
 #[post("/api/payment/verify")]
 pub async fn verify_payment(
+    user: AuthUser,
     data: web::Json<VerifyPaymentRequest>,
     db: web::Data<PgPool>,
+    config: web::Data<Config>,
+    gateways: web::Data<Gateways>,
 ) -> impl Responder {
-    let result = sqlx::query!(
-    "SELECT email, laptop_id FROM khalti_temp_payments WHERE pidx = $1",
-    data.pidx
-)
-.fetch_optional(db.get_ref())
-.await;
-
-    match result {
-        Ok(Some(row)) => {
-            let email = row.email;
-            let laptop_id: i32 = row.laptop_id.parse::<i32>()
-    .expect("Failed to parse laptop_id as i32");
-            // email-sending logic
-            if let Err(err) = send_payment_status_email(&email, &data.status).await {
-                eprintln!("Failed to send email: {}", err);
-            }
-            //store database
-            if data.status == "Completed" {
-            if let Err(err) = record_laptop_sale(db.get_ref(), laptop_id).await {
-                eprintln!("Failed to record laptop sale: {}", err);
-            }
-        }
+    let Some(gateway) = gateways.get(&data.gateway) else {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "message": "Unknown payment gateway"
+        }));
+    };
 
-            HttpResponse::Ok().json(serde_json::json!({
-                "message": "Payment verified"
-            }))
+    let callback = match gateway.read_callback(&data.callback) {
+        Ok(callback) => callback,
+        Err(reason) => {
+            eprintln!("❌ Rejected {} callback: {}", gateway.name(), reason);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "message": "Invalid payment callback"
+            }));
         }
-        Ok(None) => HttpResponse::BadRequest().json(serde_json::json!({
+    };
+
+    let payment = match find_user_payment(db.get_ref(), gateway.name(), &callback.order_ref, user.id).await {
+        Ok(Some(payment)) => payment,
+        Ok(None) => return HttpResponse::BadRequest().json(serde_json::json!({
             "message": "Invalid or expired payment link"
         })),
         Err(err) => {
             eprintln!("DB error: {}", err);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "message": "Internal server error"
+            }));
+        }
+    };
+
+    if callback.gateway_ref.is_some() && callback.gateway_ref != payment.gateway_ref {
+        eprintln!("❌ Gateway reference mismatch for order {}", payment.order_ref);
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "message": "Payment does not match this order"
+        }));
+    }
+
+    if payment.status().is_final() {
+        return HttpResponse::Ok().json(serde_json::json!({
+            "message": "Payment already verified",
+            "status": payment.status()
+        }));
+    }
+
+    match reconcile_payment(db.get_ref(), &config, gateway, &payment).await {
+        Ok(Reconciled::Settled { status, order_id }) => HttpResponse::Ok().json(serde_json::json!({
+            "message": "Payment verified",
+            "status": status,
+            "order_id": order_id
+        })),
+        // Another request for the same payment got there first.
+        Ok(Reconciled::Unchanged(status)) => HttpResponse::Ok().json(serde_json::json!({
+            "message": "Payment already verified",
+            "status": status
+        })),
+        Ok(Reconciled::Rejected(current)) => HttpResponse::Conflict().json(serde_json::json!({
+            "message": "Payment is already settled",
+            "status": current
+        })),
+        Ok(Reconciled::StillPending { .. }) => HttpResponse::Accepted().json(serde_json::json!({
+            "message": "Payment not completed yet",
+            "status": payment.status()
+        })),
+        Ok(Reconciled::NeedsReview { gateway_status }) => {
+            eprintln!(
+                "❌ Unhandled {} status '{}' for order {}",
+                gateway.name(), gateway_status, payment.order_ref
+            );
+            HttpResponse::Accepted().json(serde_json::json!({
+                "message": "Payment needs manual review",
+                "status": payment.status()
+            }))
+        }
+        Ok(Reconciled::AmountMismatch { expected, got }) => {
+            eprintln!(
+                "❌ {} amount mismatch for order {}: expected {} paisa, got {}",
+                gateway.name(), payment.order_ref, expected, got
+            );
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "message": "Paid amount does not match the product price"
+            }))
+        }
+        Ok(Reconciled::LookupFailed(err)) => {
+            eprintln!("{} lookup failed: {}", gateway.name(), err);
+            HttpResponse::BadGateway().json(serde_json::json!({
+                "message": "Could not confirm payment with the payment gateway"
+            }))
+        }
+        Err(err) => {
+            eprintln!("Failed to record payment: {:?}", err);
             HttpResponse::InternalServerError().json(serde_json::json!({
                 "message": "Internal server error"
             }))
@@ -52,74 +134,194 @@ pub async fn verify_payment(
     }
 }
 
-use anyhow::{Result, Context};
-use lettre::{Message, SmtpTransport, Transport, message::Mailbox};
-use std::env;
+/// Applies a final status and, for a completed payment, places the order and records
+/// the sale in the same transaction, so they exist exactly when the payment says it
+/// does. Returns the new order's id when one was placed.
+pub async fn settle_payment(
+    db: &PgPool,
+    payment: &Payment,
+    next: PaymentStatus,
+    transaction_id: Option<&str>,
+    reason: &str,
+) -> Result<(Transition, Option<i32>)> {
+    let mut tx = db.begin().await?;
+
+    let transition = transition_payment(&mut tx, payment.id, next, transaction_id, Some(reason)).await?;
 
-pub async fn send_payment_status_email(email: &str, status: &str) -> Result<()> {
+    let mut order_id = None;
+    if matches!(transition, Transition::Applied) && next == PaymentStatus::Completed {
+        let id = create_order_from_payment(&mut tx, payment.id).await?;
+        record_laptop_sale(&mut tx, payment.id, id).await?;
+        remove_paid_items(&mut tx, payment.user_id, payment.id).await?;
+        order_id = Some(id);
+    }
 
-    let smtp_email = env::var("SMTP_EMAIL").context("Missing SMTP_EMAIL")?;
-    let smtp_password = env::var("SMTP_PASSWORD").context("Missing SMTP_PASSWORD")?;
-    let smtp_server = env::var("SMTP_SERVER").context("Missing SMTP_SERVER")?;
+    tx.commit().await?;
+    Ok((transition, order_id))
+}
+
+/// A completed payment is announced as the placed order; anything else gets the
+/// payment status email.
+pub async fn notify_payment_status(db: &PgPool, config: &Config, user_id: i32, order_ref: &str, status: PaymentStatus) {
+    let email = sqlx::query_scalar::<_, String>("SELECT email FROM logininfo WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(db)
+        .await;
+
+    let result = match email {
+        Ok(email) if status == PaymentStatus::Completed => {
+            send_order_status_email(config, &email, order_ref, OrderStatus::Placed)
+                .await
+                .map_err(|e| anyhow::anyhow!("{}", e))
+        }
+        Ok(email) => send_payment_status_email(&config.smtp, &email, status).await,
+        Err(e) => Err(e.into()),
+    };
+    if let Err(err) = result {
+        eprintln!("Failed to send email: {}", err);
+    }
+}
+
+use anyhow::{Result, Context};
+use lettre::{Message, message::Mailbox};
+
+pub async fn send_payment_status_email(smtp: &SmtpConfig, email: &str, status: PaymentStatus) -> Result<()> {
 
     let subject = "E-Pasal Payment Status";
     let body = match status {
-        "Completed" => "Your order is set to depart soon.",
-        "User canceled" => "So sorry we could not make a deal.",
+        PaymentStatus::Completed => "Your order is set to depart soon.",
+        PaymentStatus::UserCanceled => "So sorry we could not make a deal.",
+        PaymentStatus::Expired => "Your payment link expired before the payment was made.",
+        PaymentStatus::Failed => "Your payment could not be completed.",
+        PaymentStatus::Refunded => "Your payment has been refunded.",
         _ => "Undefined payment status.",
     };
 
     let email = Message::builder()
-        .from(smtp_email.parse::<Mailbox>()?)
+        .from(smtp.email.parse::<Mailbox>()?)
         .to(email.parse::<Mailbox>()?)
         .subject(subject)
         .body(String::from(body))?;
 
-    let creds = lettre::transport::smtp::authentication::Credentials::new(
-        smtp_email,
-        smtp_password,
-    );
-
-    let mailer = SmtpTransport::relay(&smtp_server)?
-        .credentials(creds)
-        .build();
-
-    mailer.send(&email).context("Failed to send email")?;
+    send_email(smtp, email).await.context("Failed to send email")?;
 
     Ok(())
 }
 
-pub async fn record_laptop_sale(db: &PgPool, laptop_id: i32) -> Result<()> {
-    let mut tx = db.begin().await?;
-
-    let row = sqlx::query!(
-        "SELECT show_price FROM laptop_details WHERE id = $1",
-        laptop_id
-    )
-    .fetch_one(&mut *tx)
-    .await?;
-
-    let price = row.show_price;
-
-    sqlx::query!(
-        "INSERT INTO laptops_sold (laptop_id, price_at_sale) VALUES ($1, $2)",
-        laptop_id,
-        price
-    )
-    .execute(&mut *tx)
-    .await?;
-
+/// Writes one `laptops_sold` row per line of the payment, at the price it was charged.
+/// Stock was already taken out when the payment reserved it.
+pub async fn record_laptop_sale(tx: &mut sqlx::PgConnection, payment_id: i32, order_id: i32) -> Result<()> {
     sqlx::query!(
-        "UPDATE laptop_details SET quantity = quantity - 1 WHERE id = $1",
-        laptop_id
+        "INSERT INTO laptops_sold (laptop_id, quantity, price_at_sale, order_id)
+         SELECT laptop_id, quantity, unit_price, $2 FROM payment_items WHERE payment_id = $1",
+        payment_id,
+        order_id
     )
     .execute(&mut *tx)
     .await?;
 
-    tx.commit().await?;
     Ok(())
 }
 
 pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(verify_payment);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use std::collections::HashMap;
+    use crate::databases::payment::paymentdb::get_payment;
+    use crate::routes::payment::checkout;
+    use crate::testutil;
+
+    struct Case {
+        outcome: &'static str,
+        verify_status: StatusCode,
+        payment_after: PaymentStatus,
+        ordered: bool,
+        /// `stock_reservations.status` once verification is done.
+        reservation: &'static str,
+        quantity_after: i32,
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn checkout_against_mock_khalti(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let config = testutil::mock_khalti_config();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Gateways::from_config(&config)))
+                .app_data(web::Data::new(config))
+                .configure(checkout::init)
+                .configure(init),
+        )
+        .await;
+        let customer = testutil::create_user(&pool, "user").await;
+        let token = testutil::login(&pool, customer).await;
+        // Plays the customer's browser on the payment page, stopping at the redirect back.
+        let browser = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).build().unwrap();
+
+        let cases = [
+            Case { outcome: "completed", verify_status: StatusCode::OK, payment_after: PaymentStatus::Completed, ordered: true, reservation: "consumed", quantity_after: 2 },
+            Case { outcome: "pending", verify_status: StatusCode::ACCEPTED, payment_after: PaymentStatus::Pending, ordered: false, reservation: "held", quantity_after: 2 },
+            Case { outcome: "user_canceled", verify_status: StatusCode::OK, payment_after: PaymentStatus::UserCanceled, ordered: false, reservation: "released", quantity_after: 3 },
+            Case { outcome: "expired", verify_status: StatusCode::OK, payment_after: PaymentStatus::Expired, ordered: false, reservation: "released", quantity_after: 3 },
+            Case { outcome: "amount_mismatch", verify_status: StatusCode::BAD_REQUEST, payment_after: PaymentStatus::Pending, ordered: false, reservation: "held", quantity_after: 2 },
+        ];
+
+        for case in cases {
+            let laptop = testutil::create_laptop(&pool, "Acme", case.outcome, 3).await;
+
+            let req = test::TestRequest::post()
+                .uri("/api/payment/khalti/initiate")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .set_json(serde_json::json!({ "product_id": laptop.to_string() }))
+                .to_request();
+            let initiated: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            let payment_url = initiated["payment_url"].as_str().expect("payment_url");
+
+            let redirect = browser.get(format!("{}?outcome={}", payment_url, case.outcome)).send().await.unwrap();
+            assert_eq!(redirect.status(), reqwest::StatusCode::FOUND, "{}", case.outcome);
+            let location = reqwest::Url::parse(redirect.headers()["Location"].to_str().unwrap()).unwrap();
+            let params: HashMap<String, String> = location.query_pairs().into_owned().collect();
+
+            let req = test::TestRequest::post()
+                .uri("/api/payment/verify")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .set_json(serde_json::json!({
+                    "gateway": "khalti",
+                    "pidx": params["pidx"],
+                    "purchase_order_id": params["purchase_order_id"]
+                }))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), case.verify_status, "{}", case.outcome);
+
+            let payment_id: i32 = sqlx::query_scalar("SELECT id FROM payments WHERE order_ref = $1")
+                .bind(&params["purchase_order_id"])
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            let payment = get_payment(&pool, payment_id).await.unwrap().unwrap();
+            assert_eq!(payment.status(), case.payment_after, "{}", case.outcome);
+
+            let orders: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM orders WHERE payment_id = $1")
+                .bind(payment_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(orders, i64::from(case.ordered), "{}", case.outcome);
+
+            let reservation: String = sqlx::query_scalar("SELECT status FROM stock_reservations WHERE payment_id = $1")
+                .bind(payment_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(reservation, case.reservation, "{}", case.outcome);
+            assert_eq!(testutil::laptop_quantity(&pool, laptop).await, case.quantity_after, "{}", case.outcome);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use actix_web::{web, HttpResponse, Responder};
+use sqlx::PgPool;
+use serde::Deserialize;
+use crate::databases::shop::returndb::{create_return_request, list_user_returns, ReturnCreate};
+use crate::services::session::AuthUser;
+
+#[derive(Deserialize)]
+pub struct ReturnPayload {
+    reason: String,
+}
+
+pub async fn request_return(
+    user: AuthUser,
+    path: web::Path<i32>,
+    payload: web::Json<ReturnPayload>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let reason = payload.reason.trim();
+    if reason.is_empty() {
+        return HttpResponse::BadRequest().body("Please tell us why you are returning this order");
+    }
+
+    match create_return_request(&db_pool, path.into_inner(), user.id, reason).await {
+        Ok(ReturnCreate::Created(request)) => HttpResponse::Created().json(request),
+        Ok(ReturnCreate::OrderNotFound) => HttpResponse::NotFound().body("Order not found"),
+        Ok(ReturnCreate::NotDelivered(status)) => HttpResponse::Conflict().body(format!(
+            "Only delivered orders can be returned; this one is {}",
+            status.as_str()
+        )),
+        Ok(ReturnCreate::AlreadyRequested) => {
+            HttpResponse::Conflict().body("A return has already been requested for this order")
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to create return request: {:?}", e);
+            HttpResponse::InternalServerError().body("Database error")
+        }
+    }
+}
+
+pub async fn my_returns(
+    user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    match list_user_returns(&db_pool, user.id).await {
+        Ok(returns) => HttpResponse::Ok().json(returns),
+        Err(e) => {
+            eprintln!("❌ Failed to fetch returns: {:?}", e);
+            HttpResponse::InternalServerError().body("Database error")
+        }
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.route("/api/orders/{id}/return", web::post().to(request_return));
+    cfg.route("/api/returns", web::get().to(my_returns));
+}
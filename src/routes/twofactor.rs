@@ -0,0 +1,141 @@
+use actix_web::{web, HttpResponse, Responder};
+use sqlx::PgPool;
+use serde::Deserialize;
+use serde_json::json;
+use crate::databases::auth::totpdb::{
+    disable_totp, enable_totp, get_user_totp, mark_step_used, store_pending_secret, use_recovery_code,
+};
+use crate::services::session::AuthUser;
+use crate::config::Config;
+use crate::services::totp::{
+    current_unix_time, generate_recovery_codes, generate_secret,
+    normalize_recovery_code, otpauth_uri, verify_code,
+};
+
+#[derive(Deserialize)]
+pub struct TwoFactorCodeRequest {
+    pub code: String,
+}
+
+/// Accepts either a current TOTP code or an unused recovery code for an enrolled user.
+/// Both are single-use.
+pub async fn verify_second_factor(pool: &PgPool, user_id: i32, code: &str) -> Result<bool, sqlx::Error> {
+    let totp = match get_user_totp(pool, user_id).await? {
+        Some(totp) if totp.enabled => totp,
+        _ => return Ok(false),
+    };
+
+    if let Some(step) = verify_code(&totp.secret, code, current_unix_time()) {
+        return mark_step_used(pool, user_id, step).await;
+    }
+
+    use_recovery_code(pool, user_id, &normalize_recovery_code(code)).await
+}
+
+pub async fn enroll(
+    user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    if !user.is_admin() {
+        return HttpResponse::Forbidden().body("Two-factor authentication is only available for admin accounts");
+    }
+    if user.two_factor_enabled {
+        return HttpResponse::Conflict().body("Two-factor authentication is already enabled");
+    }
+
+    let email = match sqlx::query_scalar::<_, String>("SELECT email FROM logininfo WHERE id = $1")
+        .bind(user.id)
+        .fetch_one(db_pool.get_ref())
+        .await
+    {
+        Ok(email) => email,
+        Err(e) => {
+            eprintln!("❌ Failed to fetch user email: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    };
+
+    let secret = generate_secret();
+    if let Err(e) = store_pending_secret(&db_pool, user.id, &secret).await {
+        eprintln!("❌ Failed to store TOTP secret: {:?}", e);
+        return HttpResponse::InternalServerError().body("Database error");
+    }
+
+    HttpResponse::Ok().json(json!({
+        "secret": secret,
+        "otpauth_uri": otpauth_uri(&secret, &email)
+    }))
+}
+
+pub async fn confirm(
+    user: AuthUser,
+    data: web::Json<TwoFactorCodeRequest>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let totp = match get_user_totp(&db_pool, user.id).await {
+        Ok(Some(totp)) if !totp.enabled => totp,
+        Ok(Some(_)) => return HttpResponse::Conflict().body("Two-factor authentication is already enabled"),
+        Ok(None) => return HttpResponse::BadRequest().body("Start enrolment first"),
+        Err(e) => {
+            eprintln!("❌ Failed to fetch TOTP secret: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    };
+
+    let Some(step) = verify_code(&totp.secret, &data.code, current_unix_time()) else {
+        return HttpResponse::BadRequest().body("Invalid two-factor code");
+    };
+
+    let recovery_codes = generate_recovery_codes();
+    let result = match mark_step_used(&db_pool, user.id, step).await {
+        Ok(true) => enable_totp(&db_pool, user.id, &recovery_codes).await,
+        Ok(false) => return HttpResponse::BadRequest().body("Invalid two-factor code"),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        // Recovery codes are only ever shown here; the database keeps their hashes.
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "message": "Two-factor authentication enabled",
+            "recovery_codes": recovery_codes
+        })),
+        Err(e) => {
+            eprintln!("❌ Failed to enable TOTP: {:?}", e);
+            HttpResponse::InternalServerError().body("Database error")
+        }
+    }
+}
+
+pub async fn disable(
+    user: AuthUser,
+    data: web::Json<TwoFactorCodeRequest>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if user.is_admin() && config.admin.require_2fa {
+        return HttpResponse::Forbidden().body("Two-factor authentication is required for admin accounts");
+    }
+
+    match verify_second_factor(&db_pool, user.id, &data.code).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::BadRequest().body("Invalid two-factor code"),
+        Err(e) => {
+            eprintln!("❌ Failed to verify two-factor code: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    }
+
+    match disable_totp(&db_pool, user.id).await {
+        Ok(()) => HttpResponse::Ok().json(json!({ "message": "Two-factor authentication disabled" })),
+        Err(e) => {
+            eprintln!("❌ Failed to disable TOTP: {:?}", e);
+            HttpResponse::InternalServerError().body("Database error")
+        }
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.route("/account/2fa/enroll", web::post().to(enroll));
+    cfg.route("/account/2fa/confirm", web::post().to(confirm));
+    cfg.route("/account/2fa/disable", web::post().to(disable));
+}
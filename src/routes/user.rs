@@ -1,6 +1,17 @@
 use actix_web::{web, HttpResponse, Responder};
 use sqlx::{PgPool, FromRow};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use crate::databases::auth::logindb::hash_password;
+use crate::databases::auth::sessiondb::delete_other_sessions;
+use crate::databases::auth::tempdb::{
+    check_temp_code, email_taken_by_other, insert_email_change, number_taken_by_other, CodeCheck,
+};
+use crate::routes::verify::code_check_failure;
+use crate::services::email::send_code_email;
+use crate::config::Config;
+use crate::services::session::AuthUser;
 
 #[derive(Debug, Serialize, FromRow)]
 pub struct UserInfo {
@@ -11,10 +22,10 @@ pub struct UserInfo {
 }
 
 pub async fn get_user_info(
-    user_id: web::Path<i32>,
+    user: AuthUser,
     db_pool: web::Data<PgPool>,
 ) -> impl Responder {
-    let user_id = user_id.into_inner();
+    let user_id = user.id;
 
     let result = sqlx::query_as::<_, UserInfo>(
         r#"
@@ -41,6 +52,374 @@ pub async fn get_user_info(
     }
 }
 
+#[derive(Deserialize)]
+pub struct UpdateProfileRequest {
+    pub name: Option<String>,
+    pub number: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeEmailRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailRequest {
+    pub temp_id: String,
+    pub code: String,
+}
+
+pub async fn update_profile(
+    user: AuthUser,
+    data: web::Json<UpdateProfileRequest>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let current = match sqlx::query_as::<_, UserInfo>(
+        "SELECT id AS user_id, name, email, phoneNumber FROM logininfo WHERE id = $1"
+    )
+    .bind(user.id)
+    .fetch_one(db_pool.get_ref())
+    .await
+    {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("❌ Failed to fetch user info: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    };
+
+    let name = data.name.as_deref().map(str::trim).unwrap_or(&current.name).to_string();
+    let number = data.number.as_deref().map(str::trim).unwrap_or(&current.phonenumber).to_string();
+
+    if name.is_empty() || number.is_empty() {
+        return HttpResponse::BadRequest().body("Name and phone number cannot be empty");
+    }
+
+    match number_taken_by_other(&db_pool, &number, user.id).await {
+        Ok(true) => return HttpResponse::Conflict().body("Phone number already exists"),
+        Ok(false) => {}
+        Err(e) => {
+            eprintln!("❌ Error checking conflict field: {:?}", e);
+            return HttpResponse::InternalServerError().body("DB query failed");
+        }
+    }
+
+    let result = sqlx::query_as::<_, UserInfo>(
+        r#"
+        UPDATE logininfo SET name = $1, phoneNumber = $2
+        WHERE id = $3
+        RETURNING id AS user_id, name, email, phoneNumber
+        "#
+    )
+    .bind(&name)
+    .bind(&number)
+    .bind(user.id)
+    .fetch_one(db_pool.get_ref())
+    .await;
+
+    match result {
+        Ok(updated) => HttpResponse::Ok().json(updated),
+        // Someone else took the number between the check and the update.
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().body("Phone number already exists")
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to update profile: {:?}", e);
+            HttpResponse::InternalServerError().body("Database error")
+        }
+    }
+}
+
+pub async fn change_password(
+    user: AuthUser,
+    data: web::Json<ChangePasswordRequest>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    if data.new_password.is_empty() {
+        return HttpResponse::BadRequest().body("New password required");
+    }
+
+    let stored_hash = match sqlx::query_scalar::<_, String>("SELECT password FROM logininfo WHERE id = $1")
+        .bind(user.id)
+        .fetch_one(db_pool.get_ref())
+        .await
+    {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("❌ Failed to fetch password: {:?}", e);
+            return HttpResponse::InternalServerError().body("Database error");
+        }
+    };
+
+    let parsed_hash = match PasswordHash::new(&stored_hash) {
+        Ok(hash) => hash,
+        Err(_) => return HttpResponse::InternalServerError().body("Password hash parsing failed"),
+    };
+
+    if Argon2::default().verify_password(data.current_password.as_bytes(), &parsed_hash).is_err() {
+        return HttpResponse::Unauthorized().body("Current password does not match");
+    }
+
+    let hashed_password = match hash_password(&data.new_password) {
+        Ok(hash) => hash,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Hashing error: {}", e)),
+    };
+
+    if let Err(e) = sqlx::query("UPDATE logininfo SET password = $1 WHERE id = $2")
+        .bind(&hashed_password)
+        .bind(user.id)
+        .execute(db_pool.get_ref())
+        .await
+    {
+        eprintln!("❌ Failed to update password: {:?}", e);
+        return HttpResponse::InternalServerError().body("Database error");
+    }
+
+    // Keep the session that made the change, sign out everywhere else.
+    if let Err(e) = delete_other_sessions(&db_pool, user.id, &user.token).await {
+        eprintln!("❌ Failed to revoke sessions: {:?}", e);
+    }
+
+    HttpResponse::Ok().json(json!({ "message": "Password changed" }))
+}
+
+pub async fn request_email_change(
+    user: AuthUser,
+    data: web::Json<ChangeEmailRequest>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let email = data.email.trim();
+    if email.is_empty() {
+        return HttpResponse::BadRequest().body("Email required");
+    }
+
+    match email_taken_by_other(&db_pool, email, user.id).await {
+        Ok(true) => return HttpResponse::Conflict().body("Email already exists"),
+        Ok(false) => {}
+        Err(e) => {
+            eprintln!("❌ Error checking conflict field: {:?}", e);
+            return HttpResponse::InternalServerError().body("DB query failed");
+        }
+    }
+
+    match insert_email_change(&db_pool, user.id, email).await {
+        Ok(temp_user) => {
+            if let Err(e) = send_code_email(&config, &temp_user.email, &temp_user.code).await {
+                return HttpResponse::InternalServerError().body(format!("Email failed: {}", e));
+            }
+
+            HttpResponse::Ok().json(json!({
+                "temp_id": temp_user.temp_id
+            }))
+        }
+        Err(e) => HttpResponse::InternalServerError().body(format!("DB insert failed: {}", e)),
+    }
+}
+
+pub async fn verify_email_change(
+    user: AuthUser,
+    data: web::Json<VerifyEmailRequest>,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let pending = match check_temp_code(db_pool.get_ref(), &data.temp_id, &data.code, Some(user.id)).await {
+        Ok(CodeCheck::Valid(pending)) => pending,
+        Ok(other) => return code_check_failure(other),
+        Err(e) => {
+            eprintln!("❌ Error querying temp_users: {:?}", e);
+            return HttpResponse::InternalServerError().body("DB query error");
+        }
+    };
+
+    let result = sqlx::query("UPDATE logininfo SET email = $1 WHERE id = $2")
+        .bind(&pending.gmail)
+        .bind(user.id)
+        .execute(db_pool.get_ref())
+        .await;
+
+    if let Err(e) = sqlx::query("DELETE FROM temp_users WHERE temp_id = $1")
+        .bind(&data.temp_id)
+        .execute(db_pool.get_ref())
+        .await
+    {
+        eprintln!("❌ Error deleting from temp_users: {:?}", e);
+    }
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "Email updated",
+            "email": pending.gmail
+        })),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().body("Email already exists")
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to update email: {:?}", e);
+            HttpResponse::InternalServerError().body("Database error")
+        }
+    }
+}
+
 pub fn init(cfg: &mut web::ServiceConfig) {
-    cfg.route("/user/{user_id}", web::get().to(get_user_info));
-}
\ No newline at end of file
+    cfg.route("/user/me", web::get().to(get_user_info));
+    cfg.route("/user/me", web::patch().to(update_profile));
+    cfg.route("/user/me/password", web::post().to(change_password));
+    cfg.route("/user/me/email", web::post().to(request_email_change));
+    cfg.route("/user/me/email/verify", web::post().to(verify_email_change));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use crate::testutil;
+
+    const PASSWORD: &str = "correct horse";
+
+    fn authed(req: test::TestRequest, token: &str) -> test::TestRequest {
+        req.insert_header(("Authorization", format!("Bearer {}", token)))
+    }
+
+    async fn user_info(pool: &PgPool, user_id: i32) -> UserInfo {
+        sqlx::query_as::<_, UserInfo>("SELECT id AS user_id, name, email, phoneNumber FROM logininfo WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn profile_updates_keep_numbers_unique(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(App::new().app_data(web::Data::new(pool.clone())).configure(init)).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let other = testutil::create_user(&pool, "user").await;
+        let token = testutil::login(&pool, user).await;
+        let taken = user_info(&pool, other).await.phonenumber;
+
+        let update = |body: serde_json::Value| authed(test::TestRequest::patch().uri("/user/me"), &token).set_json(body);
+
+        let res = test::call_service(&app, update(json!({ "name": " Sita ", "number": "9811111111" })).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!((body["name"].as_str(), body["phonenumber"].as_str()), (Some("Sita"), Some("9811111111")));
+
+        let res = test::call_service(&app, update(json!({ "number": taken })).to_request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(test::read_body(res).await, "Phone number already exists");
+
+        let res = test::call_service(&app, update(json!({ "name": "  " })).to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(user_info(&pool, user).await.phonenumber, "9811111111");
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn a_number_taken_mid_update_is_a_conflict(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(App::new().app_data(web::Data::new(pool.clone())).configure(init)).await;
+        let user = testutil::create_user(&pool, "user").await;
+        let other = testutil::create_user(&pool, "user").await;
+        let token = testutil::login(&pool, user).await;
+
+        // The other account takes the number after our check has passed, but before our
+        // update lands.
+        let mut tx = pool.begin().await.unwrap();
+        sqlx::query("UPDATE logininfo SET phoneNumber = '9822222222' WHERE id = $1")
+            .bind(other)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        let req = authed(test::TestRequest::patch().uri("/user/me"), &token).set_json(json!({ "number": "9822222222" }));
+        let (res, _) = futures::future::join(test::call_service(&app, req.to_request()), async {
+            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+            tx.commit().await.unwrap();
+        })
+        .await;
+
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(test::read_body(res).await, "Phone number already exists");
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn changing_the_password_signs_out_other_sessions(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(App::new().app_data(web::Data::new(pool.clone())).configure(init)).await;
+        let user = testutil::create_user(&pool, "user").await;
+        sqlx::query("UPDATE logininfo SET password = $2 WHERE id = $1")
+            .bind(user)
+            .bind(hash_password(PASSWORD).unwrap())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let token = testutil::login(&pool, user).await;
+        let other_device = testutil::login(&pool, user).await;
+
+        let change = |current: &str| {
+            authed(test::TestRequest::post().uri("/user/me/password"), &token)
+                .set_json(json!({ "current_password": current, "new_password": "battery staple" }))
+                .to_request()
+        };
+
+        let res = test::call_service(&app, change("wrong")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let me = |token: &str| authed(test::TestRequest::get().uri("/user/me"), token).to_request();
+        assert_eq!(test::call_service(&app, me(&other_device)).await.status(), StatusCode::OK);
+
+        assert_eq!(test::call_service(&app, change(PASSWORD)).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&app, me(&token)).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&app, me(&other_device)).await.status(), StatusCode::UNAUTHORIZED);
+
+        let stored: String = sqlx::query_scalar("SELECT password FROM logininfo WHERE id = $1")
+            .bind(user)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(Argon2::default().verify_password(b"battery staple", &PasswordHash::new(&stored).unwrap()).is_ok());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn email_changes_only_conflict_on_email(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(testutil::test_config()))
+                .configure(init),
+        )
+        .await;
+        let user = testutil::create_user(&pool, "user").await;
+        let other = testutil::create_user(&pool, "user").await;
+        let token = testutil::login(&pool, user).await;
+        // An account with an empty phone number has nothing to do with email conflicts.
+        sqlx::query("UPDATE logininfo SET phoneNumber = '' WHERE id = $1").bind(other).execute(&pool).await.unwrap();
+        let taken = user_info(&pool, other).await.email;
+
+        let request = |email: &str| {
+            authed(test::TestRequest::post().uri("/user/me/email"), &token)
+                .set_json(json!({ "email": email }))
+                .to_request()
+        };
+
+        let res = test::call_service(&app, request(&taken)).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(test::read_body(res).await, "Email already exists");
+
+        let res = test::call_service(&app, request("new.address@example.com")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        let staged: (String, Option<i32>) = sqlx::query_as("SELECT gmail, user_id FROM temp_users WHERE temp_id = $1")
+            .bind(body["temp_id"].as_str().unwrap())
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(staged, ("new.address@example.com".to_string(), Some(user)));
+    }
+}
@@ -3,20 +3,25 @@ use sqlx::PgPool;
 use serde::Deserialize;
 use serde_json::json;
 use log::error;
+use crate::databases::auth::tempdb::{check_temp_code, CodeCheck};
 
-#[allow(dead_code)]
-#[derive(sqlx::FromRow)]
-struct TempUser {
-    name: String,
-    number: String,
-    gmail: String,
-    password: String,
-}
-
-#[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct VerifyRequest {
     pub temp_id: String,
+    pub code: String,
+}
+
+pub fn code_check_failure(check: CodeCheck) -> HttpResponse {
+    match check {
+        CodeCheck::Valid(_) => HttpResponse::InternalServerError().finish(),
+        CodeCheck::Invalid { attempts_left } => HttpResponse::BadRequest().json(json!({
+            "message": "Invalid verification code",
+            "attempts_left": attempts_left
+        })),
+        CodeCheck::Expired => HttpResponse::Gone().body("Verification code expired, please request a new one"),
+        CodeCheck::TooManyAttempts => HttpResponse::TooManyRequests().body("Too many attempts, please request a new code"),
+        CodeCheck::NotFound => HttpResponse::BadRequest().body("Invalid temp_id"),
+    }
 }
 
 pub async fn verify(
@@ -25,22 +30,22 @@ pub async fn verify(
 ) -> impl Responder {
     let temp_id = &req.temp_id;
 
-    let temp_user = match sqlx::query_as::<_, TempUser>(
-    r#"
-    SELECT name, number, gmail, password FROM temp_users WHERE temp_id = $1
-    "#
-)
-.bind(temp_id)
-.fetch_optional(db_pool.get_ref())
-.await
-{
-    Ok(Some(user)) => user,
-    Ok(None) => return HttpResponse::BadRequest().body("Invalid temp_id"),
-    Err(e) => {
-        error!("Error querying temp_users: {:?}", e);
-        return HttpResponse::InternalServerError().body("DB query error");
-    }
-};
+    let temp_user = match check_temp_code(db_pool.get_ref(), temp_id, &req.code, None).await {
+        Ok(CodeCheck::Valid(user)) => user,
+        Ok(other) => return code_check_failure(other),
+        Err(e) => {
+            error!("Error querying temp_users: {:?}", e);
+            return HttpResponse::InternalServerError().body("DB query error");
+        }
+    };
+
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {:?}", e);
+            return HttpResponse::InternalServerError().body("DB query error");
+        }
+    };
 
     let insert_res = sqlx::query(
     r#"
@@ -52,7 +57,7 @@ pub async fn verify(
 .bind(&temp_user.number)
 .bind(&temp_user.gmail)
 .bind(&temp_user.password)
-.execute(db_pool.get_ref())
+.execute(&mut *tx)
 .await;
 
     if let Err(e) = insert_res {
@@ -62,13 +67,18 @@ pub async fn verify(
 
     if let Err(e) = sqlx::query("DELETE FROM temp_users WHERE temp_id = $1")
     .bind(temp_id)
-    .execute(db_pool.get_ref())
+    .execute(&mut *tx)
     .await
     {
         error!("Error deleting from temp_users: {:?}", e);
         return HttpResponse::InternalServerError().body("DB delete error");
     }
 
+    if let Err(e) = tx.commit().await {
+        error!("Error committing verification: {:?}", e);
+        return HttpResponse::InternalServerError().body("DB insert error");
+    }
+
     HttpResponse::Ok().json(json!({
         "message": "User verified and registered successfully"
     }))
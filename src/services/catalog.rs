@@ -0,0 +1,414 @@
+pub mod filter;
+
+use sqlx::types::BigDecimal;
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+use filter::ProductFilter;
+
+const LAPTOP_COLUMNS: &str = "id, display_name, brand_name, model_name, model_year, product_authentication, \
+    product_type, processor, processor_generation, processor_series, ram, ram_type, storage, storage_type, \
+    graphic, graphic_ram, battery, touchscreen, show_price, face_image_url";
+
+// Stored and GIN-indexed on laptop_details, with brand, model and name weighted highest;
+// see databases/migrations/0015_search_vector.sql for what goes into it.
+const SEARCH_VECTOR: &str = "search_vector";
+// How much a match counts under each weight, in ts_rank's {D, C, B, A} order.
+const RANK_WEIGHTS: &str = "'{0.1, 0.2, 0.4, 1.0}'";
+// How close a typo has to be to a laptop's names (pg_trgm word similarity, 0 to 1).
+const FUZZY_THRESHOLD: &str = "0.4";
+// Longest search text compared letter by letter; anything longer isn't a typo.
+const MAX_FUZZY_LEN: usize = 64;
+
+#[derive(FromRow, Clone)]
+pub struct Laptop {
+    pub id: i32,
+    pub display_name: String,
+    pub brand_name: String,
+    pub model_name: String,
+    pub model_year: Option<i32>,
+    pub product_authentication: Option<String>,
+    pub product_type: Option<String>,
+    pub processor: Option<String>,
+    pub processor_generation: Option<String>,
+    pub processor_series: Option<String>,
+    pub ram: Option<i32>,
+    pub ram_type: Option<String>,
+    pub storage: Option<i32>,
+    pub storage_type: Option<String>,
+    pub graphic: Option<String>,
+    pub graphic_ram: Option<i32>,
+    pub battery: Option<String>,
+    pub touchscreen: Option<bool>,
+    pub show_price: BigDecimal,
+    pub face_image_url: Option<String>,
+}
+
+impl Laptop {
+    /// The label shown on product cards.
+    pub fn tag(&self) -> String {
+        self.product_authentication
+            .clone()
+            .unwrap_or_else(|| "Performance Laptop".to_string())
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum CatalogSort {
+    /// Best search match first; by id when there is no search text.
+    #[default]
+    Relevance,
+    Random,
+    PriceAsc,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Every word must match as typed, give or take stemming.
+    #[default]
+    Words,
+    /// For search-as-you-type: words may be unfinished or misspelt. Laptops matching the
+    /// text as typed come first, then the closest fuzzy matches on brand, model, name,
+    /// processor and graphics.
+    Autocomplete,
+}
+
+/// One read of the catalog: which laptops, in what order, and which page of them.
+#[derive(Default)]
+pub struct CatalogQuery {
+    pub ids: Option<Vec<i32>>,
+    pub filters: Vec<ProductFilter>,
+    /// Leave out laptops with nothing left to sell.
+    pub in_stock: bool,
+    pub search: Option<String>,
+    pub search_mode: SearchMode,
+    pub sort: CatalogSort,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+impl CatalogQuery {
+    pub fn build(&self) -> QueryBuilder<'static, Postgres> {
+        let mut query = QueryBuilder::new(format!("SELECT {} FROM laptop_details WHERE 1=1", LAPTOP_COLUMNS));
+
+        if let Some(ids) = &self.ids {
+            query.push(" AND id = ANY(").push_bind(ids.clone()).push(")");
+        }
+
+        for filter in &self.filters {
+            filter.push_sql(&mut query);
+        }
+
+        if self.in_stock {
+            query.push(" AND quantity > 0");
+        }
+
+        if let Some(search) = &self.search {
+            query.push(" AND ");
+            self.push_search_match(&mut query, search);
+        }
+
+        match (self.sort, &self.search) {
+            (CatalogSort::Relevance, Some(search)) => {
+                query.push(" ORDER BY ");
+                self.push_relevance(&mut query, search);
+                query.push(", id");
+            }
+            (CatalogSort::Relevance, None) => {
+                query.push(" ORDER BY id");
+            }
+            (CatalogSort::Random, _) => {
+                query.push(" ORDER BY RANDOM()");
+            }
+            (CatalogSort::PriceAsc, _) => {
+                query.push(" ORDER BY show_price ASC, id");
+            }
+        }
+
+        if let Some(limit) = self.limit {
+            query.push(" LIMIT ").push_bind(limit);
+        }
+        if let Some(offset) = self.offset {
+            query.push(" OFFSET ").push_bind(offset);
+        }
+
+        query
+    }
+
+    fn push_search_match(&self, query: &mut QueryBuilder<'static, Postgres>, search: &str) {
+        match (self.search_mode, prefix_tsquery(search)) {
+            (SearchMode::Words, _) => {
+                query
+                    .push(SEARCH_VECTOR)
+                    .push(" @@ plainto_tsquery('english', ")
+                    .push_bind(search.to_string())
+                    .push(")");
+            }
+            (SearchMode::Autocomplete, Some(prefixes)) => {
+                query
+                    .push("(")
+                    .push(SEARCH_VECTOR)
+                    .push(" @@ to_tsquery('english', ")
+                    .push_bind(prefixes)
+                    .push(") OR ")
+                    .push_bind(fuzzy_text(search))
+                    .push(" <% suggestion_text)");
+            }
+            (SearchMode::Autocomplete, None) => {
+                query.push_bind(fuzzy_text(search)).push(" <% suggestion_text");
+            }
+        }
+    }
+
+    fn push_relevance(&self, query: &mut QueryBuilder<'static, Postgres>, search: &str) {
+        match (self.search_mode, prefix_tsquery(search)) {
+            (SearchMode::Words, _) => {
+                query
+                    .push("ts_rank(")
+                    .push(RANK_WEIGHTS)
+                    .push(", ")
+                    .push(SEARCH_VECTOR)
+                    .push(", plainto_tsquery('english', ")
+                    .push_bind(search.to_string())
+                    .push(")) DESC");
+            }
+            (SearchMode::Autocomplete, prefixes) => {
+                if let Some(prefixes) = prefixes {
+                    query
+                        .push(SEARCH_VECTOR)
+                        .push(" @@ to_tsquery('english', ")
+                        .push_bind(prefixes.clone())
+                        .push(") DESC, ts_rank(")
+                        .push(RANK_WEIGHTS)
+                        .push(", ")
+                        .push(SEARCH_VECTOR)
+                        .push(", to_tsquery('english', ")
+                        .push_bind(prefixes)
+                        .push(")) DESC, ");
+                }
+                query
+                    .push("word_similarity(")
+                    .push_bind(fuzzy_text(search))
+                    .push(", suggestion_text) DESC");
+            }
+        }
+    }
+
+    pub async fn fetch(&self, pool: &PgPool) -> Result<Vec<Laptop>, sqlx::Error> {
+        let mut query = self.build();
+
+        if self.search_mode == SearchMode::Autocomplete && self.search.is_some() {
+            // The threshold behind `<%`, which lets the trigram index do the fuzzy match.
+            let mut tx = pool.begin().await?;
+            sqlx::query(&format!("SET LOCAL pg_trgm.word_similarity_threshold = {}", FUZZY_THRESHOLD))
+                .execute(&mut *tx)
+                .await?;
+            let laptops = query.build_query_as::<Laptop>().fetch_all(&mut *tx).await?;
+            tx.commit().await?;
+            return Ok(laptops);
+        }
+
+        query.build_query_as::<Laptop>().fetch_all(pool).await
+    }
+}
+
+/// Turns what has been typed so far into a tsquery where every word may be unfinished,
+/// e.g. "lenovo thin" into `lenovo:* & thin:*`. Only letters and digits are kept, so the
+/// result is always valid tsquery syntax. `None` if nothing searchable is left.
+fn prefix_tsquery(search: &str) -> Option<String> {
+    let words: Vec<String> = search
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .take(8)
+        .map(|word| format!("{}:*", word.to_lowercase()))
+        .collect();
+
+    (!words.is_empty()).then(|| words.join(" & "))
+}
+
+fn fuzzy_text(search: &str) -> String {
+    search.trim().to_lowercase().chars().take(MAX_FUZZY_LEN).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filter::{FilterField, FilterOp};
+
+    fn select(rest: &str) -> String {
+        format!("SELECT {} FROM laptop_details WHERE 1=1{}", LAPTOP_COLUMNS, rest)
+    }
+
+    /// The SQL and how many values it binds, checking the placeholders run $1, $2, ... in order.
+    fn built(query: CatalogQuery) -> (String, usize) {
+        let sql = query.build().sql().to_string();
+        let placeholders: Vec<usize> = sql
+            .split('$')
+            .skip(1)
+            .map(|rest| rest.chars().take_while(char::is_ascii_digit).collect::<String>().parse().unwrap())
+            .collect();
+        assert_eq!(placeholders, (1..=placeholders.len()).collect::<Vec<_>>(), "{}", sql);
+        (sql, placeholders.len())
+    }
+
+    fn filter(field: FilterField, op: FilterOp, value: serde_json::Value) -> ProductFilter {
+        ProductFilter::new(field, op, &value).unwrap()
+    }
+
+    #[test]
+    fn default_lists_everything_by_id() {
+        assert_eq!(built(CatalogQuery::default()), (select(" ORDER BY id"), 0));
+    }
+
+    #[test]
+    fn ids_stock_and_paging() {
+        let query = CatalogQuery {
+            ids: Some(vec![3, 1]),
+            in_stock: true,
+            limit: Some(10),
+            offset: Some(20),
+            ..Default::default()
+        };
+        assert_eq!(
+            built(query),
+            (select(" AND id = ANY($1) AND quantity > 0 ORDER BY id LIMIT $2 OFFSET $3"), 3)
+        );
+    }
+
+    #[test]
+    fn filters_bind_one_value_each() {
+        let query = CatalogQuery {
+            filters: vec![
+                filter(FilterField::Ram, FilterOp::Eq, serde_json::json!(16)),
+                filter(FilterField::BrandName, FilterOp::Contains, serde_json::json!("acer")),
+                filter(FilterField::ShowPrice, FilterOp::Lte, serde_json::json!(150000)),
+                filter(FilterField::Storage, FilterOp::In, serde_json::json!([512, 1024])),
+            ],
+            sort: CatalogSort::PriceAsc,
+            ..Default::default()
+        };
+        assert_eq!(
+            built(query),
+            (
+                select(
+                    " AND ram = $1 AND brand_name ILIKE $2 AND show_price <= $3 AND storage = ANY($4) \
+                     ORDER BY show_price ASC, id"
+                ),
+                4
+            )
+        );
+    }
+
+    #[test]
+    fn word_search_ranks_by_relevance() {
+        let query = CatalogQuery { search: Some("gaming laptop".to_string()), ..Default::default() };
+        assert_eq!(
+            built(query),
+            (
+                select(
+                    " AND search_vector @@ plainto_tsquery('english', $1) \
+                     ORDER BY ts_rank('{0.1, 0.2, 0.4, 1.0}', search_vector, plainto_tsquery('english', $2)) DESC, id"
+                ),
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn autocomplete_matches_prefixes_then_typos() {
+        let query = CatalogQuery {
+            search: Some("lenov thin".to_string()),
+            search_mode: SearchMode::Autocomplete,
+            limit: Some(8),
+            ..Default::default()
+        };
+        assert_eq!(
+            built(query),
+            (
+                select(
+                    " AND (search_vector @@ to_tsquery('english', $1) OR $2 <% suggestion_text) \
+                     ORDER BY search_vector @@ to_tsquery('english', $3) DESC, \
+                     ts_rank('{0.1, 0.2, 0.4, 1.0}', search_vector, to_tsquery('english', $4)) DESC, \
+                     word_similarity($5, suggestion_text) DESC, id LIMIT $6"
+                ),
+                6
+            )
+        );
+    }
+
+    #[test]
+    fn autocomplete_without_words_is_only_fuzzy() {
+        let query = CatalogQuery {
+            search: Some("!!".to_string()),
+            search_mode: SearchMode::Autocomplete,
+            ..Default::default()
+        };
+        assert_eq!(
+            built(query),
+            (select(" AND $1 <% suggestion_text ORDER BY word_similarity($2, suggestion_text) DESC, id"), 2)
+        );
+    }
+
+    #[test]
+    fn sort_overrides_relevance() {
+        let random = CatalogQuery {
+            search: Some("asus".to_string()),
+            sort: CatalogSort::Random,
+            limit: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            built(random),
+            (select(" AND search_vector @@ plainto_tsquery('english', $1) ORDER BY RANDOM() LIMIT $2"), 2)
+        );
+
+        let cheapest = CatalogQuery {
+            search: Some("asus".to_string()),
+            search_mode: SearchMode::Autocomplete,
+            sort: CatalogSort::PriceAsc,
+            ..Default::default()
+        };
+        assert_eq!(
+            built(cheapest),
+            (
+                select(
+                    " AND (search_vector @@ to_tsquery('english', $1) OR $2 <% suggestion_text) \
+                     ORDER BY show_price ASC, id"
+                ),
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn everything_at_once() {
+        let query = CatalogQuery {
+            ids: Some(vec![1, 2, 3]),
+            filters: vec![filter(FilterField::Touchscreen, FilterOp::Eq, serde_json::json!(true))],
+            in_stock: true,
+            search: Some("zenbook".to_string()),
+            search_mode: SearchMode::Words,
+            sort: CatalogSort::Relevance,
+            limit: Some(12),
+            offset: Some(24),
+        };
+        assert_eq!(
+            built(query),
+            (
+                select(
+                    " AND id = ANY($1) AND touchscreen = $2 AND quantity > 0 \
+                     AND search_vector @@ plainto_tsquery('english', $3) \
+                     ORDER BY ts_rank('{0.1, 0.2, 0.4, 1.0}', search_vector, plainto_tsquery('english', $4)) DESC, id \
+                     LIMIT $5 OFFSET $6"
+                ),
+                6
+            )
+        );
+    }
+
+    #[test]
+    fn prefix_tsquery_keeps_only_words() {
+        assert_eq!(prefix_tsquery("Lenovo thin-").as_deref(), Some("lenovo:* & thin:*"));
+        assert_eq!(prefix_tsquery("rtx's 3050 & |!").as_deref(), Some("rtx:* & s:* & 3050:*"));
+        assert_eq!(prefix_tsquery("a b c d e f g h i j").as_deref(), Some("a:* & b:* & c:* & d:* & e:* & f:* & g:* & h:*"));
+        assert_eq!(prefix_tsquery(" &|!:*() "), None);
+    }
+}
@@ -0,0 +1,470 @@
+use serde_json::{Map, Value};
+use sqlx::types::BigDecimal;
+use sqlx::{Postgres, QueryBuilder};
+use std::str::FromStr;
+
+// Caps what a single filter can ask for, so a runaway model or a hand-written URL can't
+// build a huge query.
+pub const MAX_LIST_VALUES: usize = 20;
+const MAX_TEXT_LEN: usize = 100;
+
+/// The `laptop_details` columns products can be filtered by. Anything else is refused, so
+/// a filter key never reaches SQL unless it is one of these.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FilterField {
+    BrandName,
+    ModelName,
+    ModelYear,
+    DisplayName,
+    ProductType,
+    SuitableFor,
+    Color,
+    ProcessorGeneration,
+    Processor,
+    ProcessorSeries,
+    Ram,
+    RamType,
+    Storage,
+    StorageType,
+    Graphic,
+    GraphicRam,
+    Display,
+    DisplayType,
+    Touchscreen,
+    PowerSupply,
+    Battery,
+    Warranty,
+    ShowPrice,
+}
+
+const FIELDS: [FilterField; 23] = [
+    FilterField::BrandName,
+    FilterField::ModelName,
+    FilterField::ModelYear,
+    FilterField::DisplayName,
+    FilterField::ProductType,
+    FilterField::SuitableFor,
+    FilterField::Color,
+    FilterField::ProcessorGeneration,
+    FilterField::Processor,
+    FilterField::ProcessorSeries,
+    FilterField::Ram,
+    FilterField::RamType,
+    FilterField::Storage,
+    FilterField::StorageType,
+    FilterField::Graphic,
+    FilterField::GraphicRam,
+    FilterField::Display,
+    FilterField::DisplayType,
+    FilterField::Touchscreen,
+    FilterField::PowerSupply,
+    FilterField::Battery,
+    FilterField::Warranty,
+    FilterField::ShowPrice,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FieldKind {
+    Text,
+    Integer,
+    Price,
+    Boolean,
+}
+
+impl FilterField {
+    pub fn column(&self) -> &'static str {
+        match self {
+            FilterField::BrandName => "brand_name",
+            FilterField::ModelName => "model_name",
+            FilterField::ModelYear => "model_year",
+            FilterField::DisplayName => "display_name",
+            FilterField::ProductType => "product_type",
+            FilterField::SuitableFor => "suitable_for",
+            FilterField::Color => "color",
+            FilterField::ProcessorGeneration => "processor_generation",
+            FilterField::Processor => "processor",
+            FilterField::ProcessorSeries => "processor_series",
+            FilterField::Ram => "ram",
+            FilterField::RamType => "ram_type",
+            FilterField::Storage => "storage",
+            FilterField::StorageType => "storage_type",
+            FilterField::Graphic => "graphic",
+            FilterField::GraphicRam => "graphic_ram",
+            FilterField::Display => "display",
+            FilterField::DisplayType => "display_type",
+            FilterField::Touchscreen => "touchscreen",
+            FilterField::PowerSupply => "power_supply",
+            FilterField::Battery => "battery",
+            FilterField::Warranty => "warranty",
+            FilterField::ShowPrice => "show_price",
+        }
+    }
+
+    pub fn parse(key: &str) -> Option<Self> {
+        FIELDS.into_iter().find(|field| field.column() == key)
+    }
+
+    fn kind(&self) -> FieldKind {
+        match self {
+            FilterField::ModelYear
+            | FilterField::Ram
+            | FilterField::Storage
+            | FilterField::GraphicRam => FieldKind::Integer,
+            FilterField::ShowPrice => FieldKind::Price,
+            FilterField::Touchscreen => FieldKind::Boolean,
+            _ => FieldKind::Text,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FilterOp {
+    Eq,
+    Lte,
+    Gte,
+    In,
+    Contains,
+}
+
+impl FilterOp {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "eq" => Some(FilterOp::Eq),
+            "lte" => Some(FilterOp::Lte),
+            "gte" => Some(FilterOp::Gte),
+            "in" => Some(FilterOp::In),
+            "contains" => Some(FilterOp::Contains),
+            _ => None,
+        }
+    }
+}
+
+/// A single value, already of the type its column holds.
+#[derive(Clone, Debug)]
+pub enum FilterValue {
+    Text(String),
+    Integer(i64),
+    Price(BigDecimal),
+    Boolean(bool),
+}
+
+#[derive(Clone, Debug)]
+pub enum FilterValues {
+    Text(Vec<String>),
+    Integer(Vec<i64>),
+    Price(Vec<BigDecimal>),
+}
+
+#[derive(Clone, Debug)]
+pub enum Condition {
+    /// Case-insensitive for text.
+    Eq(FilterValue),
+    Lte(FilterValue),
+    Gte(FilterValue),
+    In(FilterValues),
+    Contains(String),
+    /// Contains any one of these, e.g. a shopper ticking several brands.
+    ContainsAny(Vec<String>),
+}
+
+#[derive(Clone, Debug)]
+pub struct ProductFilter {
+    pub field: FilterField,
+    pub condition: Condition,
+}
+
+/// A filter that was left out of the search, and why.
+#[derive(Clone, Debug)]
+pub struct RejectedFilter {
+    pub key: String,
+    pub reason: String,
+}
+
+impl ProductFilter {
+    pub fn new(field: FilterField, op: FilterOp, value: &Value) -> Result<Self, String> {
+        let kind = field.kind();
+        let condition = match op {
+            FilterOp::Eq => Condition::Eq(parse_value(kind, value)?),
+            FilterOp::Lte | FilterOp::Gte if !matches!(kind, FieldKind::Integer | FieldKind::Price) => {
+                return Err("only numbers can be compared".to_string());
+            }
+            FilterOp::Lte => Condition::Lte(parse_value(kind, value)?),
+            FilterOp::Gte => Condition::Gte(parse_value(kind, value)?),
+            FilterOp::In => Condition::In(parse_values(kind, value)?),
+            FilterOp::Contains => match parse_value(kind, value)? {
+                FilterValue::Text(text) => Condition::Contains(text),
+                _ => return Err("only text can be searched for a part".to_string()),
+            },
+        };
+        Ok(ProductFilter { field, condition })
+    }
+
+    /// Reads filters shaped like `{"ram": 16, "graphic": "rtx", "show_price": {"lte": 180000},
+    /// "brand_name": {"in": ["acer", "asus"]}}`. A bare list means `in`; any other bare
+    /// value means `contains` for text and `eq` otherwise. Keys, operators and values that
+    /// don't fit the whitelist are returned as rejected instead of being guessed at.
+    pub fn from_json(filters: &Map<String, Value>) -> (Vec<ProductFilter>, Vec<RejectedFilter>) {
+        let mut accepted = Vec::new();
+        let mut rejected = Vec::new();
+
+        for (key, value) in filters {
+            let Some(field) = FilterField::parse(key) else {
+                rejected.push(RejectedFilter { key: key.clone(), reason: "not a product detail I can filter by".to_string() });
+                continue;
+            };
+
+            let conditions: Vec<(String, Option<FilterOp>, &Value)> = match value {
+                Value::Object(ops) => ops
+                    .iter()
+                    .map(|(op, v)| (format!("{}.{}", key, op), FilterOp::parse(op), v))
+                    .collect(),
+                Value::Array(_) => vec![(key.clone(), Some(FilterOp::In), value)],
+                _ if field.kind() == FieldKind::Text => vec![(key.clone(), Some(FilterOp::Contains), value)],
+                _ => vec![(key.clone(), Some(FilterOp::Eq), value)],
+            };
+
+            for (key, op, value) in conditions {
+                let Some(op) = op else {
+                    rejected.push(RejectedFilter { key, reason: "use eq, lte, gte, in or contains".to_string() });
+                    continue;
+                };
+                match ProductFilter::new(field, op, value) {
+                    Ok(filter) => accepted.push(filter),
+                    Err(reason) => rejected.push(RejectedFilter { key, reason }),
+                }
+            }
+        }
+
+        (accepted, rejected)
+    }
+
+    /// Appends ` AND <condition>` to a query, binding every value.
+    pub fn push_sql(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        let column = self.field.column();
+        query.push(" AND ");
+
+        match &self.condition {
+            Condition::Eq(FilterValue::Text(text)) => {
+                query.push(column).push(" ILIKE ").push_bind(escape_like(text));
+            }
+            Condition::Eq(value) => {
+                query.push(column).push(" = ");
+                push_value(query, value);
+            }
+            Condition::Lte(value) => {
+                query.push(column).push(" <= ");
+                push_value(query, value);
+            }
+            Condition::Gte(value) => {
+                query.push(column).push(" >= ");
+                push_value(query, value);
+            }
+            Condition::In(FilterValues::Text(values)) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_lowercase()).collect();
+                query.push("lower(").push(column).push(") = ANY(").push_bind(values).push(")");
+            }
+            Condition::In(FilterValues::Integer(values)) => {
+                query.push(column).push(" = ANY(").push_bind(values.clone()).push(")");
+            }
+            Condition::In(FilterValues::Price(values)) => {
+                query.push(column).push(" = ANY(").push_bind(values.clone()).push(")");
+            }
+            Condition::Contains(text) => {
+                query.push(column).push(" ILIKE ").push_bind(format!("%{}%", escape_like(text)));
+            }
+            Condition::ContainsAny(texts) => {
+                let patterns: Vec<String> = texts.iter().map(|t| format!("%{}%", escape_like(t))).collect();
+                query.push(column).push(" ILIKE ANY(").push_bind(patterns).push(")");
+            }
+        }
+    }
+}
+
+fn push_value(query: &mut QueryBuilder<'_, Postgres>, value: &FilterValue) {
+    match value {
+        FilterValue::Text(text) => query.push_bind(text.clone()),
+        FilterValue::Integer(n) => query.push_bind(*n),
+        FilterValue::Price(price) => query.push_bind(price.clone()),
+        FilterValue::Boolean(b) => query.push_bind(*b),
+    };
+}
+
+fn parse_value(kind: FieldKind, value: &Value) -> Result<FilterValue, String> {
+    match kind {
+        FieldKind::Text => match value.as_str().map(str::trim) {
+            Some("") => Err("expected some text".to_string()),
+            Some(text) if text.chars().count() > MAX_TEXT_LEN => Err("text is too long".to_string()),
+            Some(text) => Ok(FilterValue::Text(text.to_string())),
+            None => Err("expected text".to_string()),
+        },
+        FieldKind::Integer => value
+            .as_i64()
+            .map(FilterValue::Integer)
+            .ok_or_else(|| "expected a whole number".to_string()),
+        FieldKind::Price => match value {
+            Value::Number(n) => BigDecimal::from_str(&n.to_string())
+                .map(FilterValue::Price)
+                .map_err(|_| "expected a price in NPR".to_string()),
+            _ => Err("expected a price in NPR".to_string()),
+        },
+        FieldKind::Boolean => value
+            .as_bool()
+            .map(FilterValue::Boolean)
+            .ok_or_else(|| "expected true or false".to_string()),
+    }
+}
+
+fn parse_values(kind: FieldKind, value: &Value) -> Result<FilterValues, String> {
+    let Some(items) = value.as_array() else {
+        return Err("expected a list".to_string());
+    };
+    if items.is_empty() || items.len() > MAX_LIST_VALUES {
+        return Err(format!("expected a list of 1 to {} values", MAX_LIST_VALUES));
+    }
+
+    let mut texts = Vec::new();
+    let mut integers = Vec::new();
+    let mut prices = Vec::new();
+    for item in items {
+        match parse_value(kind, item)? {
+            FilterValue::Text(text) => texts.push(text),
+            FilterValue::Integer(n) => integers.push(n),
+            FilterValue::Price(price) => prices.push(price),
+            FilterValue::Boolean(_) => return Err("a yes/no detail can't take a list".to_string()),
+        }
+    }
+
+    Ok(match kind {
+        FieldKind::Integer => FilterValues::Integer(integers),
+        FieldKind::Price => FilterValues::Price(prices),
+        _ => FilterValues::Text(texts),
+    })
+}
+
+/// Escapes `%`, `_` and `\` so text matches literally under ILIKE.
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn read(filters: Value) -> (Vec<ProductFilter>, Vec<(String, String)>) {
+        let (accepted, rejected) = ProductFilter::from_json(filters.as_object().expect("filters object"));
+        (accepted, rejected.into_iter().map(|r| (r.key, r.reason)).collect())
+    }
+
+    fn reason(field: FilterField, op: FilterOp, value: Value) -> String {
+        ProductFilter::new(field, op, &value).expect_err("filter should be refused")
+    }
+
+    #[test]
+    fn documented_shapes_are_accepted() {
+        let (accepted, rejected) = read(json!({
+            "ram": 16,
+            "graphic": "rtx",
+            "show_price": {"lte": 180000},
+            "brand_name": {"in": ["acer", "asus"]},
+            "storage_type": ["ssd"],
+            "touchscreen": true
+        }));
+
+        assert!(rejected.is_empty(), "{:?}", rejected);
+        let by_field = |field| accepted.iter().find(|f| f.field == field).map(|f| &f.condition);
+        assert!(matches!(by_field(FilterField::Ram), Some(Condition::Eq(FilterValue::Integer(16)))));
+        assert!(matches!(by_field(FilterField::Graphic), Some(Condition::Contains(t)) if t == "rtx"));
+        assert!(matches!(by_field(FilterField::ShowPrice), Some(Condition::Lte(FilterValue::Price(p))) if p.to_string() == "180000"));
+        assert!(matches!(by_field(FilterField::BrandName), Some(Condition::In(FilterValues::Text(v))) if v == &["acer", "asus"]));
+        assert!(matches!(by_field(FilterField::StorageType), Some(Condition::In(FilterValues::Text(v))) if v == &["ssd"]));
+        assert!(matches!(by_field(FilterField::Touchscreen), Some(Condition::Eq(FilterValue::Boolean(true)))));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let (accepted, rejected) = read(json!({
+            "id": 1,
+            "quantity": 5,
+            "cost_price": {"lte": 1000},
+            "RAM": 16,
+            "ram; DROP TABLE laptop_details": 16
+        }));
+
+        assert!(accepted.is_empty());
+        assert_eq!(rejected.len(), 5);
+        for (key, reason) in rejected {
+            assert_eq!(reason, "not a product detail I can filter by", "{}", key);
+        }
+    }
+
+    #[test]
+    fn unknown_ops_are_rejected() {
+        let (accepted, rejected) = read(json!({
+            "ram": {"eq": 16, "between": [8, 32], "LTE": 32, "": 16}
+        }));
+
+        assert_eq!(accepted.len(), 1);
+        let mut keys: Vec<&str> = rejected.iter().map(|(key, _)| key.as_str()).collect();
+        keys.sort();
+        assert_eq!(keys, ["ram.", "ram.LTE", "ram.between"]);
+        assert!(rejected.iter().all(|(_, reason)| reason == "use eq, lte, gte, in or contains"));
+    }
+
+    #[test]
+    fn only_numbers_can_be_compared() {
+        for field in [FilterField::BrandName, FilterField::Processor, FilterField::Touchscreen] {
+            for op in [FilterOp::Lte, FilterOp::Gte] {
+                assert_eq!(reason(field, op, json!("m")), "only numbers can be compared", "{:?} {:?}", field, op);
+            }
+        }
+        let (_, rejected) = read(json!({ "brand_name": {"gte": "a"} }));
+        assert_eq!(rejected, [("brand_name.gte".to_string(), "only numbers can be compared".to_string())]);
+
+        assert!(ProductFilter::new(FilterField::Ram, FilterOp::Gte, &json!(8)).is_ok());
+        assert!(ProductFilter::new(FilterField::ShowPrice, FilterOp::Lte, &json!(99999.5)).is_ok());
+    }
+
+    #[test]
+    fn lists_must_hold_one_to_the_maximum() {
+        let too_many = "expected a list of 1 to 20 values";
+        let longest: Vec<i64> = (1..=MAX_LIST_VALUES as i64).collect();
+        let over: Vec<i64> = (0..=MAX_LIST_VALUES as i64).collect();
+
+        assert_eq!(reason(FilterField::Ram, FilterOp::In, json!([])), too_many);
+        assert_eq!(reason(FilterField::Ram, FilterOp::In, json!(over)), too_many);
+        assert!(ProductFilter::new(FilterField::Ram, FilterOp::In, &json!(longest)).is_ok());
+
+        let (accepted, rejected) = read(json!({ "brand_name": [] }));
+        assert!(accepted.is_empty());
+        assert_eq!(rejected, [("brand_name".to_string(), too_many.to_string())]);
+    }
+
+    #[test]
+    fn text_is_capped_in_characters() {
+        let longest = "é".repeat(MAX_TEXT_LEN);
+        let over = "a".repeat(MAX_TEXT_LEN + 1);
+
+        assert!(ProductFilter::new(FilterField::BrandName, FilterOp::Contains, &json!(longest)).is_ok());
+        assert_eq!(reason(FilterField::BrandName, FilterOp::Contains, json!(over)), "text is too long");
+        assert_eq!(reason(FilterField::BrandName, FilterOp::Eq, json!(over)), "text is too long");
+        assert_eq!(reason(FilterField::BrandName, FilterOp::In, json!(["acer", over])), "text is too long");
+        assert_eq!(reason(FilterField::BrandName, FilterOp::Contains, json!("   ")), "expected some text");
+    }
+
+    #[test]
+    fn escape_like_matches_wildcards_literally() {
+        assert_eq!(escape_like("rtx 3050"), "rtx 3050");
+        assert_eq!(escape_like("100%"), "100\\%");
+        assert_eq!(escape_like("i7_12th"), "i7\\_12th");
+        assert_eq!(escape_like("C:\\Users"), "C:\\\\Users");
+        assert_eq!(escape_like("%_\\"), "\\%\\_\\\\");
+    }
+}
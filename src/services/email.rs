@@ -1,12 +1,25 @@
-use lettre::{Message, SmtpTransport, Transport, message::{SinglePart, MultiPart, header}};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor, message::{SinglePart, MultiPart, header}};
 use lettre::transport::smtp::authentication::Credentials;
-use std::env;
+use crate::config::{Config, SmtpConfig};
+use crate::databases::shop::orderdb::OrderStatus;
 
-pub async fn send_code_email(email: &str, code: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let smtp_user = env::var("SMTP_EMAIL")?;
-    let smtp_pass = env::var("SMTP_PASSWORD")?;
-    let smtp_host = env::var("SMTP_SERVER")?;
-    let smtp_port: u16 = env::var("SMTP_PORT")?.parse()?;
+/// Sends `message` through the configured SMTP relay. The transport is async, so a slow
+/// server holds up only the caller, not the worker thread it runs on.
+pub async fn send_email(smtp: &SmtpConfig, message: Message) -> Result<(), lettre::transport::smtp::Error> {
+    let creds = Credentials::new(smtp.email.clone(), smtp.password.clone());
+
+    let mailer = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.server)?
+        .port(smtp.port)
+        .credentials(creds)
+        .build();
+
+    mailer.send(message).await?;
+
+    Ok(())
+}
+
+pub async fn send_code_email(config: &Config, email: &str, code: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let smtp = &config.smtp;
 
     let html_body = format!(r#"
     <div style="background-color:#6b7280;padding:50px 0">
@@ -23,7 +36,7 @@ pub async fn send_code_email(email: &str, code: &str) -> Result<(), Box<dyn std:
     "#, code, email);
 
     let email_message = Message::builder()
-        .from(smtp_user.parse()?)
+        .from(smtp.email.parse()?)
         .to(email.parse()?)
         .subject("Your OTP - Secure Login")
         .multipart(
@@ -36,14 +49,7 @@ pub async fn send_code_email(email: &str, code: &str) -> Result<(), Box<dyn std:
                 ),
         )?;
 
-    let creds = Credentials::new(smtp_user.clone(), smtp_pass);
-
-    let mailer = SmtpTransport::starttls_relay(&smtp_host)?
-        .port(smtp_port)
-        .credentials(creds)
-        .build();
-
-    /*let result =*/ let _ = mailer.send(&email_message);
+    /*let result =*/ let _ = send_email(smtp, email_message).await;
 /*
     match result {
         Ok(_) => println!("✅ Email sent successfully"),
@@ -55,3 +61,84 @@ pub async fn send_code_email(email: &str, code: &str) -> Result<(), Box<dyn std:
 */
     Ok(())
 }
+
+
+pub async fn send_password_reset_email(config: &Config, email: &str, token: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let smtp = &config.smtp;
+    let reset_link = format!("{}/reset-password?token={}", config.base_url, token);
+
+    let html_body = format!(r#"
+    <div style="background-color:#6b7280;padding:50px 0">
+        <div style="max-width:500px;margin:0 auto;background:#f3f4f6;padding:40px;border-radius:8px;text-align:center;font-family:Arial,sans-serif;">
+            <h1 style="color:#000">Reset Your Password</h1>
+            <p style="margin:20px 0;font-size:16px;color:#333">
+                We received a request to reset the password for {}
+            </p>
+            <a href="{}" style="display:inline-block;background:green;color:#fff;padding:12px 24px;border-radius:6px;text-decoration:none;margin:20px 0">Reset password</a>
+            <p style="color:#333">This link expires in 30 minutes.<br>
+            If you did not ask for a reset, you can ignore this email.</p>
+        </div>
+    </div>
+    "#, email, reset_link);
+
+    let email_message = Message::builder()
+        .from(smtp.email.parse()?)
+        .to(email.parse()?)
+        .subject("Reset your ePasal password")
+        .multipart(
+            MultiPart::alternative()
+                .singlepart(SinglePart::plain(format!("Reset your password here: {}", reset_link)))
+                .singlepart(
+                    SinglePart::builder()
+                        .header(header::ContentType::TEXT_HTML)
+                        .body(html_body),
+                ),
+        )?;
+
+    send_email(smtp, email_message).await?;
+
+    Ok(())
+}
+
+pub async fn send_order_status_email(config: &Config, email: &str, order_ref: &str, status: OrderStatus) -> Result<(), Box<dyn std::error::Error>> {
+    let smtp = &config.smtp;
+    let orders_link = format!("{}/orders", config.base_url);
+
+    let (heading, message) = match status {
+        OrderStatus::Placed => ("Order Placed", "Thanks for your order! We have received your payment and will start packing soon."),
+        OrderStatus::Packed => ("Order Packed", "Your order has been packed and will be handed to our courier shortly."),
+        OrderStatus::Shipped => ("Order Shipped", "Your order is on its way."),
+        OrderStatus::Delivered => ("Order Delivered", "Your order has been delivered. Enjoy your new laptop!"),
+        OrderStatus::Cancelled => ("Order Cancelled", "Your order has been cancelled."),
+        OrderStatus::Returned => ("Order Returned", "We have received your returned order."),
+    };
+
+    let html_body = format!(r#"
+    <div style="background-color:#6b7280;padding:50px 0">
+        <div style="max-width:500px;margin:0 auto;background:#f3f4f6;padding:40px;border-radius:8px;text-align:center;font-family:Arial,sans-serif;">
+            <h1 style="color:#000">{}</h1>
+            <p style="margin:20px 0;font-size:16px;color:#333">{}</p>
+            <p style="color:#333">Order reference: <b>{}</b></p>
+            <a href="{}" style="display:inline-block;background:green;color:#fff;padding:12px 24px;border-radius:6px;text-decoration:none;margin:20px 0">View your orders</a>
+        </div>
+    </div>
+    "#, heading, message, order_ref, orders_link);
+
+    let email_message = Message::builder()
+        .from(smtp.email.parse()?)
+        .to(email.parse()?)
+        .subject(format!("ePasal order {}: {}", order_ref, heading))
+        .multipart(
+            MultiPart::alternative()
+                .singlepart(SinglePart::plain(format!("{} Order reference: {}. See {}", message, order_ref, orders_link)))
+                .singlepart(
+                    SinglePart::builder()
+                        .header(header::ContentType::TEXT_HTML)
+                        .body(html_body),
+                ),
+        )?;
+
+    send_email(smtp, email_message).await?;
+
+    Ok(())
+}
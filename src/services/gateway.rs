@@ -0,0 +1,92 @@
+pub mod khalti;
+pub mod esewa;
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::config::Config;
+use crate::databases::payment::paymentdb::{Payment, PaymentStatus};
+
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct CustomerInfo {
+    pub name: String,
+    pub email: String,
+    pub phone: String,
+}
+
+/// What a gateway needs to start collecting a payment we have already recorded.
+pub struct CheckoutRequest<'a> {
+    pub order_ref: &'a str,
+    pub amount_paisa: i64,
+    pub product_name: &'a str,
+    pub customer: &'a CustomerInfo,
+}
+
+pub struct GatewayCheckout {
+    /// The gateway's id for the payment, if it issues one.
+    pub gateway_ref: String,
+    /// Returned to the frontend as-is: where to send the customer and with what.
+    pub response: Value,
+}
+
+/// Whatever the customer's browser brought back from the gateway's redirect.
+#[derive(Deserialize, Default)]
+pub struct GatewayCallback {
+    pub purchase_order_id: Option<String>,
+    pub pidx: Option<String>,
+    pub data: Option<String>,
+}
+
+/// The payment a callback claims to be about, once the gateway-specific checks pass.
+pub struct CallbackRef {
+    pub order_ref: String,
+    pub gateway_ref: Option<String>,
+}
+
+/// The gateway's authoritative view of a payment.
+pub struct GatewayStatus {
+    /// `None` for states we don't act on automatically, such as partial refunds.
+    pub status: Option<PaymentStatus>,
+    pub raw_status: String,
+    pub amount_paisa: i64,
+    pub transaction_id: Option<String>,
+}
+
+pub trait PaymentGateway: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn initiate<'a>(&'a self, request: &'a CheckoutRequest<'a>) -> BoxFuture<'a, Result<GatewayCheckout>>;
+
+    /// Checks the redirect parameters (signatures and the like) without trusting any
+    /// status they carry; `lookup` decides what actually happened.
+    fn read_callback(&self, callback: &GatewayCallback) -> Result<CallbackRef, String>;
+
+    fn lookup<'a>(&'a self, payment: &'a Payment) -> BoxFuture<'a, Result<GatewayStatus>>;
+
+    /// Refunds `amount_paisa`, or the whole payment when `None`.
+    fn refund<'a>(&'a self, payment: &'a Payment, amount_paisa: Option<i64>) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Every configured gateway, shared with handlers as `web::Data<Gateways>`.
+pub struct Gateways {
+    gateways: Vec<Box<dyn PaymentGateway>>,
+}
+
+impl Gateways {
+    pub fn from_config(config: &Config) -> Self {
+        let mut gateways: Vec<Box<dyn PaymentGateway>> = vec![Box::new(khalti::KhaltiGateway::new(config))];
+        if let Some(esewa) = esewa::EsewaGateway::from_config(config) {
+            gateways.push(Box::new(esewa));
+        }
+        Gateways { gateways }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn PaymentGateway> {
+        self.gateways.iter().find(|g| g.name() == name).map(|g| g.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.gateways.iter().map(|g| g.name()).collect()
+    }
+}
@@ -0,0 +1,460 @@
+use anyhow::{anyhow, bail, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+use crate::config::{Config, EsewaConfig};
+use crate::databases::payment::paymentdb::{Payment, PaymentStatus};
+use super::{CallbackRef, CheckoutRequest, GatewayCallback, GatewayCheckout, GatewayStatus, PaymentGateway};
+
+pub const ESEWA_GATEWAY: &str = "esewa";
+
+/// The fields of eSewa's status check that verification relies on.
+#[derive(Deserialize)]
+struct EsewaStatus {
+    transaction_uuid: String,
+    total_amount: Value,
+    status: String,
+    ref_id: Option<String>,
+}
+
+/// eSewa v2 (ePay) checkout. The customer's browser posts a signed form to eSewa, which
+/// redirects back with a signed, base64-encoded JSON `data` parameter.
+pub struct EsewaGateway {
+    client: Client,
+    config: EsewaConfig,
+    success_url: String,
+    failure_url: String,
+}
+
+impl EsewaGateway {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let esewa = config.esewa.clone()?;
+        Some(EsewaGateway {
+            client: Client::new(),
+            config: esewa,
+            success_url: format!("{}/payment/status?gateway={}", config.base_url, ESEWA_GATEWAY),
+            failure_url: format!("{}/payment/status?gateway={}&failed=true", config.base_url, ESEWA_GATEWAY),
+        })
+    }
+
+    /// HMAC-SHA256 over `name=value` pairs joined with commas, in the order listed in
+    /// `signed_field_names`.
+    fn mac(&self, fields: &[(&str, &str)]) -> Hmac<Sha256> {
+        let message = fields
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.config.secret_key.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(message.as_bytes());
+        mac
+    }
+
+    /// The base64 signature eSewa expects alongside `fields`.
+    fn sign(&self, fields: &[(&str, &str)]) -> String {
+        STANDARD.encode(self.mac(fields).finalize().into_bytes())
+    }
+
+    /// Checks a base64 signature from eSewa in constant time.
+    fn verify(&self, fields: &[(&str, &str)], signature: &str) -> bool {
+        STANDARD
+            .decode(signature)
+            .is_ok_and(|signature| self.mac(fields).verify_slice(&signature).is_ok())
+    }
+}
+
+/// eSewa takes rupees, not paisa.
+fn rupees(paisa: i64) -> String {
+    if paisa % 100 == 0 {
+        (paisa / 100).to_string()
+    } else {
+        format!("{}.{:02}", paisa / 100, paisa % 100)
+    }
+}
+
+/// Reads an amount eSewa sent back, which may be a number or a string such as "1,000.0".
+fn paisa(amount: &Value) -> Option<i64> {
+    let rupees = match amount {
+        Value::Number(n) => n.as_f64()?,
+        Value::String(s) => s.replace(',', "").parse().ok()?,
+        _ => return None,
+    };
+    Some((rupees * 100.0).round() as i64)
+}
+
+fn field_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn esewa_status(status: &str) -> Option<PaymentStatus> {
+    match status {
+        "PENDING" | "AMBIGUOUS" => Some(PaymentStatus::Pending),
+        "COMPLETE" => Some(PaymentStatus::Completed),
+        "CANCELED" => Some(PaymentStatus::UserCanceled),
+        "NOT_FOUND" => Some(PaymentStatus::Expired),
+        "FULL_REFUND" => Some(PaymentStatus::Refunded),
+        _ => None,
+    }
+}
+
+impl PaymentGateway for EsewaGateway {
+    fn name(&self) -> &'static str {
+        ESEWA_GATEWAY
+    }
+
+    fn initiate<'a>(&'a self, request: &'a CheckoutRequest<'a>) -> BoxFuture<'a, Result<GatewayCheckout>> {
+        Box::pin(async move {
+            let total_amount = rupees(request.amount_paisa);
+            let signed_field_names = "total_amount,transaction_uuid,product_code";
+            let signature = self.sign(&[
+                ("total_amount", &total_amount),
+                ("transaction_uuid", request.order_ref),
+                ("product_code", &self.config.product_code),
+            ]);
+
+            let response = serde_json::json!({
+                "payment_url": self.config.form_url,
+                "form_fields": {
+                    "amount": total_amount,
+                    "tax_amount": "0",
+                    "product_service_charge": "0",
+                    "product_delivery_charge": "0",
+                    "total_amount": total_amount,
+                    "transaction_uuid": request.order_ref,
+                    "product_code": self.config.product_code,
+                    "success_url": self.success_url,
+                    "failure_url": self.failure_url,
+                    "signed_field_names": signed_field_names,
+                    "signature": signature
+                }
+            });
+
+            // eSewa has no id of its own until the payment completes; the order ref is
+            // the transaction_uuid it reports back.
+            Ok(GatewayCheckout { gateway_ref: request.order_ref.to_string(), response })
+        })
+    }
+
+    fn read_callback(&self, callback: &GatewayCallback) -> Result<CallbackRef, String> {
+        let Some(data) = &callback.data else {
+            return Err("data is required".to_string());
+        };
+
+        let decoded = STANDARD.decode(data).map_err(|_| "data is not valid base64".to_string())?;
+        let fields: serde_json::Map<String, Value> =
+            serde_json::from_slice(&decoded).map_err(|_| "data is not valid JSON".to_string())?;
+
+        let text = |name: &str| fields.get(name).and_then(field_text);
+
+        let (Some(names), Some(signature)) = (text("signed_field_names"), text("signature")) else {
+            return Err("data is not signed".to_string());
+        };
+
+        let mut signed = Vec::new();
+        for name in names.split(',') {
+            let Some(value) = text(name) else {
+                return Err(format!("signed field {} is missing", name));
+            };
+            signed.push((name, value));
+        }
+        let signed: Vec<(&str, &str)> = signed.iter().map(|(n, v)| (*n, v.as_str())).collect();
+
+        if !self.verify(&signed, &signature) {
+            return Err("signature does not match".to_string());
+        }
+        if text("product_code").as_deref() != Some(self.config.product_code.as_str()) {
+            return Err("product code does not match".to_string());
+        }
+
+        let order_ref = text("transaction_uuid").ok_or_else(|| "transaction_uuid is missing".to_string())?;
+        Ok(CallbackRef { gateway_ref: Some(order_ref.clone()), order_ref })
+    }
+
+    fn lookup<'a>(&'a self, payment: &'a Payment) -> BoxFuture<'a, Result<GatewayStatus>> {
+        Box::pin(async move {
+            let total_amount = rupees(payment.amount_paisa);
+            let response = self.client
+                .get(format!("{}/", self.config.status_url))
+                .query(&[
+                    ("product_code", self.config.product_code.as_str()),
+                    ("total_amount", total_amount.as_str()),
+                    ("transaction_uuid", payment.order_ref.as_str()),
+                ])
+                .send()
+                .await?;
+
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            log::debug!("eSewa Status Response ({}): {}", status, text);
+
+            if !status.is_success() {
+                bail!("eSewa status check returned {}: {}", status, text);
+            }
+
+            let lookup: EsewaStatus = serde_json::from_str(&text)?;
+            if lookup.transaction_uuid != payment.order_ref {
+                bail!("eSewa returned transaction {} for {}", lookup.transaction_uuid, payment.order_ref);
+            }
+
+            Ok(GatewayStatus {
+                status: esewa_status(&lookup.status),
+                amount_paisa: paisa(&lookup.total_amount)
+                    .ok_or_else(|| anyhow!("eSewa returned an unreadable amount"))?,
+                raw_status: lookup.status,
+                transaction_id: lookup.ref_id,
+            })
+        })
+    }
+
+    fn refund<'a>(&'a self, _payment: &'a Payment, _amount_paisa: Option<i64>) -> BoxFuture<'a, Result<()>> {
+        // eSewa offers no merchant refund API; refunds are arranged with eSewa directly.
+        Box::pin(async { bail!("eSewa refunds have to be made from the merchant dashboard") })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use std::collections::HashMap;
+
+    // eSewa's test merchant, as published in their ePay v2 documentation.
+    const TEST_SECRET: &str = "8gBm/:&EnhH.1/q";
+    const TEST_PRODUCT: &str = "EPAYTEST";
+
+    fn gateway(status_url: &str) -> EsewaGateway {
+        EsewaGateway {
+            client: Client::new(),
+            config: EsewaConfig {
+                product_code: TEST_PRODUCT.to_string(),
+                secret_key: TEST_SECRET.to_string(),
+                form_url: "https://rc-epay.esewa.com.np/api/epay/main/v2/form".to_string(),
+                status_url: status_url.to_string(),
+            },
+            success_url: "http://localhost:5173/payment/status?gateway=esewa".to_string(),
+            failure_url: "http://localhost:5173/payment/status?gateway=esewa&failed=true".to_string(),
+        }
+    }
+
+    /// The success redirect from eSewa's documentation, decoded.
+    fn documented_callback() -> serde_json::Map<String, Value> {
+        serde_json::from_value(serde_json::json!({
+            "transaction_code": "000AWEO",
+            "status": "COMPLETE",
+            "total_amount": 1000.0,
+            "transaction_uuid": "250610-162413",
+            "product_code": "EPAYTEST",
+            "signed_field_names": "transaction_code,status,total_amount,transaction_uuid,product_code,signed_field_names",
+            "signature": "62GcfZTmVkzhtUeh+QJ1AqiJrjoWWGof3U+eTPTZ7fA="
+        }))
+        .unwrap()
+    }
+
+    fn callback(fields: &serde_json::Map<String, Value>) -> GatewayCallback {
+        GatewayCallback {
+            purchase_order_id: None,
+            pidx: None,
+            data: Some(STANDARD.encode(serde_json::to_vec(fields).unwrap())),
+        }
+    }
+
+    fn payment(order_ref: &str, amount_paisa: i64) -> Payment {
+        Payment {
+            id: 1,
+            user_id: 1,
+            gateway: ESEWA_GATEWAY.to_string(),
+            gateway_ref: Some(order_ref.to_string()),
+            order_ref: order_ref.to_string(),
+            amount_paisa,
+            currency: "NPR".to_string(),
+            status: PaymentStatus::Pending.as_str().to_string(),
+            transaction_id: None,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn sign_matches_esewa_example() {
+        let signature = gateway("").sign(&[
+            ("total_amount", "110"),
+            ("transaction_uuid", "241028"),
+            ("product_code", TEST_PRODUCT),
+        ]);
+        assert_eq!(signature, "i94zsd3oXF6ZsSr/kGqT4sSzYQzjj1W/waxjWyRwaME=");
+    }
+
+    #[test]
+    fn documented_callback_is_accepted() {
+        let found = gateway("").read_callback(&callback(&documented_callback())).unwrap();
+        assert_eq!(found.order_ref, "250610-162413");
+        assert_eq!(found.gateway_ref.as_deref(), Some("250610-162413"));
+    }
+
+    #[test]
+    fn tampered_field_is_rejected() {
+        let mut fields = documented_callback();
+        fields.insert("total_amount".to_string(), serde_json::json!(10.0));
+
+        let error = gateway("").read_callback(&callback(&fields)).err();
+        assert_eq!(error.as_deref(), Some("signature does not match"));
+    }
+
+    #[test]
+    fn unreadable_signature_is_rejected() {
+        let mut fields = documented_callback();
+        fields.insert("signature".to_string(), serde_json::json!("not base64!"));
+
+        let error = gateway("").read_callback(&callback(&fields)).err();
+        assert_eq!(error.as_deref(), Some("signature does not match"));
+    }
+
+    #[test]
+    fn missing_signed_field_is_rejected() {
+        let mut fields = documented_callback();
+        fields.remove("transaction_code");
+
+        let error = gateway("").read_callback(&callback(&fields)).err();
+        assert_eq!(error.as_deref(), Some("signed field transaction_code is missing"));
+    }
+
+    #[test]
+    fn other_product_code_is_rejected() {
+        let gateway = gateway("");
+        let signature = gateway.sign(&[
+            ("total_amount", "1000"),
+            ("transaction_uuid", "250610-162413"),
+            ("product_code", "OTHERSHOP"),
+        ]);
+        let fields = serde_json::from_value(serde_json::json!({
+            "total_amount": "1000",
+            "transaction_uuid": "250610-162413",
+            "product_code": "OTHERSHOP",
+            "signed_field_names": "total_amount,transaction_uuid,product_code",
+            "signature": signature
+        }))
+        .unwrap();
+
+        let error = gateway.read_callback(&callback(&fields)).err();
+        assert_eq!(error.as_deref(), Some("product code does not match"));
+    }
+
+    #[test]
+    fn rupees_keeps_paisa_only_when_there_are_some() {
+        let cases = [
+            (0, "0"),
+            (1, "0.01"),
+            (10, "0.10"),
+            (99, "0.99"),
+            (100, "1"),
+            (101, "1.01"),
+            (199, "1.99"),
+            (100_000, "1000"),
+            (123_456_789, "1234567.89"),
+        ];
+        for (paisa, expected) in cases {
+            assert_eq!(rupees(paisa), expected, "{} paisa", paisa);
+        }
+    }
+
+    #[test]
+    fn paisa_reads_numbers_and_strings() {
+        let cases = [
+            (serde_json::json!(0), Some(0)),
+            (serde_json::json!(0.01), Some(1)),
+            (serde_json::json!(0.99), Some(99)),
+            (serde_json::json!(1), Some(100)),
+            (serde_json::json!(1000.0), Some(100_000)),
+            (serde_json::json!(1234567.89), Some(123_456_789)),
+            (serde_json::json!("0.01"), Some(1)),
+            (serde_json::json!("1000.0"), Some(100_000)),
+            (serde_json::json!("1,000.0"), Some(100_000)),
+            (serde_json::json!("1,234,567.89"), Some(123_456_789)),
+            (serde_json::json!(""), None),
+            (serde_json::json!("Rs. 100"), None),
+            (serde_json::json!(null), None),
+            (serde_json::json!(true), None),
+        ];
+        for (amount, expected) in cases {
+            assert_eq!(paisa(&amount), expected, "{}", amount);
+        }
+    }
+
+    /// Answers eSewa's status check the way the sandbox does for a completed payment,
+    /// except for a few transaction ids that script a misbehaving server.
+    async fn stub_status(query: web::Query<HashMap<String, String>>) -> HttpResponse {
+        let uuid = query.get("transaction_uuid").cloned().unwrap_or_default();
+        if query.get("product_code").map(String::as_str) != Some(TEST_PRODUCT) {
+            return HttpResponse::BadRequest().json(serde_json::json!({ "code": 0, "error_message": "Invalid product" }));
+        }
+        match uuid.as_str() {
+            "server-error" => HttpResponse::InternalServerError().body("down"),
+            "other-uuid" => HttpResponse::Ok().json(serde_json::json!({
+                "product_code": TEST_PRODUCT,
+                "transaction_uuid": "someone-else",
+                "total_amount": 1000.0,
+                "status": "COMPLETE",
+                "ref_id": "000AWEO"
+            })),
+            "pending" => HttpResponse::Ok().json(serde_json::json!({
+                "product_code": TEST_PRODUCT,
+                "transaction_uuid": uuid,
+                "total_amount": query.get("total_amount"),
+                "status": "PENDING",
+                "ref_id": null
+            })),
+            _ => HttpResponse::Ok().json(serde_json::json!({
+                "product_code": TEST_PRODUCT,
+                "transaction_uuid": uuid,
+                "total_amount": query.get("total_amount"),
+                "status": "COMPLETE",
+                "ref_id": "000AWEO"
+            })),
+        }
+    }
+
+    /// A status server on a free local port, returning the `status_url` to configure.
+    fn start_stub() -> String {
+        let server = HttpServer::new(|| App::new().route("/status/", web::get().to(stub_status)))
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{}/status", addr)
+    }
+
+    #[actix_web::test]
+    async fn lookup_reads_the_status_check() {
+        let gateway = gateway(&start_stub());
+
+        let completed = gateway.lookup(&payment("250610-162413", 100_050)).await.unwrap();
+        assert_eq!(completed.status, Some(PaymentStatus::Completed));
+        assert_eq!(completed.raw_status, "COMPLETE");
+        assert_eq!(completed.amount_paisa, 100_050);
+        assert_eq!(completed.transaction_id.as_deref(), Some("000AWEO"));
+
+        let pending = gateway.lookup(&payment("pending", 100_000)).await.unwrap();
+        assert_eq!(pending.status, Some(PaymentStatus::Pending));
+        assert_eq!(pending.transaction_id, None);
+    }
+
+    #[actix_web::test]
+    async fn lookup_rejects_bad_answers() {
+        let gateway = gateway(&start_stub());
+
+        assert!(gateway.lookup(&payment("other-uuid", 100_000)).await.is_err());
+        assert!(gateway.lookup(&payment("server-error", 100_000)).await.is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{anyhow, bail, Result};
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::config::Config;
+use crate::databases::payment::paymentdb::{Payment, PaymentStatus};
+use super::{CallbackRef, CheckoutRequest, CustomerInfo, GatewayCallback, GatewayCheckout, GatewayStatus, PaymentGateway};
+
+pub const KHALTI_GATEWAY: &str = "khalti";
+
+#[derive(Serialize)]
+struct KhaltiPayload<'a> {
+    return_url: &'a str,
+    website_url: &'a str,
+    amount: i64,
+    purchase_order_id: &'a str,
+    purchase_order_name: &'a str,
+    customer_info: &'a CustomerInfo,
+}
+
+/// The fields of Khalti's lookup response that verification relies on.
+#[derive(Deserialize)]
+struct KhaltiLookup {
+    pidx: String,
+    total_amount: i64,
+    status: String,
+    transaction_id: Option<String>,
+}
+
+pub struct KhaltiGateway {
+    client: Client,
+    secret_key: String,
+    url: String,
+    refund_url: String,
+    return_url: String,
+    website_url: String,
+}
+
+impl KhaltiGateway {
+    pub fn new(config: &Config) -> Self {
+        KhaltiGateway {
+            client: Client::new(),
+            secret_key: config.khalti.secret_key.clone(),
+            url: config.khalti.url.clone(),
+            refund_url: config.khalti.refund_url.clone(),
+            return_url: format!("{}/payment/status", config.base_url),
+            website_url: config.backend_url.clone(),
+        }
+    }
+
+    async fn post(&self, path: &str, body: &impl Serialize) -> Result<Value> {
+        let response = self.client
+            .post(format!("{}{}", self.url, path))
+            .header("Authorization", format!("Key {}", self.secret_key))
+            .json(body)
+            .send()
+            .await?;
+
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        println!("Khalti Response {} ({}): {}", path, status, text);
+
+        if !status.is_success() {
+            bail!("Khalti {} returned {}: {}", path, status, text);
+        }
+        Ok(serde_json::from_str(&text)?)
+    }
+}
+
+/// Maps Khalti's lookup status onto ours. Partial refunds are left for an admin.
+fn khalti_status(status: &str) -> Option<PaymentStatus> {
+    match status {
+        "Initiated" | "Pending" => Some(PaymentStatus::Pending),
+        "Completed" => Some(PaymentStatus::Completed),
+        "Expired" => Some(PaymentStatus::Expired),
+        "User canceled" => Some(PaymentStatus::UserCanceled),
+        "Refunded" => Some(PaymentStatus::Refunded),
+        _ => None,
+    }
+}
+
+impl PaymentGateway for KhaltiGateway {
+    fn name(&self) -> &'static str {
+        KHALTI_GATEWAY
+    }
+
+    fn initiate<'a>(&'a self, request: &'a CheckoutRequest<'a>) -> BoxFuture<'a, Result<GatewayCheckout>> {
+        Box::pin(async move {
+            let payload = KhaltiPayload {
+                return_url: &self.return_url,
+                website_url: &self.website_url,
+                amount: request.amount_paisa,
+                purchase_order_id: request.order_ref,
+                purchase_order_name: request.product_name,
+                customer_info: request.customer,
+            };
+
+            let response = self.post("/epayment/initiate/", &payload).await?;
+            let pidx = response
+                .get("pidx")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| anyhow!("Khalti initiate response had no pidx"))?
+                .to_string();
+
+            Ok(GatewayCheckout { gateway_ref: pidx, response })
+        })
+    }
+
+    fn read_callback(&self, callback: &GatewayCallback) -> Result<CallbackRef, String> {
+        match (&callback.purchase_order_id, &callback.pidx) {
+            (Some(order_ref), Some(pidx)) => Ok(CallbackRef {
+                order_ref: order_ref.clone(),
+                gateway_ref: Some(pidx.clone()),
+            }),
+            _ => Err("purchase_order_id and pidx are required".to_string()),
+        }
+    }
+
+    fn lookup<'a>(&'a self, payment: &'a Payment) -> BoxFuture<'a, Result<GatewayStatus>> {
+        Box::pin(async move {
+            let pidx = payment
+                .gateway_ref
+                .as_deref()
+                .ok_or_else(|| anyhow!("Payment {} has no pidx", payment.id))?;
+
+            let response = self.post("/epayment/lookup/", &serde_json::json!({ "pidx": pidx })).await?;
+            let lookup: KhaltiLookup = serde_json::from_value(response)?;
+
+            if lookup.pidx != pidx {
+                bail!("Khalti lookup returned pidx {} for {}", lookup.pidx, pidx);
+            }
+
+            Ok(GatewayStatus {
+                status: khalti_status(&lookup.status),
+                raw_status: lookup.status,
+                amount_paisa: lookup.total_amount,
+                transaction_id: lookup.transaction_id,
+            })
+        })
+    }
+
+    fn refund<'a>(&'a self, payment: &'a Payment, amount_paisa: Option<i64>) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let transaction_id = payment
+                .transaction_id
+                .as_deref()
+                .ok_or_else(|| anyhow!("Payment {} has no Khalti transaction id", payment.id))?;
+
+            let url = format!("{}/{}/refund/", self.refund_url, transaction_id);
+            let mut body = serde_json::json!({});
+            if let Some(amount) = amount_paisa {
+                body["amount"] = amount.into();
+            }
+
+            let response = self.client
+                .post(url)
+                .header("Authorization", format!("Key {}", self.secret_key))
+                .json(&body)
+                .send()
+                .await?;
+
+            let status = response.status();
+            if !status.is_success() {
+                let text = response.text().await.unwrap_or_default();
+                bail!("Khalti refund returned {}: {}", status, text);
+            }
+            Ok(())
+        })
+    }
+}
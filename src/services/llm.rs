@@ -0,0 +1,257 @@
+use anyhow::{anyhow, Context, Result};
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+use crate::config::{LlmConfig, LlmProviderKind};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Serialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: &str) -> Self {
+        ChatMessage { role: role.to_string(), content: content.to_string() }
+    }
+}
+
+/// A chat-completion backend for the shop assistant.
+pub trait LlmProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Sends the whole conversation and returns the assistant's reply text.
+    fn chat<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<String>>;
+
+    /// Cheap reachability probe, used at startup and to recover after an outage.
+    fn is_healthy(&self) -> BoxFuture<'_, bool>;
+}
+
+pub struct OllamaProvider {
+    client: Client,
+    url: String,
+    model: String,
+}
+
+impl OllamaProvider {
+    pub fn new(url: &str, model: &str) -> Self {
+        OllamaProvider { client: Client::new(), url: url.to_string(), model: model.to_string() }
+    }
+}
+
+impl LlmProvider for OllamaProvider {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn chat<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let res: Value = self.client
+                .post(format!("{}/api/chat", self.url))
+                .timeout(REQUEST_TIMEOUT)
+                .json(&json!({
+                    "model": self.model,
+                    "messages": messages,
+                    "stream": false
+                }))
+                .send()
+                .await
+                .context("Ollama request failed")?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            res["message"]["content"]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("Ollama response had no message content"))
+        })
+    }
+
+    fn is_healthy(&self) -> BoxFuture<'_, bool> {
+        Box::pin(async move {
+            self.client
+                .get(format!("{}/api/tags", self.url))
+                .timeout(HEALTH_TIMEOUT)
+                .send()
+                .await
+                .map(|res| res.status().is_success())
+                .unwrap_or(false)
+        })
+    }
+}
+
+pub struct OpenAiProvider {
+    client: Client,
+    url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+impl OpenAiProvider {
+    pub fn new(url: &str, model: &str, api_key: Option<String>) -> Self {
+        OpenAiProvider { client: Client::new(), url: url.to_string(), model: model.to_string(), api_key }
+    }
+
+    fn authorized(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.api_key {
+            Some(key) => req.bearer_auth(key),
+            None => req,
+        }
+    }
+}
+
+impl LlmProvider for OpenAiProvider {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn chat<'a>(&'a self, messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let req = self.client
+                .post(format!("{}/chat/completions", self.url))
+                .timeout(REQUEST_TIMEOUT)
+                .json(&json!({
+                    "model": self.model,
+                    "messages": messages
+                }));
+
+            let res: Value = self.authorized(req)
+                .send()
+                .await
+                .context("OpenAI-compatible request failed")?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            res["choices"][0]["message"]["content"]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("Chat completion response had no message content"))
+        })
+    }
+
+    fn is_healthy(&self) -> BoxFuture<'_, bool> {
+        Box::pin(async move {
+            self.authorized(self.client.get(format!("{}/models", self.url)))
+                .timeout(HEALTH_TIMEOUT)
+                .send()
+                .await
+                .map(|res| res.status().is_success())
+                .unwrap_or(false)
+        })
+    }
+}
+
+/// Returns its replies in order, repeating the last one once the script runs out.
+pub struct ScriptedProvider {
+    replies: Vec<String>,
+    next: AtomicUsize,
+}
+
+impl ScriptedProvider {
+    pub fn new(replies: Vec<String>) -> Self {
+        ScriptedProvider { replies, next: AtomicUsize::new(0) }
+    }
+
+    /// A short conversation that asks one question and then searches, so both bot paths
+    /// can be exercised without a model.
+    pub fn demo() -> Self {
+        ScriptedProvider::new(vec![
+            "What will you mostly use the laptop for, and what is your budget?".to_string(),
+            r#"{"action": "search", "filters": {"ram": 16, "storage": 512, "show_price": {"lte": 200000}}}"#.to_string(),
+        ])
+    }
+}
+
+impl LlmProvider for ScriptedProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn chat<'a>(&'a self, _messages: &'a [ChatMessage]) -> BoxFuture<'a, Result<String>> {
+        let i = self.next.fetch_add(1, Ordering::SeqCst);
+        let reply = self.replies
+            .get(i.min(self.replies.len().saturating_sub(1)))
+            .cloned()
+            .ok_or_else(|| anyhow!("Scripted provider has no replies"));
+        Box::pin(async move { reply })
+    }
+
+    fn is_healthy(&self) -> BoxFuture<'_, bool> {
+        Box::pin(async { true })
+    }
+}
+
+/// The configured provider plus whether it answered its last health check. While it is
+/// unavailable, customer messages go to an admin instead of the bot.
+pub struct Chatbot {
+    provider: Option<Box<dyn LlmProvider>>,
+    available: AtomicBool,
+}
+
+impl Chatbot {
+    pub async fn from_config(config: &LlmConfig) -> Self {
+        let provider: Option<Box<dyn LlmProvider>> = match config.provider {
+            LlmProviderKind::Ollama => Some(Box::new(OllamaProvider::new(&config.url, &config.model))),
+            LlmProviderKind::OpenAi => {
+                Some(Box::new(OpenAiProvider::new(&config.url, &config.model, config.api_key.clone())))
+            }
+            LlmProviderKind::Mock => Some(Box::new(ScriptedProvider::demo())),
+            LlmProviderKind::Disabled => None,
+        };
+
+        let chatbot = Chatbot { provider, available: AtomicBool::new(false) };
+
+        match &chatbot.provider {
+            None => println!("⚠️  Chatbot disabled; customer messages will go to admins."),
+            Some(provider) => {
+                let healthy = provider.is_healthy().await;
+                chatbot.available.store(healthy, Ordering::Relaxed);
+                if healthy {
+                    println!("✅ Chatbot provider '{}' is ready.", provider.name());
+                } else {
+                    println!(
+                        "⚠️  Chatbot provider '{}' is not reachable at {}; messages will go to admins until it is.",
+                        provider.name(),
+                        config.url
+                    );
+                }
+            }
+        }
+
+        chatbot
+    }
+
+    pub fn provider(&self) -> Option<&dyn LlmProvider> {
+        self.provider.as_deref()
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    /// Re-probes the provider and returns the new availability.
+    pub async fn refresh(&self) -> bool {
+        let healthy = match &self.provider {
+            Some(provider) => provider.is_healthy().await,
+            None => false,
+        };
+
+        let was = self.available.swap(healthy, Ordering::Relaxed);
+        if was != healthy {
+            if healthy {
+                println!("✅ Chatbot provider is back; bot replies resumed.");
+            } else {
+                println!("⚠️  Chatbot provider is unreachable; routing messages to admins.");
+            }
+        }
+        healthy
+    }
+}
@@ -2,4 +2,11 @@ pub mod email;
 pub mod toppicks;
 pub mod brandpage;
 pub mod search;
-pub mod suggestion;
\ No newline at end of file
+pub mod catalog;
+pub mod suggestion;
+pub mod session;
+pub mod totp;
+pub mod llm;
+pub mod reservations;
+pub mod gateway;
+pub mod reconciliation;
@@ -0,0 +1,294 @@
+use sqlx::PgPool;
+use crate::config::Config;
+use crate::databases::payment::paymentdb::{list_unsettled_payments, Payment, PaymentStatus, Transition};
+use crate::databases::payment::reconciliationdb::{add_item, finish_run, start_run, ReconciliationRun};
+use crate::routes::payment::verifypay::{notify_payment_status, settle_payment};
+use crate::services::gateway::{Gateways, PaymentGateway};
+
+// Payments younger than this are left to the customer's own redirect back to the shop.
+const RECONCILE_AFTER_MINUTES: i32 = 2;
+
+/// What asking the gateway about a payment led to.
+pub enum Reconciled {
+    /// The payment reached a final status; `order_id` is set when an order was placed.
+    Settled { status: PaymentStatus, order_id: Option<i32> },
+    /// Someone else already moved the payment to this status.
+    Unchanged(PaymentStatus),
+    /// The payment was already settled differently.
+    Rejected(PaymentStatus),
+    StillPending { gateway_status: String },
+    /// A status we don't act on automatically, such as a partial refund.
+    NeedsReview { gateway_status: String },
+    /// The gateway completed the payment for a different amount than we charged.
+    AmountMismatch { expected: i64, got: i64 },
+    LookupFailed(String),
+}
+
+impl Reconciled {
+    fn outcome(&self) -> &'static str {
+        match self {
+            Reconciled::Settled { .. } => "settled",
+            Reconciled::Unchanged(_) | Reconciled::Rejected(_) => "already_settled",
+            Reconciled::StillPending { .. } => "pending",
+            Reconciled::NeedsReview { .. } => "needs_review",
+            Reconciled::AmountMismatch { .. } => "amount_mismatch",
+            Reconciled::LookupFailed(_) => "lookup_failed",
+        }
+    }
+
+    fn needs_admin(&self) -> bool {
+        matches!(
+            self,
+            Reconciled::NeedsReview { .. } | Reconciled::AmountMismatch { .. } | Reconciled::LookupFailed(_)
+        )
+    }
+}
+
+/// Asks the gateway for the authoritative state of a payment and applies it: a completed
+/// payment places its order and records the sale, any other final status settles the
+/// payment, and the customer is emailed either way. Safe to run alongside the customer's
+/// own verification, since the payment only moves once.
+pub async fn reconcile_payment(
+    pool: &PgPool,
+    config: &Config,
+    gateway: &dyn PaymentGateway,
+    payment: &Payment,
+) -> anyhow::Result<Reconciled> {
+    let lookup = match gateway.lookup(payment).await {
+        Ok(lookup) => lookup,
+        Err(e) => return Ok(Reconciled::LookupFailed(e.to_string())),
+    };
+
+    let next = match lookup.status {
+        Some(status) if status.is_final() => status,
+        Some(_) => return Ok(Reconciled::StillPending { gateway_status: lookup.raw_status }),
+        None => return Ok(Reconciled::NeedsReview { gateway_status: lookup.raw_status }),
+    };
+
+    if next == PaymentStatus::Completed && lookup.amount_paisa != payment.amount_paisa {
+        return Ok(Reconciled::AmountMismatch { expected: payment.amount_paisa, got: lookup.amount_paisa });
+    }
+
+    let reason = format!("{} lookup: {}", gateway.name(), lookup.raw_status);
+    let (transition, order_id) = settle_payment(pool, payment, next, lookup.transaction_id.as_deref(), &reason).await?;
+
+    Ok(match transition {
+        Transition::Applied => {
+            notify_payment_status(pool, config, payment.user_id, &payment.order_ref, next).await;
+            Reconciled::Settled { status: next, order_id }
+        }
+        Transition::Unchanged => Reconciled::Unchanged(next),
+        Transition::Rejected(current) => Reconciled::Rejected(current),
+    })
+}
+
+/// Looks up every payment still waiting on its gateway, settles the ones the gateway has
+/// finished with, and records what happened to each as a report for admins.
+pub async fn run_reconciliation(
+    pool: &PgPool,
+    config: &Config,
+    gateways: &Gateways,
+    triggered_by: Option<i32>,
+) -> anyhow::Result<ReconciliationRun> {
+    let payments = list_unsettled_payments(pool, &gateways.names(), RECONCILE_AFTER_MINUTES).await?;
+    let run_id = start_run(pool, triggered_by).await?;
+
+    let (mut settled, mut unresolved) = (0, 0);
+
+    for payment in &payments {
+        let Some(gateway) = gateways.get(&payment.gateway) else {
+            continue;
+        };
+
+        let reconciled = match reconcile_payment(pool, config, gateway, payment).await {
+            Ok(reconciled) => reconciled,
+            Err(e) => {
+                eprintln!("❌ Failed to reconcile payment {}: {:?}", payment.id, e);
+                Reconciled::LookupFailed(format!("could not record the gateway's answer: {}", e))
+            }
+        };
+
+        let (new_status, detail) = match &reconciled {
+            Reconciled::Settled { status, order_id } => {
+                (Some(status.as_str()), order_id.map(|id| format!("order {}", id)))
+            }
+            Reconciled::Unchanged(status) | Reconciled::Rejected(status) => (Some(status.as_str()), None),
+            Reconciled::StillPending { gateway_status } | Reconciled::NeedsReview { gateway_status } => {
+                (None, Some(format!("gateway status: {}", gateway_status)))
+            }
+            Reconciled::AmountMismatch { expected, got } => {
+                (None, Some(format!("expected {} paisa, gateway reports {}", expected, got)))
+            }
+            Reconciled::LookupFailed(error) => (None, Some(error.clone())),
+        };
+
+        // A payment missing from the report needs an admin as much as one marked for review,
+        // and the rest of the run still has to happen and be finished.
+        let recorded =
+            add_item(pool, run_id, payment.id, &payment.status, reconciled.outcome(), new_status, detail.as_deref()).await;
+        if let Err(e) = &recorded {
+            eprintln!("❌ Failed to record payment {} in reconciliation run {}: {:?}", payment.id, run_id, e);
+        }
+
+        if matches!(reconciled, Reconciled::Settled { .. }) {
+            settled += 1;
+        }
+        if reconciled.needs_admin() || recorded.is_err() {
+            unresolved += 1;
+        }
+    }
+
+    Ok(finish_run(pool, run_id, payments.len() as i32, settled, unresolved).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+    use std::collections::HashMap;
+    use sqlx::Executor;
+    use crate::databases::payment::reconciliationdb::get_run_items;
+    use crate::mock_khalti::Outcome;
+    use crate::routes::payment::checkout;
+    use crate::testutil;
+
+    /// Starts a Khalti checkout on the mock for each case and, for the ones named after an
+    /// outcome, scripts the customer leaving the payment page that way. The payments are
+    /// then left long enough for reconciliation to pick them up, except "fresh". Returns
+    /// each case's order reference.
+    async fn stale_checkouts(pool: &PgPool, config: &Config, cases: &[&'static str]) -> HashMap<&'static str, String> {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(Gateways::from_config(config)))
+                .configure(checkout::init),
+        )
+        .await;
+        let customer = testutil::create_user(pool, "user").await;
+        let token = testutil::login(pool, customer).await;
+        let mock = config.khalti.url.trim_end_matches("/api/v2");
+        let client = reqwest::Client::new();
+
+        let mut refs = HashMap::new();
+        for &case in cases {
+            let laptop = testutil::create_laptop(pool, "Acme", case, 3).await;
+            let req = test::TestRequest::post()
+                .uri("/api/payment/khalti/initiate")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .set_json(serde_json::json!({ "product_id": laptop.to_string() }))
+                .to_request();
+            let initiated: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+            if case.parse::<Outcome>().is_ok() {
+                let scripted = client
+                    .post(format!("{}/mock/payments/{}", mock, initiated["pidx"].as_str().unwrap()))
+                    .json(&serde_json::json!({ "outcome": case }))
+                    .send()
+                    .await
+                    .unwrap();
+                assert!(scripted.status().is_success(), "{}", case);
+            }
+            refs.insert(case, initiated["purchase_order_id"].as_str().unwrap().to_string());
+        }
+
+        sqlx::query("UPDATE payments SET created_at = NOW() - INTERVAL '10 minutes' WHERE order_ref <> $1")
+            .bind(refs.get("fresh").map(String::as_str).unwrap_or(""))
+            .execute(pool)
+            .await
+            .unwrap();
+        refs
+    }
+
+    async fn payment_status(pool: &PgPool, order_ref: &str) -> String {
+        sqlx::query_scalar("SELECT status FROM payments WHERE order_ref = $1")
+            .bind(order_ref)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn stale_payments_are_settled_from_the_gateway(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let config = testutil::mock_khalti_config();
+        let cases = ["completed", "user_canceled", "unvisited", "amount_mismatch", "unknown", "fresh"];
+        let refs = stale_checkouts(&pool, &config, &cases).await;
+
+        // One the customer never got as far as Khalti with, and one Khalti has never heard of.
+        sqlx::query("UPDATE payments SET status = 'initiated' WHERE order_ref = $1")
+            .bind(&refs["completed"])
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE payments SET gateway_ref = 'no-such-pidx' WHERE order_ref = $1")
+            .bind(&refs["unknown"])
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let run = run_reconciliation(&pool, &config, &Gateways::from_config(&config), None).await.unwrap();
+        assert!(run.finished_at.is_some());
+        assert_eq!((run.checked, run.settled, run.unresolved), (5, 2, 2));
+
+        let items: HashMap<String, (String, String, Option<String>)> = get_run_items(&pool, run.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|i| (i.order_ref, (i.previous_status, i.outcome, i.new_status)))
+            .collect();
+        let item = |case: &str| items.get(&refs[case]).cloned().map(|(p, o, n)| (p, o, n.unwrap_or_default()));
+        let expected = |previous: &str, outcome: &str, new_status: &str| {
+            Some((previous.to_string(), outcome.to_string(), new_status.to_string()))
+        };
+
+        assert_eq!(item("completed"), expected("initiated", "settled", "completed"));
+        assert_eq!(item("user_canceled"), expected("pending", "settled", "user_canceled"));
+        assert_eq!(item("unvisited"), expected("pending", "pending", ""));
+        assert_eq!(item("amount_mismatch"), expected("pending", "amount_mismatch", ""));
+        assert_eq!(item("unknown"), expected("pending", "lookup_failed", ""));
+        assert_eq!(item("fresh"), None);
+
+        assert_eq!(payment_status(&pool, &refs["completed"]).await, "completed");
+        assert_eq!(payment_status(&pool, &refs["user_canceled"]).await, "user_canceled");
+        assert_eq!(payment_status(&pool, &refs["amount_mismatch"]).await, "pending");
+        let orders: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM orders o JOIN payments p ON p.id = o.payment_id WHERE p.order_ref = $1",
+        )
+        .bind(&refs["completed"])
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(orders, 1);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn a_payment_that_cannot_be_recorded_does_not_stop_the_run(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let config = testutil::mock_khalti_config();
+        let refs = stale_checkouts(&pool, &config, &["completed", "unvisited"]).await;
+
+        // The report refuses the completed payment's row.
+        let refuse_item = format!(
+            "CREATE FUNCTION refuse_item() RETURNS trigger LANGUAGE plpgsql AS $$
+             BEGIN
+                 IF NEW.payment_id = (SELECT id FROM payments WHERE order_ref = '{}') THEN
+                     RAISE EXCEPTION 'report unavailable';
+                 END IF;
+                 RETURN NEW;
+             END $$;
+             CREATE TRIGGER refuse_item BEFORE INSERT ON reconciliation_items
+                 FOR EACH ROW EXECUTE FUNCTION refuse_item();",
+            refs["completed"]
+        );
+        pool.execute(refuse_item.as_str()).await.unwrap();
+
+        let run = run_reconciliation(&pool, &config, &Gateways::from_config(&config), None).await.unwrap();
+        assert!(run.finished_at.is_some());
+        assert_eq!((run.checked, run.settled, run.unresolved), (2, 1, 1));
+
+        let items = get_run_items(&pool, run.id).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!((items[0].order_ref.as_str(), items[0].outcome.as_str()), (refs["unvisited"].as_str(), "pending"));
+        assert_eq!(payment_status(&pool, &refs["completed"]).await, "completed");
+    }
+}
@@ -0,0 +1,49 @@
+use sqlx::PgPool;
+use crate::config::Config;
+use crate::databases::payment::paymentdb::{apply_transition, get_payment, PaymentStatus, Transition};
+use crate::databases::shop::stockdb::expired_reservations;
+use crate::services::gateway::Gateways;
+use crate::services::reconciliation::{reconcile_payment, Reconciled};
+
+/// Expires payments whose stock reservation has run out, which puts their stock back on
+/// sale. A payment the gateway knows about is looked up one last time first, so money
+/// collected just before the deadline still places the order; if the gateway can't be
+/// reached the stock stays held until it can. Returns how many were expired.
+pub async fn release_expired_reservations(
+    pool: &PgPool,
+    config: &Config,
+    gateways: &Gateways,
+) -> anyhow::Result<usize> {
+    let mut expired = 0;
+
+    for payment_id in expired_reservations(pool).await? {
+        let Some(payment) = get_payment(pool, payment_id).await? else {
+            continue;
+        };
+
+        if let (Some(gateway), Some(_)) = (gateways.get(&payment.gateway), &payment.gateway_ref) {
+            match reconcile_payment(pool, config, gateway, &payment).await? {
+                Reconciled::StillPending { .. } => {}
+                Reconciled::NeedsReview { .. } | Reconciled::AmountMismatch { .. } => {
+                    eprintln!("❌ Payment {} needs review; keeping its stock held", payment_id);
+                    continue;
+                }
+                Reconciled::LookupFailed(e) => {
+                    eprintln!("❌ Could not look up payment {} before expiring it: {}", payment_id, e);
+                    continue;
+                }
+                Reconciled::Settled { .. } | Reconciled::Unchanged(_) | Reconciled::Rejected(_) => continue,
+            }
+        }
+
+        match apply_transition(pool, payment_id, PaymentStatus::Expired, Some("stock reservation expired")).await? {
+            Transition::Applied => expired += 1,
+            Transition::Unchanged => {}
+            Transition::Rejected(current) => {
+                eprintln!("❌ Payment {} is {} but still holds stock", payment_id, current.as_str());
+            }
+        }
+    }
+
+    Ok(expired)
+}
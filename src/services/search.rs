@@ -1,10 +1,15 @@
 use actix_web::{get, web, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
-use sqlx::{query_as_with, PgPool, FromRow, postgres::PgArguments};
-use sqlx::Arguments;
-use bigdecimal::ToPrimitive;
- use sqlx::types::BigDecimal;
-
+use sqlx::PgPool;
+use bigdecimal::{FromPrimitive, ToPrimitive};
+use sqlx::types::BigDecimal;
+use crate::services::catalog::filter::{
+    Condition, FilterField, FilterValue, FilterValues, ProductFilter, MAX_LIST_VALUES,
+};
+use crate::services::catalog::{CatalogQuery, CatalogSort, Laptop};
+
+/// Listing filters. Lists are comma-separated and match any of their values; text
+/// values match as part of the column, so `graphic=rtx 3050` finds "NVIDIA RTX 3050".
 #[derive(Deserialize)]
 pub struct ProductQuery {
     search: Option<String>,
@@ -13,6 +18,26 @@ pub struct ProductQuery {
     max_price: Option<f64>,
     random: Option<bool>,
     viewed: Option<String>,
+    ram: Option<String>,
+    min_ram: Option<i64>,
+    max_ram: Option<i64>,
+    storage: Option<String>,
+    min_storage: Option<i64>,
+    max_storage: Option<i64>,
+    storage_type: Option<String>,
+    graphic: Option<String>,
+    graphic_ram: Option<String>,
+    min_graphic_ram: Option<i64>,
+    max_graphic_ram: Option<i64>,
+    processor_series: Option<String>,
+    processor_generation: Option<String>,
+    display_type: Option<String>,
+    touchscreen: Option<bool>,
+    model_year: Option<String>,
+    min_year: Option<i64>,
+    max_year: Option<i64>,
+    suitable_for: Option<String>,
+    in_stock: Option<bool>,
 }
 
 #[derive(Serialize)]
@@ -24,38 +49,18 @@ pub struct LaptopResponse {
     tag: String,
 }
 
-#[derive( FromRow, Clone)]
-pub struct Laptop {
-    id: i32,
-    display_name: String,
-    brand_name: String,
-    model_name: String,
-    model_year: Option<i32>,
-    product_authentication: Option<String>,
-    product_type: Option<String>,
-    processor: Option<String>,
-    processor_generation: Option<String>,
-    processor_series: Option<String>,
-    ram: Option<i32>,
-    ram_type: Option<String>,
-    storage: Option<i32>,
-    storage_type: Option<String>,
-    graphic: Option<String>,
-    graphic_ram: Option<i32>,
-    battery: Option<String>,
-    touchscreen: Option<bool>,
-    show_price: BigDecimal,
-    face_image_url: Option<String>,
-}
-
-#[allow(unused_assignments)]
 #[get("/api/productshow/getproduct")]
 async fn get_filtered_products(
     pool: web::Data<PgPool>,
     query: web::Query<ProductQuery>,
 ) -> impl Responder {
+    let filters = match catalog_filters(&query) {
+        Ok(filters) => filters,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+
     if query.random.unwrap_or(false) {
-    return match get_random_laptops(pool.get_ref(), &query).await {
+    return match get_random_laptops(pool.get_ref(), &query, filters).await {
         Ok(results) => {
             let response_vec: Vec<LaptopResponse> = results.into_iter()
                 .map(map_to_response)
@@ -69,7 +74,7 @@ async fn get_filtered_products(
     };
 }
     if let Some(viewed_ids) = &query.viewed {
-    return match recommendation_list(pool.get_ref(), viewed_ids, &query).await {
+    return match recommendation_list(pool.get_ref(), viewed_ids, &query, filters).await {
         Ok(results) => {
             let response_vec: Vec<LaptopResponse> = results.into_iter()
                 .map(map_to_response)
@@ -83,218 +88,125 @@ async fn get_filtered_products(
     };
 }
 
-    let mut sql = String::from("SELECT id, brand_name, model_name, show_price, face_image_url FROM laptop_details WHERE 1=1");
-    let mut args = PgArguments::default();
-    let mut param_index = 1;
-
-    if let Some(brands) = &query.brands {
-        let brand_list: Vec<&str> = brands.split(',').collect();
-        if !brand_list.is_empty() {
-            sql.push_str(" AND (");
-            for (i, brand) in brand_list.iter().enumerate() {
-                if i > 0 {
-                    sql.push_str(" OR ");
-                }
-                sql.push_str(&format!("brand_name ILIKE ${}", param_index));
-                args.add(format!("%{}%", brand));
-                param_index += 1;
-            }
-            sql.push(')');
-        }
-    }
-
-    if let Some(min_price) = query.min_price {
-        sql.push_str(&format!(" AND show_price >= ${}", param_index));
-        args.add(min_price);
-        param_index += 1;
+    let laptops = CatalogQuery {
+        filters,
+        search: query.search.clone(),
+        in_stock: query.in_stock.unwrap_or(false),
+        ..Default::default()
     }
+    .fetch(pool.get_ref())
+    .await;
 
-    if let Some(max_price) = query.max_price {
-        sql.push_str(&format!(" AND show_price <= ${}", param_index));
-        args.add(max_price);
-        param_index += 1;
+    match laptops {
+        Ok(results) => {
+            let response_vec: Vec<LaptopResponse> = results.into_iter()
+                .map(map_to_response)
+                .collect();
+            HttpResponse::Ok().json(response_vec)
+        },
+        Err(err) => {
+            eprintln!("Product search error: {:?}", err);
+            HttpResponse::InternalServerError().body("Failed to fetch products")
+        }
     }
+}
 
-        if let Some(search) = &query.search {
-        sql.push_str(&format!(
-            " AND to_tsvector('english', \
-                coalesce(brand_name, '') || ' ' || \
-                coalesce(model_name, '') || ' ' || \
-                coalesce(display_name, '') || ' ' || \
-                coalesce(product_type, '') || ' ' || \
-                coalesce(product_authentication, '') || ' ' || \
-                coalesce(suitable_for, '') || ' ' || \
-                coalesce(color, '') || ' ' || \
-                coalesce(processor_generation, '') || ' ' || \
-                coalesce(processor, '') || ' ' || \
-                coalesce(processor_series, '') || ' ' || \
-                coalesce(ram_type, '') || ' ' || \
-                coalesce(storage_type, '') || ' ' || \
-                coalesce(graphic, '') || ' ' || \
-                coalesce(display, '') || ' ' || \
-                coalesce(display_type, '') || ' ' || \
-                coalesce(power_supply, '') || ' ' || \
-                coalesce(battery, '') || ' ' || \
-                coalesce(warranty, '') \
-            ) @@ plainto_tsquery('english', ${})",
-            param_index
-        ));
-        args.add(search);
-        param_index += 1;
-
-        sql.push_str(&format!(
-            " ORDER BY ts_rank( \
-                to_tsvector('english', \
-                    coalesce(brand_name, '') || ' ' || \
-                    coalesce(model_name, '') || ' ' || \
-                    coalesce(display_name, '') || ' ' || \
-                    coalesce(product_type, '') || ' ' || \
-                    coalesce(product_authentication, '') || ' ' || \
-                    coalesce(suitable_for, '') || ' ' || \
-                    coalesce(color, '') || ' ' || \
-                    coalesce(processor_generation, '') || ' ' || \
-                    coalesce(processor, '') || ' ' || \
-                    coalesce(processor_series, '') || ' ' || \
-                    coalesce(ram_type, '') || ' ' || \
-                    coalesce(storage_type, '') || ' ' || \
-                    coalesce(graphic, '') || ' ' || \
-                    coalesce(display, '') || ' ' || \
-                    coalesce(display_type, '') || ' ' || \
-                    coalesce(power_supply, '') || ' ' || \
-                    coalesce(battery, '') || ' ' || \
-                    coalesce(warranty, '') \
-                ), plainto_tsquery('english', ${}) \
-            ) DESC",
-            param_index - 1
-        ));
+/// The listing's filters, in the catalog's terms. Fails with a message for the shopper
+/// when a list holds something that isn't a number, or more than `MAX_LIST_VALUES` values.
+fn catalog_filters(query: &ProductQuery) -> Result<Vec<ProductFilter>, String> {
+    let mut filters = Vec::new();
+
+    let text_filters = [
+        (FilterField::BrandName, &query.brands),
+        (FilterField::StorageType, &query.storage_type),
+        (FilterField::Graphic, &query.graphic),
+        (FilterField::ProcessorSeries, &query.processor_series),
+        (FilterField::ProcessorGeneration, &query.processor_generation),
+        (FilterField::DisplayType, &query.display_type),
+        (FilterField::SuitableFor, &query.suitable_for),
+    ];
+    for (field, values) in text_filters {
+        let values = split_list(field, values)?;
+        if !values.is_empty() {
+            filters.push(ProductFilter { field, condition: Condition::ContainsAny(values) });
+        }
     }
 
-    let laptops = query_as_with::<_, Laptop, _>(&sql, args)
-        .fetch_all(pool.get_ref())
-        .await;
-match laptops {
-    Ok(results) if !results.is_empty() => {
-        let response_vec: Vec<LaptopResponse> = results.into_iter()
-            .map(map_to_response)
-            .collect();
-        HttpResponse::Ok().json(response_vec)
-    },
-    _ => HttpResponse::Ok().json(Vec::<LaptopResponse>::new()),
-}
-}
-
-#[allow(unused_assignments)]
-async fn get_random_laptops(
-    pool: &PgPool,
-    query: &ProductQuery,
-) -> Result<Vec<Laptop>, sqlx::Error> {
-    let mut sql = String::from(
-    "SELECT id, display_name, brand_name, model_name, model_year, product_authentication, product_type,
-       processor, processor_generation, processor_series, ram, ram_type,
-       storage, storage_type, graphic, graphic_ram, battery, touchscreen, show_price, face_image_url
- FROM laptop_details WHERE 1=1",
-);
-    let mut args = PgArguments::default();
-    let mut param_index = 1;
-
-    if let Some(brands) = &query.brands {
-        let brand_list: Vec<&str> = brands.split(',').collect();
-        if !brand_list.is_empty() {
-            sql.push_str(" AND (");
-            for (i, brand) in brand_list.iter().enumerate() {
-                if i > 0 {
-                    sql.push_str(" OR ");
-                }
-                sql.push_str(&format!("brand_name ILIKE ${}", param_index));
-                args.add(format!("%{}%", brand));
-                param_index += 1;
-            }
-            sql.push(')');
+    let number_filters = [
+        (FilterField::Ram, &query.ram, query.min_ram, query.max_ram),
+        (FilterField::Storage, &query.storage, query.min_storage, query.max_storage),
+        (FilterField::GraphicRam, &query.graphic_ram, query.min_graphic_ram, query.max_graphic_ram),
+        (FilterField::ModelYear, &query.model_year, query.min_year, query.max_year),
+    ];
+    for (field, values, min, max) in number_filters {
+        let values = split_list(field, values)?
+            .iter()
+            .map(|v| v.parse::<i64>().map_err(|_| format!("{} must be a list of numbers", field.column())))
+            .collect::<Result<Vec<i64>, String>>()?;
+        if !values.is_empty() {
+            filters.push(ProductFilter { field, condition: Condition::In(FilterValues::Integer(values)) });
+        }
+        if let Some(min) = min {
+            filters.push(ProductFilter { field, condition: Condition::Gte(FilterValue::Integer(min)) });
+        }
+        if let Some(max) = max {
+            filters.push(ProductFilter { field, condition: Condition::Lte(FilterValue::Integer(max)) });
         }
     }
 
-    if let Some(min_price) = query.min_price {
-        sql.push_str(&format!(" AND show_price >= ${}", param_index));
-        args.add(min_price);
-        param_index += 1;
+    if let Some(touchscreen) = query.touchscreen {
+        filters.push(ProductFilter { field: FilterField::Touchscreen, condition: Condition::Eq(FilterValue::Boolean(touchscreen)) });
     }
 
-    if let Some(max_price) = query.max_price {
-        sql.push_str(&format!(" AND show_price <= ${}", param_index));
-        args.add(max_price);
-        param_index += 1;
+    if let Some(min_price) = query.min_price.and_then(BigDecimal::from_f64) {
+        filters.push(ProductFilter { field: FilterField::ShowPrice, condition: Condition::Gte(FilterValue::Price(min_price)) });
     }
 
-    if let Some(search) = &query.search {
-        sql.push_str(&format!(
-            " AND to_tsvector('english', \
-                coalesce(brand_name, '') || ' ' || \
-                coalesce(model_name, '') || ' ' || \
-                coalesce(display_name, '') || ' ' || \
-                coalesce(product_type, '') || ' ' || \
-                coalesce(product_authentication, '') || ' ' || \
-                coalesce(suitable_for, '') || ' ' || \
-                coalesce(color, '') || ' ' || \
-                coalesce(processor_generation, '') || ' ' || \
-                coalesce(processor, '') || ' ' || \
-                coalesce(processor_series, '') || ' ' || \
-                coalesce(ram_type, '') || ' ' || \
-                coalesce(storage_type, '') || ' ' || \
-                coalesce(graphic, '') || ' ' || \
-                coalesce(display, '') || ' ' || \
-                coalesce(display_type, '') || ' ' || \
-                coalesce(power_supply, '') || ' ' || \
-                coalesce(battery, '') || ' ' || \
-                coalesce(warranty, '') \
-            ) @@ plainto_tsquery('english', ${})",
-            param_index
-        ));
-        args.add(search);
-        param_index += 1;
-
-        sql.push_str(&format!(
-            " ORDER BY ts_rank( \
-                to_tsvector('english', \
-                    coalesce(brand_name, '') || ' ' || \
-                    coalesce(model_name, '') || ' ' || \
-                    coalesce(display_name, '') || ' ' || \
-                    coalesce(product_type, '') || ' ' || \
-                    coalesce(product_authentication, '') || ' ' || \
-                    coalesce(suitable_for, '') || ' ' || \
-                    coalesce(color, '') || ' ' || \
-                    coalesce(processor_generation, '') || ' ' || \
-                    coalesce(processor, '') || ' ' || \
-                    coalesce(processor_series, '') || ' ' || \
-                    coalesce(ram_type, '') || ' ' || \
-                    coalesce(storage_type, '') || ' ' || \
-                    coalesce(graphic, '') || ' ' || \
-                    coalesce(display, '') || ' ' || \
-                    coalesce(display_type, '') || ' ' || \
-                    coalesce(power_supply, '') || ' ' || \
-                    coalesce(battery, '') || ' ' || \
-                    coalesce(warranty, '') \
-                ), plainto_tsquery('english', ${}) \
-            ) DESC",
-            param_index - 1
-        ));
-    }else{
-        sql.push_str(" ORDER BY RANDOM()");
+    if let Some(max_price) = query.max_price.and_then(BigDecimal::from_f64) {
+        filters.push(ProductFilter { field: FilterField::ShowPrice, condition: Condition::Lte(FilterValue::Price(max_price)) });
     }
 
-    sql.push_str(" LIMIT 16");
+    Ok(filters)
+}
 
-    let laptops = query_as_with::<_, Laptop, _>(&sql, args)
-        .fetch_all(pool)
-        .await?;
+fn split_list(field: FilterField, values: &Option<String>) -> Result<Vec<String>, String> {
+    let values: Vec<String> = values
+        .as_deref()
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+        .collect();
 
-    Ok(laptops)
+    if values.len() > MAX_LIST_VALUES {
+        return Err(format!("{} takes at most {} values", field.column(), MAX_LIST_VALUES));
+    }
+    Ok(values)
+}
+
+async fn get_random_laptops(
+    pool: &PgPool,
+    query: &ProductQuery,
+    filters: Vec<ProductFilter>,
+) -> Result<Vec<Laptop>, sqlx::Error> {
+    CatalogQuery {
+        filters,
+        search: query.search.clone(),
+        in_stock: query.in_stock.unwrap_or(false),
+        sort: if query.search.is_some() { CatalogSort::Relevance } else { CatalogSort::Random },
+        limit: Some(16),
+        ..Default::default()
+    }
+    .fetch(pool)
+    .await
 }
 
-#[allow(unused_assignments)]
 async fn recommendation_list(
     pool: &PgPool,
     viewed_ids: &str,
     query: &ProductQuery,
+    filters: Vec<ProductFilter>,
 ) -> Result<Vec<Laptop>, sqlx::Error> {
     let ids: Vec<i32> = viewed_ids
         .split(',')
@@ -302,130 +214,25 @@ async fn recommendation_list(
         .collect();
 
     if ids.is_empty() {
-        return get_random_laptops(pool, query).await;
-    }
-
-    let placeholders = ids
-        .iter()
-        .enumerate()
-        .map(|(i, _)| format!("${}", i + 1))
-        .collect::<Vec<_>>()
-        .join(",");
-
-  let sql = format!(
-    "SELECT id, display_name, brand_name, model_name, model_year, product_authentication, product_type,
-       processor, processor_generation, processor_series, ram, ram_type,
-       storage, storage_type, graphic, graphic_ram, battery, touchscreen, show_price, face_image_url
- FROM laptop_details WHERE id IN ({})",
-    placeholders
-);
-
-    let mut args = PgArguments::default();
-    for id in &ids {
-        args.add(*id);
+        return get_random_laptops(pool, query, filters).await;
     }
 
-    let viewed_laptops = query_as_with::<_, Laptop, _>(&sql, args)
-        .fetch_all(pool)
+    let viewed_laptops = CatalogQuery { ids: Some(ids), ..Default::default() }
+        .fetch(pool)
         .await?;
 
     if viewed_laptops.is_empty() {
-        return get_random_laptops(pool, query).await;
+        return get_random_laptops(pool, query, filters).await;
     }
 
-    let mut filter_sql =
-        String::from("SELECT id, display_name, brand_name, model_name, model_year, product_authentication, product_type,
-       processor, processor_generation, processor_series, ram, ram_type,
-       storage, storage_type, graphic, graphic_ram, battery, touchscreen, show_price, face_image_url
-    FROM laptop_details WHERE 1=1");
-    let mut filter_args = PgArguments::default();
-    let mut param_index = 1;
-
-    if let Some(brands) = &query.brands {
-        let brand_list: Vec<&str> = brands.split(',').collect();
-        if !brand_list.is_empty() {
-            filter_sql.push_str(" AND (");
-            for (i, brand) in brand_list.iter().enumerate() {
-                if i > 0 {
-                    filter_sql.push_str(" OR ");
-                }
-                filter_sql.push_str(&format!("brand_name ILIKE ${}", param_index));
-                filter_args.add(format!("%{}%", brand));
-                param_index += 1;
-            }
-            filter_sql.push(')');
-        }
+    let all_candidates = CatalogQuery {
+        filters,
+        search: query.search.clone(),
+        in_stock: query.in_stock.unwrap_or(false),
+        ..Default::default()
     }
-
-    if let Some(min_price) = query.min_price {
-        filter_sql.push_str(&format!(" AND show_price >= ${}", param_index));
-        filter_args.add(min_price);
-        param_index += 1;
-    }
-
-    if let Some(max_price) = query.max_price {
-        filter_sql.push_str(&format!(" AND show_price <= ${}", param_index));
-        filter_args.add(max_price);
-        param_index += 1;
-    }
-
-    if let Some(search) = &query.search {
-        filter_sql.push_str(&format!(
-            " AND to_tsvector('english', \
-                coalesce(brand_name, '') || ' ' || \
-                coalesce(model_name, '') || ' ' || \
-                coalesce(display_name, '') || ' ' || \
-                coalesce(product_type, '') || ' ' || \
-                coalesce(product_authentication, '') || ' ' || \
-                coalesce(suitable_for, '') || ' ' || \
-                coalesce(color, '') || ' ' || \
-                coalesce(processor_generation, '') || ' ' || \
-                coalesce(processor, '') || ' ' || \
-                coalesce(processor_series, '') || ' ' || \
-                coalesce(ram_type, '') || ' ' || \
-                coalesce(storage_type, '') || ' ' || \
-                coalesce(graphic, '') || ' ' || \
-                coalesce(display, '') || ' ' || \
-                coalesce(display_type, '') || ' ' || \
-                coalesce(power_supply, '') || ' ' || \
-                coalesce(battery, '') || ' ' || \
-                coalesce(warranty, '') \
-            ) @@ plainto_tsquery('english', ${})",
-            param_index
-        ));
-        filter_args.add(search);
-        param_index += 1;
-
-        filter_sql.push_str(&format!(
-            " ORDER BY ts_rank( \
-                to_tsvector('english', \
-                    coalesce(brand_name, '') || ' ' || \
-                    coalesce(model_name, '') || ' ' || \
-                    coalesce(display_name, '') || ' ' || \
-                    coalesce(product_type, '') || ' ' || \
-                    coalesce(product_authentication, '') || ' ' || \
-                    coalesce(suitable_for, '') || ' ' || \
-                    coalesce(color, '') || ' ' || \
-                    coalesce(processor_generation, '') || ' ' || \
-                    coalesce(processor, '') || ' ' || \
-                    coalesce(processor_series, '') || ' ' || \
-                    coalesce(ram_type, '') || ' ' || \
-                    coalesce(storage_type, '') || ' ' || \
-                    coalesce(graphic, '') || ' ' || \
-                    coalesce(display, '') || ' ' || \
-                    coalesce(display_type, '') || ' ' || \
-                    coalesce(power_supply, '') || ' ' || \
-                    coalesce(battery, '') || ' ' || \
-                    coalesce(warranty, '') \
-                ), plainto_tsquery('english', ${}) \
-            ) DESC",
-            param_index - 1
-        ));
-    }
-
-    let all_candidates = query_as_with::<_, Laptop, _>(&filter_sql, filter_args)
-        .fetch_all(pool)
-        .await?;
+    .fetch(pool)
+    .await?;
 
     let mut scored: Vec<(Laptop, f64)> = all_candidates
     .into_iter()
@@ -509,11 +316,8 @@ async fn recommendation_list(
 }
 
 fn map_to_response(laptop: Laptop) -> LaptopResponse {
+    let tag = laptop.tag();
     let display_name = laptop.display_name;
-    let tag = laptop
-        .product_authentication
-        .clone()
-        .unwrap_or_else(|| "Performance Laptop".to_string());
 
     LaptopResponse {
         id: laptop.id.to_string(),
@@ -526,4 +330,108 @@ fn map_to_response(laptop: Laptop) -> LaptopResponse {
 
 pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(get_filtered_products);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use crate::testutil;
+
+    // Brand, model, RAM, storage, cost before tax (sold for 1.18 times that), quantity.
+    const LAPTOPS: [(&str, &str, i32, i32, i32, i32); 5] = [
+        ("Lenovo", "ThinkPad", 8, 256, 50_000, 2),
+        ("Lenovo", "Legion", 16, 512, 100_000, 0),
+        ("ASUS", "Zenbook", 16, 1024, 80_000, 1),
+        ("HP", "Victus", 32, 512, 120_000, 4),
+        ("Dell", "XPS", 12, 512, 150_000, 1),
+    ];
+
+    async fn seed(pool: &PgPool) {
+        for (brand, model, ram, storage, cost, quantity) in LAPTOPS {
+            sqlx::query(
+                "INSERT INTO laptop_details (brand_name, model_name, display_name, ram, storage, cost_price, quantity)
+                 VALUES ($1, $2, $1 || ' ' || $2, $3, $4, $5, $6)"
+            )
+            .bind(brand)
+            .bind(model)
+            .bind(ram)
+            .bind(storage)
+            .bind(cost)
+            .bind(quantity)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn listing_filters_narrow_the_catalog(pool: PgPool) {
+        testutil::setup(&pool).await;
+        seed(&pool).await;
+        let app = test::init_service(App::new().app_data(web::Data::new(pool.clone())).configure(init)).await;
+
+        let cases: [(&str, &[&str]); 11] = [
+            ("brands=lenovo,asus", &["Lenovo ThinkPad", "Lenovo Legion", "ASUS Zenbook"]),
+            ("brands=%20Lenovo%20,%20,asus%20", &["Lenovo ThinkPad", "Lenovo Legion", "ASUS Zenbook"]),
+            ("ram=8,16", &["Lenovo ThinkPad", "Lenovo Legion", "ASUS Zenbook"]),
+            ("ram=16&storage=512,256", &["Lenovo Legion"]),
+            ("min_ram=12&max_ram=16", &["Lenovo Legion", "ASUS Zenbook", "Dell XPS"]),
+            ("min_storage=1000", &["ASUS Zenbook"]),
+            ("min_price=90000&max_price=150000", &["Lenovo Legion", "ASUS Zenbook", "HP Victus"]),
+            ("max_price=59000", &["Lenovo ThinkPad"]),
+            ("in_stock=true", &["Lenovo ThinkPad", "ASUS Zenbook", "HP Victus", "Dell XPS"]),
+            ("in_stock=false", &["Lenovo ThinkPad", "Lenovo Legion", "ASUS Zenbook", "HP Victus", "Dell XPS"]),
+            ("brands=lenovo&in_stock=true", &["Lenovo ThinkPad"]),
+        ];
+
+        for (params, expected) in cases {
+            let req = test::TestRequest::get().uri(&format!("/api/productshow/getproduct?{}", params)).to_request();
+            let found: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+            let names: Vec<&str> = found.iter().map(|l| l["display_name"].as_str().unwrap()).collect();
+            assert_eq!(names, expected, "{}", params);
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn lists_of_numbers_must_be_numbers(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(App::new().app_data(web::Data::new(pool.clone())).configure(init)).await;
+
+        for (params, message) in [
+            ("ram=16,abc", "ram must be a list of numbers"),
+            ("storage=512gb", "storage must be a list of numbers"),
+            ("model_year=2023,2024.5", "model_year must be a list of numbers"),
+        ] {
+            let req = test::TestRequest::get().uri(&format!("/api/productshow/getproduct?{}", params)).to_request();
+            let response = test::call_service(&app, req).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", params);
+            assert_eq!(test::read_body(response).await, message.as_bytes(), "{}", params);
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn lists_are_capped(pool: PgPool) {
+        testutil::setup(&pool).await;
+        seed(&pool).await;
+        let app = test::init_service(App::new().app_data(web::Data::new(pool.clone())).configure(init)).await;
+
+        let list = |len: usize| (1..=len).map(|n| n.to_string()).collect::<Vec<_>>().join(",");
+        let longest = list(MAX_LIST_VALUES);
+        let over = list(MAX_LIST_VALUES + 1);
+
+        let req = test::TestRequest::get().uri(&format!("/api/productshow/getproduct?ram={}", longest)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        for (params, message) in [
+            (format!("ram={}", over), "ram takes at most 20 values"),
+            (format!("brands={}", over), "brand_name takes at most 20 values"),
+        ] {
+            let req = test::TestRequest::get().uri(&format!("/api/productshow/getproduct?{}", params)).to_request();
+            let response = test::call_service(&app, req).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", params);
+            assert_eq!(test::read_body(response).await, message.as_bytes(), "{}", params);
+        }
+    }
+}
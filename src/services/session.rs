@@ -0,0 +1,153 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{error, web, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+use sqlx::PgPool;
+use crate::databases::auth::sessiondb::find_session_user;
+use crate::config::Config;
+
+/// The logged-in caller, resolved from the `Authorization: Bearer <token>` header.
+/// Handlers take this as an argument instead of trusting a user id from the request.
+pub struct AuthUser {
+    pub id: i32,
+    pub status: String,
+    pub two_factor_enabled: bool,
+    pub token: String,
+}
+
+impl AuthUser {
+    pub fn is_admin(&self) -> bool {
+        self.status == "admin"
+    }
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+}
+
+impl FromRequest for AuthUser {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let pool = req.app_data::<web::Data<PgPool>>().cloned();
+        let token = bearer_token(req);
+
+        Box::pin(async move {
+            let pool = pool.ok_or_else(|| error::ErrorInternalServerError("Database unavailable"))?;
+            let token = token.ok_or_else(|| error::ErrorUnauthorized("Missing session token"))?;
+
+            match find_session_user(pool.get_ref(), &token).await {
+                Ok(Some(user)) => Ok(AuthUser {
+                    id: user.id,
+                    status: user.status,
+                    two_factor_enabled: user.two_factor_enabled,
+                    token,
+                }),
+                Ok(None) => Err(error::ErrorUnauthorized("Invalid or expired session")),
+                Err(e) => {
+                    eprintln!("❌ Session lookup failed: {:?}", e);
+                    Err(error::ErrorInternalServerError("Database error"))
+                }
+            }
+        })
+    }
+}
+
+/// Scope middleware: only sessions whose `logininfo.status` is 'admin' get through.
+/// Missing or expired sessions are rejected with 401, everyone else with 403. When
+/// `admin.require_2fa` is on, admins must also have enrolled TOTP.
+pub async fn require_admin(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let user = req.extract::<AuthUser>().await?;
+
+    if !user.is_admin() {
+        return Err(error::ErrorForbidden("Admin access required"));
+    }
+
+    // Fail closed if the config is somehow missing from the app.
+    let require_2fa = req
+        .app_data::<web::Data<Config>>()
+        .map(|c| c.admin.require_2fa)
+        .unwrap_or(true);
+
+    if require_2fa && !user.two_factor_enabled {
+        return Err(error::ErrorForbidden("Two-factor authentication must be enabled for admin accounts"));
+    }
+
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App, HttpResponse};
+    use crate::routes::login::logout;
+    use crate::testutil;
+
+    async fn whoami(user: AuthUser) -> HttpResponse {
+        HttpResponse::Ok().json(serde_json::json!({ "id": user.id }))
+    }
+
+    fn whoami_as(authorization: &str) -> test::TestRequest {
+        test::TestRequest::get().uri("/whoami").insert_header(("Authorization", authorization))
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn only_live_bearer_tokens_get_through(pool: PgPool) {
+        testutil::setup(&pool).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .route("/whoami", web::get().to(whoami))
+                .route("/logout", web::post().to(logout)),
+        )
+        .await;
+        let user = testutil::create_user(&pool, "user").await;
+        let token = testutil::login(&pool, user).await;
+
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, whoami_as(&format!("Bearer {}", token)).to_request()).await;
+        assert_eq!(body["id"], user);
+
+        let req = test::TestRequest::get().uri("/whoami").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+        let malformed = [
+            token.clone(),
+            format!("Basic {}", token),
+            format!("bearer {}", token),
+            "Bearer ".to_string(),
+            "Bearer not-a-session".to_string(),
+        ];
+        for malformed in malformed {
+            let resp = test::call_service(&app, whoami_as(&malformed).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{:?}", malformed);
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/logout")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let resp = test::call_service(&app, whoami_as(&format!("Bearer {}", token)).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let expired = testutil::login(&pool, user).await;
+        sqlx::query("UPDATE sessions SET expires_at = NOW() - INTERVAL '1 second' WHERE user_id = $1")
+            .bind(user)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let resp = test::call_service(&app, whoami_as(&format!("Bearer {}", expired)).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -1,10 +1,10 @@
 use actix_web::{get, web, HttpResponse, Responder};
 use serde::Serialize;
 use sqlx::PgPool;
-use sqlx::FromRow;
 use serde::Deserialize;
+use crate::services::catalog::{CatalogQuery, SearchMode};
 
-#[derive(Serialize, FromRow)]
+#[derive(Serialize)]
 pub struct LaptopResponse {
     id: i32,
     image: Option<String>,
@@ -18,72 +18,36 @@ pub struct SuggestionQuery {
     search: Option<String>,
 }
 
+fn suggestion_query(search: &str) -> CatalogQuery {
+    CatalogQuery {
+        search: Some(search.to_string()),
+        search_mode: SearchMode::Autocomplete,
+        limit: Some(5),
+        ..Default::default()
+    }
+}
+
 #[get("/api/productshow/suggestion")]
 async fn get_suggestion(
     pool: web::Data<PgPool>,
     query: web::Query<SuggestionQuery>,
 ) -> impl Responder {
     if let Some(search) = &query.search {
-        let sql = r#"
-            SELECT 
-                id,
-                face_image_url as image,
-                display_name,
-                show_price::TEXT,
-                product_authentication as tag
-            FROM laptop_details
-            WHERE to_tsvector('english', 
-                coalesce(brand_name, '') || ' ' || 
-                coalesce(model_name, '') || ' ' || 
-                coalesce(display_name, '') || ' ' || 
-                coalesce(product_type, '') || ' ' || 
-                coalesce(product_authentication, '') || ' ' || 
-                coalesce(suitable_for, '') || ' ' || 
-                coalesce(color, '') || ' ' || 
-                coalesce(processor_generation, '') || ' ' || 
-                coalesce(processor, '') || ' ' || 
-                coalesce(processor_series, '') || ' ' || 
-                coalesce(ram_type, '') || ' ' || 
-                coalesce(storage_type, '') || ' ' || 
-                coalesce(graphic, '') || ' ' || 
-                coalesce(display, '') || ' ' || 
-                coalesce(display_type, '') || ' ' || 
-                coalesce(power_supply, '') || ' ' || 
-                coalesce(battery, '') || ' ' || 
-                coalesce(warranty, '')
-            ) @@ plainto_tsquery('english', $1)
-            ORDER BY ts_rank(
-                to_tsvector('english', 
-                    coalesce(brand_name, '') || ' ' || 
-                    coalesce(model_name, '') || ' ' || 
-                    coalesce(display_name, '') || ' ' || 
-                    coalesce(product_type, '') || ' ' || 
-                    coalesce(product_authentication, '') || ' ' || 
-                    coalesce(suitable_for, '') || ' ' || 
-                    coalesce(color, '') || ' ' || 
-                    coalesce(processor_generation, '') || ' ' || 
-                    coalesce(processor, '') || ' ' || 
-                    coalesce(processor_series, '') || ' ' || 
-                    coalesce(ram_type, '') || ' ' || 
-                    coalesce(storage_type, '') || ' ' || 
-                    coalesce(graphic, '') || ' ' || 
-                    coalesce(display, '') || ' ' || 
-                    coalesce(display_type, '') || ' ' || 
-                    coalesce(power_supply, '') || ' ' || 
-                    coalesce(battery, '') || ' ' || 
-                    coalesce(warranty, '')
-                ), plainto_tsquery('english', $1)
-            ) DESC
-            LIMIT 5
-        "#;
-
-        let laptops = sqlx::query_as::<_, LaptopResponse>(sql)
-            .bind(search)
-            .fetch_all(pool.get_ref())
-            .await;
+        let laptops = suggestion_query(search).fetch(pool.get_ref()).await;
 
         match laptops {
-            Ok(results) => HttpResponse::Ok().json(results),
+            Ok(results) => {
+                let suggestions: Vec<LaptopResponse> = results.into_iter()
+                    .map(|laptop| LaptopResponse {
+                        id: laptop.id,
+                        tag: laptop.tag(),
+                        image: laptop.face_image_url,
+                        display_name: laptop.display_name,
+                        show_price: laptop.show_price.with_scale(2).to_string(),
+                    })
+                    .collect();
+                HttpResponse::Ok().json(suggestions)
+            }
             Err(e) => {
                 eprintln!("DB error: {:?}", e);
                 HttpResponse::InternalServerError().finish()
@@ -96,4 +60,89 @@ async fn get_suggestion(
 
 pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(get_suggestion);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use crate::testutil;
+
+    const LAPTOPS: [(&str, &str, &str, &str); 6] = [
+        ("Lenovo", "ThinkPad E14", "Intel Core i5-1235U", "Intel Iris Xe"),
+        ("Lenovo", "IdeaPad Slim 3", "AMD Ryzen 7 5700U", "AMD Radeon"),
+        ("ASUS", "TUF Gaming F15", "Intel Core i7-12700H", "NVIDIA GeForce RTX 3050"),
+        ("Acer", "Nitro V 15", "Intel Core i5-13420H", "NVIDIA GeForce RTX 4050"),
+        ("HP", "Pavilion 15", "AMD Ryzen 5 5600H", "AMD Radeon"),
+        ("Dell", "Inspiron 14", "Intel Core i3-1215U", "Intel UHD Graphics"),
+    ];
+
+    async fn seed(pool: &PgPool) {
+        for (brand, model, processor, graphic) in LAPTOPS {
+            sqlx::query(
+                "INSERT INTO laptop_details (brand_name, model_name, display_name, processor, graphic, cost_price, quantity)
+                 VALUES ($1, $2, $1 || ' ' || $2, $3, $4, 1000, 1)"
+            )
+            .bind(brand)
+            .bind(model)
+            .bind(processor)
+            .bind(graphic)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn unfinished_and_misspelt_words_find_laptops(pool: PgPool) {
+        testutil::setup(&pool).await;
+        seed(&pool).await;
+        let app = test::init_service(App::new().app_data(web::Data::new(pool.clone())).configure(init)).await;
+
+        let cases = [
+            // Unfinished: every Lenovo, by prefix.
+            ("lenov", vec!["Lenovo ThinkPad E14", "Lenovo IdeaPad Slim 3"]),
+            // Missing its space and a digit: the RTX 3050 first, the RTX 4050 close behind.
+            ("rtx305", vec!["ASUS TUF Gaming F15", "Acer Nitro V 15"]),
+            // Misspelt.
+            ("thinkpd", vec!["Lenovo ThinkPad E14"]),
+        ];
+
+        for (search, expected) in cases {
+            let req = test::TestRequest::get()
+                .uri(&format!("/api/productshow/suggestion?search={}", search))
+                .to_request();
+            let found: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+            let names: Vec<&str> = found.iter().map(|l| l["display_name"].as_str().unwrap()).collect();
+            assert_eq!(names, expected, "{}", search);
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn fuzzy_match_uses_the_trigram_index(pool: PgPool) {
+        testutil::setup(&pool).await;
+        seed(&pool).await;
+
+        let query = suggestion_query("thinkpd").build();
+        let mut tx = pool.begin().await.unwrap();
+        // A handful of rows is cheaper to scan than to look up, so take the scan away to
+        // see which index the planner would reach for on a real catalog.
+        sqlx::query("SET LOCAL enable_seqscan = off").execute(&mut *tx).await.unwrap();
+        // The values build() binds, in order: prefixes, fuzzy text, prefixes twice more
+        // for ranking, fuzzy text again, then the limit.
+        let plan: Vec<String> = sqlx::query_scalar(&format!("EXPLAIN {}", query.sql()))
+            .bind("thinkpd:*")
+            .bind("thinkpd")
+            .bind("thinkpd:*")
+            .bind("thinkpd:*")
+            .bind("thinkpd")
+            .bind(5_i64)
+            .fetch_all(&mut *tx)
+            .await
+            .unwrap();
+        let plan = plan.join("\n");
+
+        assert!(plan.contains("Bitmap Index Scan on laptop_details_suggestion_trgm_idx"), "{}", plan);
+        assert!(!plan.contains("Seq Scan"), "{}", plan);
+    }
+}
@@ -0,0 +1,150 @@
+use base32::Alphabet;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rand_core::OsRng;
+use sha1::Sha1;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// RFC 6238 defaults, which is what every authenticator app expects.
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+const ISSUER: &str = "ePasal";
+const SECRET_ALPHABET: Alphabet = Alphabet::RFC4648 { padding: false };
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    OsRng.fill_bytes(&mut bytes);
+    base32::encode(SECRET_ALPHABET, &bytes)
+}
+
+pub fn otpauth_uri(secret: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}",
+        issuer = ISSUER,
+        account = urlencoding::encode(account),
+        secret = secret,
+    )
+}
+
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    binary % 10u32.pow(DIGITS)
+}
+
+pub fn current_unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Returns the time step the code belongs to, allowing one step of clock drift either way.
+/// Callers store the step so the same code can't be replayed.
+pub fn verify_code(secret: &str, code: &str, unix_time: u64) -> Option<u64> {
+    let code = code.trim();
+    // Digits only: `parse` alone would also take a leading '+'.
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let key = base32::decode(SECRET_ALPHABET, secret)?;
+
+    let current = unix_time / STEP_SECONDS;
+    [current.saturating_sub(1), current, current + 1]
+        .into_iter()
+        .find(|&step| hotp(&key, step) == code)
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 5];
+            OsRng.fill_bytes(&mut bytes);
+            let raw = hex::encode(bytes);
+            format!("{}-{}", &raw[..5], &raw[5..])
+        })
+        .collect()
+}
+
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The shared secret of RFC 4226 appendix D and RFC 6238 appendix B (SHA-1).
+    const RFC_KEY: &[u8] = b"12345678901234567890";
+
+    fn rfc_secret() -> String {
+        base32::encode(SECRET_ALPHABET, RFC_KEY)
+    }
+
+    #[test]
+    fn hotp_matches_rfc_4226() {
+        let expected = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(RFC_KEY, counter as u64), code, "counter {}", counter);
+        }
+    }
+
+    #[test]
+    fn verify_code_matches_rfc_6238() {
+        // The RFC lists eight-digit codes; ours are their last six digits.
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+        for (time, code) in vectors {
+            assert_eq!(verify_code(&rfc_secret(), code, time), Some(time / STEP_SECONDS), "time {}", time);
+        }
+    }
+
+    #[test]
+    fn one_step_of_drift_is_allowed_either_way() {
+        let secret = rfc_secret();
+        let step = 1111111109 / STEP_SECONDS;
+        let code = "081804";
+        let at_step = |s: u64| s * STEP_SECONDS + 7;
+
+        assert_eq!(verify_code(&secret, code, at_step(step - 1)), Some(step));
+        assert_eq!(verify_code(&secret, code, at_step(step)), Some(step));
+        assert_eq!(verify_code(&secret, code, at_step(step + 1)), Some(step));
+        assert_eq!(verify_code(&secret, code, at_step(step - 2)), None);
+        assert_eq!(verify_code(&secret, code, at_step(step + 2)), None);
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        let secret = rfc_secret();
+        let time = 1111111109;
+
+        assert_eq!(verify_code(&secret, " 081804 ", time), Some(time / STEP_SECONDS));
+        for code in ["", "81804", "0818040", "08180a", "+81804", "-81804", "08 804", "０８１８０４"] {
+            assert_eq!(verify_code(&secret, code, time), None, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn unreadable_secret_is_rejected() {
+        assert_eq!(verify_code("not base32!", "081804", 1111111109), None);
+    }
+}
@@ -0,0 +1,184 @@
+//! Fixtures for tests that run against Postgres. `#[sqlx::test(migrations = false)]`
+//! hands each test a fresh, empty database (named after `DATABASE_URL`); `setup` brings
+//! it up to the current schema with our own migrations.
+
+use actix_web::{web, App, HttpServer};
+use sqlx::PgPool;
+use crate::config::{AdminConfig, CodConfig, Config, KhaltiConfig, LlmConfig, LlmProviderKind, SmtpConfig};
+use crate::databases::auth::{sessiondb, totpdb};
+use crate::databases::migrations::run_migrations;
+use crate::databases::payment::paymentdb::PaymentStatus;
+use crate::databases::shop::orderdb::{Order, OrderStatus};
+use crate::mock_khalti::{self, MockState, Outcome};
+
+/// A config that keeps tests offline: mail goes to a closed local port and fails at
+/// once, and the gateways point at nothing until a test says otherwise.
+pub fn test_config() -> Config {
+    Config {
+        host: "127.0.0.1".to_string(),
+        port: 0,
+        cors_origin: "http://localhost:5173".to_string(),
+        database_url: String::new(),
+        base_url: "http://localhost:5173".to_string(),
+        backend_url: "http://localhost:8080".to_string(),
+        trusted_proxy: false,
+        smtp: SmtpConfig {
+            email: "shop@example.com".to_string(),
+            password: "x".to_string(),
+            server: "127.0.0.1".to_string(),
+            port: 1,
+        },
+        admin: AdminConfig {
+            email: "admin@example.com".to_string(),
+            phone: "9800000000".to_string(),
+            require_2fa: false,
+        },
+        khalti: KhaltiConfig {
+            secret_key: "test".to_string(),
+            url: "http://127.0.0.1:1/api/v2".to_string(),
+            refund_url: "http://127.0.0.1:1/api/merchant-transaction".to_string(),
+        },
+        esewa: None,
+        cod: CodConfig { default_limit_paisa: 5_000_000 },
+        llm: LlmConfig {
+            provider: LlmProviderKind::Disabled,
+            url: "http://127.0.0.1:1".to_string(),
+            model: "test".to_string(),
+            api_key: None,
+        },
+    }
+}
+
+/// `test_config` pointed at a mock Khalti of its own, running on a free port in a thread
+/// of its own. Payments settle as the customer picks on the mock's `payment_url`.
+pub fn mock_khalti_config() -> Config {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind mock khalti");
+    let base_url = format!("http://{}", listener.local_addr().expect("mock khalti address"));
+    let state = web::Data::new(MockState::new(base_url.clone(), Outcome::Completed));
+
+    std::thread::spawn(move || {
+        actix_web::rt::System::new().block_on(async move {
+            HttpServer::new(move || App::new().app_data(state.clone()).configure(mock_khalti::configure))
+                .workers(1)
+                .listen(listener)
+                .expect("serve mock khalti")
+                .run()
+                .await
+        })
+    });
+
+    let mut config = test_config();
+    config.khalti.url = format!("{}/api/v2", base_url);
+    config.khalti.refund_url = format!("{}/api/merchant-transaction", base_url);
+    config
+}
+
+pub async fn setup(pool: &PgPool) {
+    run_migrations(pool).await.expect("migrations should apply to a fresh database");
+}
+
+/// A user with the given `logininfo.status` ("user" or "admin") and a unique email.
+pub async fn create_user(pool: &PgPool, status: &str) -> i32 {
+    let tag = uuid::Uuid::new_v4().simple().to_string();
+    sqlx::query_scalar(
+        "INSERT INTO logininfo (name, email, phonenumber, password, status)
+         VALUES ('Test User', $1, $2, 'not-a-hash', $3)
+         RETURNING id"
+    )
+    .bind(format!("{}@example.com", tag))
+    .bind(&tag[..15])
+    .bind(status)
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+}
+
+/// A session token for `user_id`, for the `Authorization: Bearer` header.
+pub async fn login(pool: &PgPool, user_id: i32) -> String {
+    sessiondb::create_session(pool, user_id).await.expect("create session")
+}
+
+pub async fn enroll_totp(pool: &PgPool, user_id: i32) {
+    totpdb::store_pending_secret(pool, user_id, "JBSWY3DPEHPK3PXP").await.expect("store secret");
+    totpdb::enable_totp(pool, user_id, &[]).await.expect("enable totp");
+}
+
+/// A laptop costing Rs. 1000 before tax, so it sells for Rs. 1180.
+pub async fn create_laptop(pool: &PgPool, brand: &str, model: &str, quantity: i32) -> i32 {
+    sqlx::query_scalar(
+        "INSERT INTO laptop_details (brand_name, model_name, display_name, cost_price, quantity)
+         VALUES ($1, $2, $1 || ' ' || $2, 1000, $3)
+         RETURNING id"
+    )
+    .bind(brand)
+    .bind(model)
+    .bind(quantity)
+    .fetch_one(pool)
+    .await
+    .expect("insert laptop")
+}
+
+pub async fn laptop_quantity(pool: &PgPool, laptop_id: i32) -> i32 {
+    sqlx::query_scalar("SELECT quantity FROM laptop_details WHERE id = $1")
+        .bind(laptop_id)
+        .fetch_one(pool)
+        .await
+        .expect("laptop quantity")
+}
+
+/// An order for one of `laptop_id`, paid for through `gateway`, written straight into
+/// the given statuses. Stock is left alone.
+pub async fn create_order(
+    pool: &PgPool,
+    user_id: i32,
+    laptop_id: i32,
+    gateway: &str,
+    payment_status: PaymentStatus,
+    order_status: OrderStatus,
+) -> Order {
+    let order_ref = uuid::Uuid::new_v4().to_string();
+    let mut tx = pool.begin().await.expect("begin");
+
+    let payment_id: i32 = sqlx::query_scalar(
+        "INSERT INTO payments (user_id, gateway, gateway_ref, order_ref, amount_paisa, status, transaction_id)
+         VALUES ($1, $2, $3, $3, 118000, $4, $3)
+         RETURNING id"
+    )
+    .bind(user_id)
+    .bind(gateway)
+    .bind(&order_ref)
+    .bind(payment_status.as_str())
+    .fetch_one(&mut *tx)
+    .await
+    .expect("insert payment");
+
+    sqlx::query("INSERT INTO payment_items (payment_id, laptop_id, quantity, unit_price) VALUES ($1, $2, 1, 1180)")
+        .bind(payment_id)
+        .bind(laptop_id)
+        .execute(&mut *tx)
+        .await
+        .expect("insert payment item");
+
+    let order = sqlx::query_as::<_, Order>(
+        "INSERT INTO orders (user_id, payment_id, order_ref, total_paisa, status)
+         VALUES ($1, $2, $3, 118000, $4)
+         RETURNING *"
+    )
+    .bind(user_id)
+    .bind(payment_id)
+    .bind(&order_ref)
+    .bind(order_status.as_str())
+    .fetch_one(&mut *tx)
+    .await
+    .expect("insert order");
+
+    sqlx::query("INSERT INTO order_items (order_id, laptop_id, quantity, unit_price) VALUES ($1, $2, 1, 1180)")
+        .bind(order.id)
+        .bind(laptop_id)
+        .execute(&mut *tx)
+        .await
+        .expect("insert order item");
+
+    tx.commit().await.expect("commit");
+    order
+}